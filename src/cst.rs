@@ -0,0 +1,684 @@
+// The concrete syntax tree: every token of a source along with its trivia,
+// grouped into nodes, so that the tree gives back the source byte for
+// byte. The green tree holds the text and the widths, the red tree on top
+// of it the offsets and the parents.
+//
+// The parser builds it from the tokens, recording where it starts and
+// finishes each node. The AST is then lowered from the tree, see lower.rs.
+
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Lexer;
+use crate::lower;
+use crate::parser::Parser;
+use crate::syntax::{Expr, LosslessToken, Program, TokenKind, TriviaKind};
+
+#[cfg(test)]
+mod cst_test {
+    use super::*;
+    use crate::syntax::{Item, TokenInfo};
+
+    // the byte range of `info` in `src`
+    fn range_of(src: &str, info: &TokenInfo) -> Range<usize> {
+        let offset = |row: usize, col: usize| {
+            let line: usize = src.split_inclusive('\n').take(row - 1).map(str::len).sum();
+            let rest = &src[line..];
+            line + rest
+                .char_indices()
+                .nth(col - 1)
+                .map_or(rest.len(), |(i, _)| i)
+        };
+        let end = offset(info.e_row, info.e_col);
+        offset(info.s_row, info.s_col)..end + src[end..].chars().next().map_or(0, char::len_utf8)
+    }
+
+    fn ranges(node: &SyntaxNode, ranges: &mut Vec<Range<usize>>) {
+        ranges.push(node.text_range());
+        for child in node.child_nodes() {
+            self::ranges(&child, ranges);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let sources = [
+            "  // leading\nfn main() -> I32 { let x = 1 /* a */ + 2 ; x }\n\n",
+            "/// Doc.\nstruct P<T> { x: T, y: (I32, Fn(I32) -> Bool) };\n/// at the end",
+            "enum E { A(I32), B }\nfn f(e: E) { match e { A(n) => -n, B => 0 } }",
+            "fn g() { if a { 1 } else if b { (2, \"é\") } else { 'c' } }",
+            "fn h( { let = 1; 2 + ; @ \"x",
+            "let z = f(1)(2).0.y as I64 /* open",
+            "",
+        ];
+        for src in sources.iter() {
+            let (tree, _, _) = parse(src);
+            assert_eq!(tree.to_string(), *src);
+            assert_eq!(tree.kind(), SyntaxKind::Program);
+            let text: String = tree.tokens().iter().map(SyntaxToken::full_text).collect();
+            assert_eq!(text, *src);
+        }
+    }
+
+    #[test]
+    fn test_nodes() {
+        let src = "fn main() {\n    f(1, 2) + 3 // three\n}\n";
+        let (tree, _, errors) = parse(src);
+        assert!(errors.is_empty());
+        let func = &tree.child_nodes()[0];
+        assert_eq!(func.kind(), SyntaxKind::FuncItem);
+        assert_eq!(func.text_range(), 0..src.len() - 1);
+        let block = func.child_nodes()[0].child_nodes()[1].clone();
+        assert_eq!(block.kind(), SyntaxKind::Block);
+        let bin = &block.child_nodes()[0];
+        assert_eq!(bin.kind(), SyntaxKind::BinExpr);
+        assert_eq!(bin.to_string(), "\n    f(1, 2) + 3 // three");
+        let kinds: Vec<SyntaxKind> = bin.child_nodes().iter().map(SyntaxNode::kind).collect();
+        assert_eq!(kinds, vec![SyntaxKind::CallExpr, SyntaxKind::Literal]);
+        assert_eq!(bin.parent().unwrap().kind(), SyntaxKind::Block);
+        let three = bin.tokens()[7].clone();
+        assert_eq!(three.kind(), &TokenKind::Num(String::from("3")));
+        assert_eq!(three.text_range(), 26..27);
+        assert_eq!(three.trailing()[0].0, TriviaKind::Whitespace);
+        assert_eq!(three.trailing()[1], (TriviaKind::LineComment, "// three"));
+        let rbrace = tree.tokens()[tree.tokens().len() - 2].clone();
+        assert_eq!(rbrace.leading(), vec![(TriviaKind::Newline, "\n")]);
+        // nodes made around one starting at the same token nest in the
+        // order they were made
+        let (tree, _, _) = parse("let x = a.b(1) as I64 + 2 + 3;");
+        let mut node = tree.child_nodes()[0].child_nodes()[0].child_nodes()[1].clone();
+        let mut outline = Vec::new();
+        while node.kind() != SyntaxKind::Var {
+            outline.push(format!("{:?} {}", node.kind(), node.to_string().trim()));
+            node = node.child_nodes()[0].clone();
+        }
+        assert_eq!(
+            outline,
+            vec![
+                "BinExpr a.b(1) as I64 + 2 + 3",
+                "BinExpr a.b(1) as I64 + 2",
+                "CastExpr a.b(1) as I64",
+                "CallExpr a.b(1)",
+                "FieldExpr a.b",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skipped() {
+        // the tokens skipped after an error go to an Error node where they
+        // were skipped
+        fn errors(node: &SyntaxNode, texts: &mut Vec<String>) {
+            for child in node.child_nodes() {
+                if child.kind() == SyntaxKind::Error {
+                    texts.push(String::from(child.to_string().trim()));
+                }
+                errors(&child, texts);
+            }
+        }
+        let src =
+            "fn f(x y) { x }\nfn g() { let = 1; 2 + ; h(1 2, 3) }\nstruct S { x I32 }\n/// end";
+        let (tree, _, _) = parse(src);
+        let mut texts = Vec::new();
+        errors(&tree, &mut texts);
+        assert_eq!(
+            texts,
+            vec!["y", "let = 1", "2 +", "2", "struct S { x I32 }"]
+        );
+        let kinds: Vec<SyntaxKind> = tree.child_nodes().iter().map(SyntaxNode::kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                SyntaxKind::FuncItem,
+                SyntaxKind::FuncItem,
+                SyntaxKind::Error
+            ]
+        );
+        let rest: Vec<TokenKind> = tree
+            .children()
+            .into_iter()
+            .filter_map(|c| match c {
+                SyntaxElement::Token(t) => Some(t.kind().clone()),
+                SyntaxElement::Node(_) => None,
+            })
+            .collect();
+        assert_eq!(
+            rest,
+            vec![TokenKind::DocComment(String::from("end")), TokenKind::EOF]
+        );
+    }
+
+    #[test]
+    fn test_ast() {
+        // every expression of the AST has a node over the same text
+        let src = "fn f(x: I32) -> I32 { let (a, b) = (x, -1); g(a).0 + b as I32 * (2) }";
+        let (tree, program, errors) = parse(src);
+        assert!(errors.is_empty());
+        let mut nodes = Vec::new();
+        ranges(&tree, &mut nodes);
+        let mut exprs = Vec::new();
+        fn walk(e: &Expr, exprs: &mut Vec<TokenInfo>) {
+            exprs.push(e.info().clone());
+            match e {
+                Expr::NamedFunc { block, .. } => walk(block, exprs),
+                Expr::Block { exprs: es, .. } | Expr::Tuple { elems: es, .. } => {
+                    es.iter().for_each(|e| walk(e, exprs))
+                }
+                Expr::Let { init, .. } => walk(init, exprs),
+                Expr::BinOp { lhs, rhs, .. } => {
+                    walk(lhs, exprs);
+                    walk(rhs, exprs);
+                }
+                Expr::FuncApp { callee, args, .. } => {
+                    walk(callee, exprs);
+                    args.iter().for_each(|e| walk(e, exprs));
+                }
+                Expr::TupleField { expr, .. } | Expr::Cast { expr, .. } => walk(expr, exprs),
+                _ => (),
+            }
+        }
+        match &program.items[0] {
+            Item::Func(f) => walk(f, &mut exprs),
+            i => panic!("{:?}", i),
+        }
+        assert_eq!(exprs.len(), 15);
+        for info in exprs.iter() {
+            let range = range_of(src, info);
+            assert!(nodes.contains(&range), "{} {}", info, &src[range]);
+        }
+    }
+}
+
+// Kinds of the nodes of the tree. Their tokens are the ones the parser
+// reads for the node, including the brackets and separators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntaxKind {
+    Program,
+    // tokens skipped after a syntax error
+    Error,
+
+    // items
+    FuncItem,
+    ConstItem,
+    StructItem,
+    EnumItem,
+    TypeAliasItem,
+    ImportItem,
+
+    // parts of declarations
+    TypeParams,
+    FieldDecl,
+    VariantDecl,
+    ParamList,
+    Param,
+    RetType,
+
+    // types
+    Type,
+    TupleType,
+    ParenType,
+    FuncType,
+
+    // expressions
+    Literal,
+    Var,
+    StructLit,
+    FieldInit,
+    TupleExpr,
+    ParenExpr,
+    Block,
+    LetStmt,
+    NamedFunc,
+    AnonFunc,
+    IfExpr,
+    MatchExpr,
+    MatchArm,
+    CallExpr,
+    ArgList,
+    FieldExpr,
+    CastExpr,
+    UnaryExpr,
+    BinExpr,
+
+    // patterns
+    Pattern,
+    CtorPattern,
+    TuplePattern,
+    ParenPattern,
+}
+
+// What the parser did, in order, from which the tree is built. Each Token
+// is the next token of the stream.
+//
+// A Start is recorded where a node may start, before the parser knows
+// whether one will, and has no kind unless one was made there. A node
+// made later around one starting at the same token has its Start at the
+// end instead, and `forward_parent` leads there from the inner one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Start {
+        kind: Option<SyntaxKind>,
+        forward_parent: Option<usize>,
+    },
+    Token,
+    Finish,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct GreenTrivia {
+    pub kind: TriviaKind,
+    pub text: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct GreenToken {
+    pub kind: TokenKind,
+    pub text: String,
+    pub leading: Vec<GreenTrivia>,
+    pub trailing: Vec<GreenTrivia>,
+}
+
+impl GreenToken {
+    // the width of the token with its trivia
+    fn width(&self) -> usize {
+        let trivia = |ts: &[GreenTrivia]| ts.iter().map(|t| t.text.len()).sum::<usize>();
+        trivia(&self.leading) + self.text.len() + trivia(&self.trailing)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum GreenElement {
+    Node(Rc<GreenNode>),
+    Token(Rc<GreenToken>),
+}
+
+impl GreenElement {
+    fn width(&self) -> usize {
+        match self {
+            GreenElement::Node(n) => n.width,
+            GreenElement::Token(t) => t.width(),
+        }
+    }
+}
+
+// A node without a position, which could be shared by trees
+#[derive(Debug, PartialEq)]
+pub struct GreenNode {
+    pub kind: SyntaxKind,
+    pub width: usize,
+    pub children: Vec<GreenElement>,
+    // the width before the text of its first token but doc comments,
+    // if it has one
+    leading: Option<usize>,
+    // the width of the trivia after its last token
+    trailing: usize,
+}
+
+impl GreenNode {
+    fn new(kind: SyntaxKind, children: Vec<GreenElement>) -> GreenNode {
+        let width = children.iter().map(GreenElement::width).sum();
+        let mut before = 0;
+        let mut leading = None;
+        for child in &children {
+            let child_leading = match child {
+                GreenElement::Token(t) if matches!(t.kind, TokenKind::DocComment(_)) => None,
+                GreenElement::Token(t) => Some(t.leading.iter().map(|t| t.text.len()).sum()),
+                GreenElement::Node(n) => n.leading,
+            };
+            if let Some(l) = child_leading {
+                leading = Some(before + l);
+                break;
+            }
+            before += child.width();
+        }
+        let trailing = match children.last() {
+            Some(GreenElement::Token(t)) => t.trailing.iter().map(|t| t.text.len()).sum(),
+            Some(GreenElement::Node(n)) => n.trailing,
+            None => 0,
+        };
+        GreenNode {
+            kind,
+            width,
+            children,
+            leading,
+            trailing,
+        }
+    }
+}
+
+// A green node at its offset in the source, knowing its parent
+#[derive(Debug, Clone)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+#[derive(Debug)]
+struct NodeData {
+    green: Rc<GreenNode>,
+    offset: usize,
+    parent: Option<SyntaxNode>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntaxToken {
+    green: Rc<GreenToken>,
+    offset: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxNode {
+    pub fn new_root(green: Rc<GreenNode>) -> SyntaxNode {
+        SyntaxNode(Rc::new(NodeData {
+            green,
+            offset: 0,
+            parent: None,
+        }))
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.0.green.kind
+    }
+
+    pub fn parent(&self) -> Option<SyntaxNode> {
+        self.0.parent.clone()
+    }
+
+    // the range from its first token to its last, without their trivia
+    // and the doc comments before the first
+    pub fn text_range(&self) -> Range<usize> {
+        let green = &self.0.green;
+        match green.leading {
+            Some(leading) => self.0.offset + leading..self.0.offset + green.width - green.trailing,
+            None => self.0.offset..self.0.offset,
+        }
+    }
+
+    pub fn children(&self) -> Vec<SyntaxElement> {
+        let mut offset = self.0.offset;
+        let mut children = Vec::new();
+        for child in &self.0.green.children {
+            children.push(match child {
+                GreenElement::Node(green) => SyntaxElement::Node(SyntaxNode(Rc::new(NodeData {
+                    green: green.clone(),
+                    offset,
+                    parent: Some(self.clone()),
+                }))),
+                GreenElement::Token(green) => SyntaxElement::Token(SyntaxToken {
+                    green: green.clone(),
+                    offset,
+                }),
+            });
+            offset += child.width();
+        }
+        children
+    }
+
+    #[allow(dead_code)]
+    pub fn child_nodes(&self) -> Vec<SyntaxNode> {
+        self.children()
+            .into_iter()
+            .filter_map(|c| match c {
+                SyntaxElement::Node(n) => Some(n),
+                SyntaxElement::Token(_) => None,
+            })
+            .collect()
+    }
+
+    // all the tokens under the node, in order
+    pub fn tokens(&self) -> Vec<SyntaxToken> {
+        let mut tokens = Vec::new();
+        self.tokens_into(&mut tokens);
+        tokens
+    }
+
+    fn tokens_into(&self, tokens: &mut Vec<SyntaxToken>) {
+        for child in self.children() {
+            match child {
+                SyntaxElement::Node(n) => n.tokens_into(tokens),
+                SyntaxElement::Token(t) => tokens.push(t),
+            }
+        }
+    }
+
+    // the first token under the node, found without taking the others
+    pub fn first_token(&self) -> Option<SyntaxToken> {
+        let mut green = &self.0.green;
+        let offset = self.0.offset;
+        loop {
+            match green.children.first()? {
+                GreenElement::Node(n) => green = n,
+                GreenElement::Token(t) => {
+                    return Some(SyntaxToken {
+                        green: t.clone(),
+                        offset,
+                    })
+                }
+            }
+        }
+    }
+
+    // An indented outline of the tree with the ranges of the nodes and the
+    // tokens, and the trivia around the tokens.
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        self.dump_into(0, &mut out);
+        out
+    }
+
+    fn dump_into(&self, depth: usize, out: &mut String) {
+        let range = self.text_range();
+        out.push_str(&format!(
+            "{:indent$}{:?}@{}..{}\n",
+            "",
+            self.kind(),
+            range.start,
+            range.end,
+            indent = depth * 2
+        ));
+        for child in self.children() {
+            match child {
+                SyntaxElement::Node(n) => n.dump_into(depth + 1, out),
+                SyntaxElement::Token(t) => {
+                    let indent = (depth + 1) * 2;
+                    for (kind, text) in t.leading() {
+                        out.push_str(&format!("{:indent$}{:?} {:?}\n", "", kind, text));
+                    }
+                    let range = t.text_range();
+                    out.push_str(&format!(
+                        "{:indent$}{:?}@{}..{} {:?}\n",
+                        "",
+                        t.kind(),
+                        range.start,
+                        range.end,
+                        t.text(),
+                    ));
+                    for (kind, text) in t.trailing() {
+                        out.push_str(&format!("{:indent$}{:?} {:?}\n", "", kind, text));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// the source text under the node
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for t in self.tokens() {
+            write!(f, "{}", t.full_text())?;
+        }
+        Ok(())
+    }
+}
+
+impl SyntaxToken {
+    pub fn kind(&self) -> &TokenKind {
+        &self.green.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.green.text
+    }
+
+    pub fn text_range(&self) -> Range<usize> {
+        let start = self.offset
+            + self
+                .green
+                .leading
+                .iter()
+                .map(|t| t.text.len())
+                .sum::<usize>();
+        start..start + self.green.text.len()
+    }
+
+    pub fn leading(&self) -> Vec<(TriviaKind, &str)> {
+        self.green
+            .leading
+            .iter()
+            .map(|t| (t.kind, t.text.as_str()))
+            .collect()
+    }
+
+    pub fn trailing(&self) -> Vec<(TriviaKind, &str)> {
+        self.green
+            .trailing
+            .iter()
+            .map(|t| (t.kind, t.text.as_str()))
+            .collect()
+    }
+
+    // the text of the token with its trivia
+    pub fn full_text(&self) -> String {
+        let mut text = String::new();
+        for t in &self.green.leading {
+            text.push_str(&t.text);
+        }
+        text.push_str(&self.green.text);
+        for t in &self.green.trailing {
+            text.push_str(&t.text);
+        }
+        text
+    }
+}
+
+// Builds the tree of `tokens`, lexed from `source`, along the `events` of
+// the parser which read them. The parser reads every token but the EOF,
+// which goes to the root.
+pub fn build(source: &str, tokens: Vec<LosslessToken>, events: Vec<Event>) -> SyntaxNode {
+    let trivia = |ts: Vec<crate::syntax::Trivia>| {
+        ts.into_iter()
+            .map(|t| GreenTrivia {
+                kind: t.kind,
+                text: String::from(&source[t.range]),
+            })
+            .collect()
+    };
+    let mut tokens = tokens.into_iter().map(|t| {
+        GreenElement::Token(Rc::new(GreenToken {
+            kind: t.token.kind,
+            text: String::from(&source[t.range]),
+            leading: trivia(t.leading),
+            trailing: trivia(t.trailing),
+        }))
+    });
+    let mut events = events;
+    let mut stack = vec![(SyntaxKind::Program, Vec::new())];
+    for i in 0..events.len() {
+        let none = Event::Start {
+            kind: None,
+            forward_parent: None,
+        };
+        match std::mem::replace(&mut events[i], none.clone()) {
+            Event::Start {
+                kind,
+                mut forward_parent,
+            } => {
+                // the nodes starting here, from the innermost, whose later
+                // Starts are done with
+                let mut kinds = vec![kind];
+                while let Some(parent) = forward_parent {
+                    match std::mem::replace(&mut events[parent], none.clone()) {
+                        Event::Start {
+                            kind,
+                            forward_parent: next,
+                        } => {
+                            kinds.push(kind);
+                            forward_parent = next;
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+                for kind in kinds.into_iter().rev().flatten() {
+                    stack.push((kind, Vec::new()));
+                }
+            }
+            Event::Token => {
+                if let Some(t) = tokens.next() {
+                    stack.last_mut().unwrap().1.push(t);
+                }
+            }
+            Event::Finish => {
+                let (kind, children) = stack.pop().unwrap();
+                let node = GreenNode::new(kind, children);
+                stack
+                    .last_mut()
+                    .unwrap()
+                    .1
+                    .push(GreenElement::Node(Rc::new(node)));
+            }
+        }
+    }
+    let (kind, mut children) = stack.remove(0);
+    children.extend(tokens);
+    SyntaxNode::new_root(Rc::new(GreenNode::new(kind, children)))
+}
+
+// a parser reading `tokens`, which leaves the EOF to the root
+fn parser_of(tokens: &[LosslessToken]) -> Parser {
+    Parser::new(
+        tokens
+            .iter()
+            .map(|t| t.token.clone())
+            .filter(|t| t.kind != TokenKind::EOF)
+            .collect(),
+    )
+}
+
+// Reads `source` into its tree and the AST lowered from it, along with
+// every lexical and syntax error.
+pub fn parse(source: &str) -> (SyntaxNode, Program, Vec<Diagnostic>) {
+    let (tokens, mut errors) = Lexer::from_string(String::from(source)).lex_lossless();
+    let mut parser = parser_of(&tokens);
+    errors.extend(parser.parse_program());
+    let tree = build(source, tokens, parser.take_events());
+    let program = lower::lower(&tree);
+    (tree, program, errors)
+}
+
+// The AST of `source`, failing with its first lexical or syntax error.
+pub fn parse_program(source: &str) -> Result<Program, Diagnostic> {
+    let (_, program, mut errors) = parse(source);
+    match errors.is_empty() {
+        true => Ok(program),
+        false => Err(errors.remove(0)),
+    }
+}
+
+// The AST of the one expression `source` holds, optionally followed by a
+// semicolon, failing with its first lexical or syntax error. Used by the
+// REPL.
+pub fn parse_expr(source: &str) -> Result<Box<Expr>, Diagnostic> {
+    let (tokens, mut errors) = Lexer::from_string(String::from(source)).lex_lossless();
+    if !errors.is_empty() {
+        return Err(errors.remove(0));
+    }
+    let mut parser = parser_of(&tokens);
+    parser.parse_expr()?;
+    let tree = build(source, tokens, parser.take_events());
+    Ok(lower::lower_expr(&tree))
+}
@@ -0,0 +1,168 @@
+// Diagnostics reported by every phase of lung.
+//
+// Error codes:
+//   E0001  unrecognized character
+//   E0002  incomplete operator, like a lone `=` or `&`
+//   E0003  invalid escape sequence
+//   E0004  unterminated string literal
+//   E0005  invalid char literal
+//   E0006  unterminated block comment
+//   E0100  unexpected token
+//   E0101  invalid number literal
+//   E0102  number literal out of range for its type
+//   E0200  type mismatch
+//   E0201  unbound variable
+//   E0202  infinite type
+//   E0203  wrong number of arguments
+//   E0204  callee is not a function
+//   E0206  values holding functions compared for equality
+//   E0207  function defined twice in a group of adjacent functions
+//   E0208  unknown type name
+//   E0209  missing or ill-typed `main`
+//   E0210  struct literal fields do not match the struct
+//   E0211  no such field
+//   E0212  field, variant or type parameter declared twice
+//   E0213  type or constructor defined twice
+//   E0214  unknown constructor in a pattern
+//   E0215  wrong number of fields in a constructor pattern
+//   E0216  match not exhaustive
+//   E0217  wrong number of type arguments
+//   E0218  refutable pattern in a let or a function argument
+//   E0219  `as` conversion from or to a type that is not a number
+//   E0300  runtime: unbound variable
+//   E0301  runtime: callee is not a function
+//   E0302  runtime: wrong number of arguments
+//   E0303  runtime: integer overflow, also by an `as` conversion
+//   E0304  runtime: division by zero
+//   E0305  runtime: no match arm matches
+//   E0306  runtime: calls nested too deep
+//   E0400  imported file cannot be read
+//   E0401  import cycle
+//
+// Warning codes:
+//   W0200  unreachable match arm
+use std::fmt;
+
+use crate::syntax::TokenInfo;
+
+#[cfg(test)]
+mod diagnostic_test {
+    use super::*;
+
+    fn span(row: usize, col: usize) -> TokenInfo {
+        TokenInfo {
+            s_col: col,
+            s_row: row,
+            e_col: col,
+            e_row: row,
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let d = Diagnostic::error("E0100", "Expected RPAREN")
+            .with_primary(span(1, 3))
+            .with_secondary(span(1, 1), "opened here")
+            .with_note("parens must be balanced");
+        assert_eq!(
+            d.to_string(),
+            "error[E0100]: Expected RPAREN\n  --> 1:3-1:3\n  = 1:1-1:1: opened here\n  = note: parens must be balanced"
+        );
+        let d = Diagnostic::error("E0201", "Could not find variable `x`");
+        assert_eq!(d.to_string(), "error[E0201]: Could not find variable `x`");
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+// Diagnostics are the error of most results, so they are kept behind a
+// box to keep those results small. The fields are reached through it.
+#[derive(Debug, Clone)]
+pub struct Diagnostic(Box<DiagnosticData>);
+
+#[derive(Debug, Clone)]
+pub struct DiagnosticData {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    // where the problem is, if known
+    pub primary: Option<TokenInfo>,
+    // related locations, each with a label
+    pub secondary: Vec<(TokenInfo, String)>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &'static str, message: &str) -> Diagnostic {
+        Diagnostic(Box::new(DiagnosticData {
+            severity,
+            code,
+            message: String::from(message),
+            primary: None,
+            secondary: Vec::new(),
+            notes: Vec::new(),
+        }))
+    }
+
+    pub fn error(code: &'static str, message: &str) -> Diagnostic {
+        Diagnostic::new(Severity::Error, code, message)
+    }
+
+    pub fn with_primary(mut self, span: TokenInfo) -> Diagnostic {
+        self.primary = Some(span);
+        self
+    }
+
+    pub fn with_secondary(mut self, span: TokenInfo, label: &str) -> Diagnostic {
+        self.secondary.push((span, String::from(label)));
+        self
+    }
+
+    pub fn with_note(mut self, note: &str) -> Diagnostic {
+        self.notes.push(String::from(note));
+        self
+    }
+}
+
+impl std::ops::Deref for Diagnostic {
+    type Target = DiagnosticData;
+
+    fn deref(&self) -> &DiagnosticData {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for Diagnostic {
+    fn deref_mut(&mut self) -> &mut DiagnosticData {
+        &mut self.0
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)?;
+        if let Some(span) = &self.primary {
+            write!(f, "\n  --> {}", span)?;
+        }
+        for (span, label) in &self.secondary {
+            write!(f, "\n  = {}: {}", span, label)?;
+        }
+        for note in &self.notes {
+            write!(f, "\n  = note: {}", note)?;
+        }
+        Ok(())
+    }
+}
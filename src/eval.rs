@@ -0,0 +1,840 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::diagnostic::Diagnostic;
+use crate::syntax::*;
+use crate::type_def::Type;
+
+#[cfg(test)]
+mod eval_test {
+    use super::*;
+    use crate::cst;
+
+    fn run_file(fname: &str) -> Result<Value, Diagnostic> {
+        let src = std::fs::read_to_string(fname).unwrap();
+        let program = cst::parse_program(&src).unwrap();
+        program.run(&Env::new())
+    }
+
+    #[test]
+    fn test() {
+        let val = run_file("src/test/test_parser.txt").unwrap();
+        assert_eq!(val.to_string(), "unit");
+    }
+
+    #[test]
+    fn test_program() {
+        let src = "
+            let base = 10;
+            fn add(x) { x + base }
+            let base = 20;
+            fn main() { add(1) + base }
+        ";
+        let program = cst::parse_program(src).unwrap();
+        assert_eq!(program.run(&Env::new()).unwrap().to_string(), "31");
+    }
+
+    #[test]
+    fn test_struct() {
+        let src = "
+            struct Point { x: I32, y: I32 }
+            fn add(a: Point, b: Point) { Point { x: a.x + b.x, y: a.y + b.y } }
+            fn main() {
+                let p = add(Point { x: 1, y: 2 }, Point { y: 20, x: 10 });
+                if p == (Point { y: 22, x: 11 }) { p } else { Point { x: 0, y: 0 } }
+            }
+        ";
+        let program = cst::parse_program(src).unwrap();
+        let val = program.run(&Env::new()).unwrap();
+        assert_eq!(val.to_string(), "Point { x: 11, y: 22 }");
+    }
+
+    #[test]
+    fn test_match() {
+        let src = "
+            enum List { Cons(I32, List), Nil }
+            fn sum(l) { match l { Cons(x, rest) => x + sum(rest), Nil => 0 } }
+            fn describe(l) {
+                match l { Nil => 0, Cons(1, Nil) => 1, Cons(_, Cons(y, _)) => y, _ => -1 }
+            }
+            fn main() {
+                let l = Cons(1, Cons(2, Cons(3, Nil)));
+                sum(l) * 100 + describe(l) * 10 + describe(Cons(1, Nil))
+            }
+        ";
+        let program = cst::parse_program(src).unwrap();
+        assert_eq!(program.run(&Env::new()).unwrap().to_string(), "621");
+        assert_eq!(
+            run_str("match 3 { 1 => false, n => n == 3 }")
+                .unwrap()
+                .to_string(),
+            "true"
+        );
+        let src = "match ('b', \"ab\") { ('a', _) => 1, (_, \"ab\") => 2, _ => 0 }";
+        assert_eq!(run_str(src).unwrap().to_string(), "2");
+    }
+
+    #[test]
+    fn test_generics() {
+        let src = "
+            enum List<T> { Cons(T, List<T>), Nil }
+            fn map<A, B>(l: List<A>, f: Fn(A) -> B) -> List<B> {
+                match l { Cons(x, rest) => Cons(f(x), map(rest, f)), Nil => Nil }
+            }
+            fn main() { map(Cons(1, Cons(2, Nil)), function<T>(x: T) { x == 2 }) }
+        ";
+        let program = cst::parse_program(src).unwrap();
+        assert_eq!(
+            program.run(&Env::new()).unwrap().to_string(),
+            "Cons(false, Cons(true, Nil))"
+        );
+    }
+
+    #[test]
+    fn test_tuple() {
+        let src = "{
+            let (q, r) = (7 / 2, 7 % 2);
+            let f = function((a, b), c) { (b, a + c) };
+            let t = f((q, r), 10);
+            (t, t.1, t == (1, 13), (1, (2, 3)).1.0)
+        }";
+        assert_eq!(run_str(src).unwrap().to_string(), "((1, 13), 13, true, 2)");
+    }
+
+    #[test]
+    fn test_string() {
+        let src = r#"{ let s = "tab\t\"q\""; (s, 'c', s == "tab\u{9}\"q\"", '\n' == 'n') }"#;
+        assert_eq!(
+            run_str(src).unwrap().to_string(),
+            r#"("tab\t\"q\"", 'c', true, false)"#
+        );
+    }
+
+    #[test]
+    fn test_number() {
+        let src = "(0xff_u8 as I32 + 1, 4_000_000_000u32 / 3u32, -9223372036854775808i64)";
+        assert_eq!(
+            run_str(src).unwrap().to_string(),
+            "(256, 1333333333, -9223372036854775808)"
+        );
+        assert_eq!(run_str("255u8 + 1u8").unwrap_err().code, "E0303");
+        assert_eq!(run_str("0u32 - 1u32").unwrap_err().code, "E0303");
+        assert_eq!(run_str("300 as U8").unwrap_err().code, "E0303");
+        assert_eq!(run_str("-1 as U32").unwrap_err().code, "E0303");
+        let src = "match 7u8 { 0u8 => false, 7u8 => true, _ => false }";
+        assert_eq!(run_str(src).unwrap().to_string(), "true");
+    }
+
+    #[test]
+    fn test_float() {
+        let src = "(0.1 + 0.2, 1.0 / 0.0, 7.5 % 2.0, -2.7 as I32, 3 as F64 / 2.0, 1e21)";
+        assert_eq!(
+            run_str(src).unwrap().to_string(),
+            "(0.30000000000000004, inf, 1.5, -2, 1.5, 1e21)"
+        );
+        assert_eq!(
+            run_str("0.0 / 0.0 == 0.0 / 0.0").unwrap().to_string(),
+            "false"
+        );
+        assert_eq!(run_str("-1.5 < -1.0").unwrap().to_string(), "true");
+        assert_eq!(run_str("1e10 as I32").unwrap_err().code, "E0303");
+        assert_eq!(run_str("(0.0 / 0.0) as I64").unwrap_err().code, "E0303");
+        assert_eq!(run_str("-0.5 as U8").unwrap().to_string(), "0");
+    }
+
+    #[test]
+    fn test_closure() {
+        let val = run_file("src/test/test_eval.txt").unwrap();
+        assert_eq!(val.to_string(), "1");
+    }
+
+    #[test]
+    fn test_runtime_error() {
+        let callee_not_func = Expr::FuncApp {
+            callee: Box::from(Expr::Int {
+                val: 1,
+                ty: Type::I32,
+                info: TokenInfo::default(),
+            }),
+            args: Vec::new(),
+            info: TokenInfo::default(),
+        };
+        assert!(callee_not_func.eval(&Env::new()).is_err());
+        let unbound = Expr::Var {
+            name: String::from("nowhere"),
+            info: TokenInfo::default(),
+        };
+        assert!(unbound.eval(&Env::new()).is_err());
+    }
+
+    fn run_str(src: &str) -> Result<Value, Diagnostic> {
+        let expr = cst::parse_expr(src).unwrap();
+        expr.eval(&Env::new())
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(run_str("1 + 2 * 3 - 4 / 2").unwrap().to_string(), "5");
+        assert_eq!(run_str("-7 % 3").unwrap().to_string(), "-1");
+        assert_eq!(run_str("1 < 2 && !(2 <= 1)").unwrap().to_string(), "true");
+        assert_eq!(run_str("unit == unit").unwrap().to_string(), "true");
+        // rhs of && is never evaluated, so no division by zero
+        assert_eq!(run_str("1 > 2 && 1 / 0 == 0").unwrap().to_string(), "false");
+        assert_eq!(run_str("2147483647 + 1").unwrap_err().code, "E0303");
+        assert_eq!(run_str("-2147483647 - 2").unwrap_err().code, "E0303");
+        assert_eq!(run_str("1 % 0").unwrap_err().code, "E0304");
+    }
+
+    #[test]
+    fn test_let() {
+        let src = "{ let x = 1; let f = function() { x }; let x = x + 10; f() + x }";
+        assert_eq!(run_str(src).unwrap().to_string(), "12");
+    }
+
+    #[test]
+    fn test_named_func() {
+        let src = "{ fn fact(n) { if n == 0 { 1 } else { n * fact(n - 1) } }; fact(5) }";
+        assert_eq!(run_str(src).unwrap().to_string(), "120");
+        let src = "{
+            fn even(n) { if n == 0 { true } else { odd(n - 1) } };
+            fn odd(n) { if n == 0 { false } else { even(n - 1) } };
+            even(10)
+        }";
+        assert_eq!(run_str(src).unwrap().to_string(), "true");
+        // a later definition does not change what earlier ones call
+        let src = "{ fn f() { 1 }; fn g() { f() }; let x = 0; fn f() { 2 }; g() + f() }";
+        assert_eq!(run_str(src).unwrap().to_string(), "3");
+    }
+
+    #[test]
+    fn test_if() {
+        let src = "function(x) { if x < 0 { -x } else if x == 0 { 100 } else { x } }";
+        assert_eq!(run_str(&format!("{}(-3)", src)).unwrap().to_string(), "3");
+        assert_eq!(run_str(&format!("{}(0)", src)).unwrap().to_string(), "100");
+        assert_eq!(run_str("if false { 1 }").unwrap().to_string(), "unit");
+        assert_eq!(run_str("if true { }").unwrap().to_string(), "unit");
+    }
+
+    // Runs `f(n)` for a recursive `f` on a thread with the stack evaluation
+    // needs, as the lung binary does.
+    fn run_recursion(n: i32) -> Result<String, Diagnostic> {
+        let src = format!(
+            "{{ fn f(n: I32) -> I32 {{ if n == 0 {{ 0 }} else {{ 1 + f(n - 1) }} }}; f({}) }}",
+            n
+        );
+        std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || run_str(&src).map(|v| v.to_string()))
+            .unwrap()
+            .join()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_recursion_depth() {
+        assert_eq!(run_recursion(4_000).unwrap(), "4000");
+        let d = run_recursion(6_000).unwrap_err();
+        assert_eq!(d.code, "E0306");
+        // only the innermost calls are in the trace
+        assert_eq!(d.secondary.len(), TRACE_LEN);
+    }
+
+    #[test]
+    fn test_stack_trace() {
+        let expr = cst::parse_expr("function(f) { f(1) }(function(x) { x(2) })").unwrap();
+        let d = expr.eval(&Env::new()).unwrap_err();
+        // `x(2)` fails, called from `f(1)`, called from the outermost call
+        assert_eq!(d.primary.as_ref().unwrap().s_col, 36);
+        let callers: Vec<usize> = d.secondary.iter().map(|(i, _)| i.s_col).collect();
+        assert_eq!(callers, vec![15, 1]);
+    }
+}
+
+// Calls nested deeper than this fail with E0306 rather than overflowing the
+// native stack, as long as evaluation runs on a thread of STACK_SIZE.
+pub const MAX_CALL_DEPTH: usize = 5_000;
+pub const STACK_SIZE: usize = 1 << 30;
+// calls shown by a runtime error
+const TRACE_LEN: usize = 16;
+
+// Runtime value of a Lung expression.
+#[derive(Clone)]
+pub enum Value {
+    // an integer of the integer type, within its range
+    Int(i64, Type),
+    F64(f64),
+    Unit,
+    Bool(bool),
+    Str(Rc<str>),
+    Char(char),
+    Closure(Rc<Closure>),
+    Struct(Rc<StructValue>),
+    Variant(Rc<VariantValue>),
+    Tuple(Rc<Vec<Value>>),
+    // constructor of the named variant, taking that many fields
+    Ctor(String, usize),
+}
+
+// Fields are kept in the order of the literal.
+pub struct StructValue {
+    pub name: String,
+    pub fields: Vec<(String, Value)>,
+}
+
+pub struct VariantValue {
+    pub name: String,
+    pub args: Vec<Value>,
+}
+
+// A function value together with the environment it was defined in.
+pub struct Closure {
+    pub args: Vec<Pattern>,
+    pub block: Box<Expr>,
+    pub env: Env,
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(n, _) => write!(f, "{}", n),
+            // always with a `.` or an exponent, like the literals
+            Value::F64(x) => write!(f, "{:?}", x),
+            Value::Unit => write!(f, "unit"),
+            Value::Bool(b) => write!(f, "{}", b),
+            // quoted and escaped like the literals
+            Value::Str(s) => write!(f, "{:?}", s),
+            Value::Char(c) => write!(f, "{:?}", c),
+            Value::Closure(c) => write!(f, "<function/{}>", c.args.len()),
+            Value::Struct(s) => {
+                write!(f, "{} {{", s.name)?;
+                for (i, (name, v)) in s.fields.iter().enumerate() {
+                    write!(f, "{} {}: {}", if i == 0 { "" } else { "," }, name, v)?;
+                }
+                write!(f, " }}")
+            }
+            Value::Variant(v) => {
+                write!(f, "{}", v.name)?;
+                if !v.args.is_empty() {
+                    let args: Vec<String> = v.args.iter().map(|a| a.to_string()).collect();
+                    write!(f, "({})", args.join(", "))?;
+                }
+                Ok(())
+            }
+            Value::Tuple(elems) => {
+                let elems: Vec<String> = elems.iter().map(|e| e.to_string()).collect();
+                write!(f, "({})", elems.join(", "))
+            }
+            Value::Ctor(_, arity) => write!(f, "<function/{}>", arity),
+        }
+    }
+}
+
+// Closures may capture themselves through their env, so Debug must not
+// walk into it.
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+struct Frame {
+    vars: HashMap<String, Value>,
+    parent: Option<Env>,
+}
+
+// Layered runtime environment. Cloning an Env shares the frames, which is
+// what closures need to see later definitions in the same scope.
+#[derive(Clone)]
+pub struct Env {
+    frame: Rc<RefCell<Frame>>,
+    // number of calls being evaluated
+    depth: usize,
+}
+
+impl Env {
+    pub fn new() -> Env {
+        Env {
+            frame: Rc::new(RefCell::new(Frame {
+                vars: HashMap::new(),
+                parent: None,
+            })),
+            depth: 0,
+        }
+    }
+
+    pub fn child(&self) -> Env {
+        Env {
+            frame: Rc::new(RefCell::new(Frame {
+                vars: HashMap::new(),
+                parent: Some(self.clone()),
+            })),
+            depth: self.depth,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        let frame = self.frame.borrow();
+        match frame.vars.get(name) {
+            Some(v) => Some(v.clone()),
+            None => frame.parent.as_ref().and_then(|p| p.get(name)),
+        }
+    }
+
+    pub fn define(&self, name: String, val: Value) {
+        self.frame.borrow_mut().vars.insert(name, val);
+    }
+}
+
+impl Program {
+    // Defines the items in order, scoped like the elements of a block, and
+    // returns the scope holding them.
+    pub fn define(&self, env: &Env) -> Result<Env, Diagnostic> {
+        let mut scope = env.child();
+        let mut after_func = false;
+        for item in &self.items {
+            match item {
+                Item::Func(f) => {
+                    if !after_func {
+                        scope = scope.child();
+                    }
+                    f.eval(&scope)?;
+                }
+                Item::Const(c) => {
+                    scope = scope.child();
+                    c.eval(&scope)?;
+                }
+                Item::Enum { variants, .. } => {
+                    scope = scope.child();
+                    for v in variants {
+                        let ctor = if v.args.is_empty() {
+                            Value::Variant(Rc::new(VariantValue {
+                                name: v.name.clone(),
+                                args: Vec::new(),
+                            }))
+                        } else {
+                            Value::Ctor(v.name.clone(), v.args.len())
+                        };
+                        scope.define(v.name.clone(), ctor);
+                    }
+                }
+                Item::Struct { .. }
+                | Item::TypeAlias { .. }
+                | Item::Import { .. }
+                | Item::Error { .. } => (),
+            }
+            after_func = matches!(item, Item::Func(_));
+        }
+        Ok(scope)
+    }
+
+    // Defines the items and calls `main`.
+    pub fn run(&self, env: &Env) -> Result<Value, Diagnostic> {
+        match self.define(env)?.get("main") {
+            Some(main) => main.apply(Vec::new(), env.depth + 1),
+            None => Err(Diagnostic::error("E0300", "unbound variable `main`")),
+        }
+    }
+}
+
+impl Expr {
+    pub fn eval(&self, env: &Env) -> Result<Value, Diagnostic> {
+        match self {
+            Expr::Unit { .. } => Ok(Value::Unit),
+            Expr::Int { val, ty, .. } => Ok(Value::Int(*val, ty.clone())),
+            Expr::Float { val, .. } => Ok(Value::F64(*val)),
+            // only in a tree the parser recovered from errors in
+            Expr::Error { info } => {
+                Err(Diagnostic::error("E0100", "Syntax error").with_primary(info.clone()))
+            }
+            Expr::Bool { val, .. } => Ok(Value::Bool(*val)),
+            Expr::Str { val, .. } => Ok(Value::Str(Rc::from(val.as_str()))),
+            Expr::Char { val, .. } => Ok(Value::Char(*val)),
+            Expr::Var { name, info } => match env.get(name) {
+                Some(v) => Ok(v),
+                None => Err(
+                    Diagnostic::error("E0300", &format!("unbound variable `{}`", name))
+                        .with_primary(info.clone()),
+                ),
+            },
+            Expr::Block { exprs, .. } => {
+                let mut scope = env.child();
+                let mut last = Value::Unit;
+                let mut after_func = false;
+                for expr in exprs {
+                    // each let opens a scope for the rest of the block, so
+                    // that closures keep seeing the binding they captured
+                    // even after it is shadowed. Adjacent named functions
+                    // share one, where they can see each other.
+                    match **expr {
+                        Expr::Let { .. } => scope = scope.child(),
+                        Expr::NamedFunc { .. } if !after_func => scope = scope.child(),
+                        _ => (),
+                    }
+                    after_func = matches!(**expr, Expr::NamedFunc { .. });
+                    last = expr.eval(&scope)?;
+                }
+                Ok(last)
+            }
+            Expr::Let {
+                pattern,
+                init,
+                info,
+                ..
+            } => {
+                let val = init.eval(env)?;
+                pattern
+                    .bind(&val, env)
+                    .map_err(|d| d.with_primary(info.clone()))?;
+                Ok(Value::Unit)
+            }
+            Expr::AnonFunc {
+                args_decl, block, ..
+            } => Ok(Value::Closure(Rc::new(Closure {
+                args: args_decl.iter().map(|d| d.pattern.clone()).collect(),
+                block: block.clone(),
+                env: env.clone(),
+            }))),
+            Expr::If {
+                cond,
+                then_block,
+                else_block,
+                ..
+            } => match cond.eval(env)? {
+                Value::Bool(true) => then_block.eval(env),
+                Value::Bool(false) => match else_block {
+                    Some(e) => e.eval(env),
+                    None => Ok(Value::Unit),
+                },
+                v => Err(type_error(&format!("if {}", v)).with_primary(cond.info().clone())),
+            },
+            Expr::BinOp { op, lhs, rhs, info } => {
+                let l = lhs.eval(env)?;
+                // && and || do not evaluate rhs unless needed
+                match (op, &l) {
+                    (BinOp::And, Value::Bool(false)) => return Ok(l),
+                    (BinOp::Or, Value::Bool(true)) => return Ok(l),
+                    _ => (),
+                }
+                let r = rhs.eval(env)?;
+                eval_binop(*op, l, r).map_err(|d| d.with_primary(info.clone()))
+            }
+            Expr::UnaryOp { op, operand, info } => match (op, operand.eval(env)?) {
+                (UnaryOp::Neg, Value::Int(n, ty)) => {
+                    int_value(-(n as i128), ty).map_err(|d| d.with_primary(info.clone()))
+                }
+                (UnaryOp::Neg, Value::F64(x)) => Ok(Value::F64(-x)),
+                (UnaryOp::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+                (op, v) => Err(type_error(&format!("{}{}", op, v)).with_primary(info.clone())),
+            },
+            Expr::Cast { expr, ty, info } => match (expr.eval(env)?, &ty.ty) {
+                (Value::Int(n, _), Type::F64) => Ok(Value::F64(n as f64)),
+                (Value::Int(n, _), to) => {
+                    int_value(n as i128, to.clone()).map_err(|d| d.with_primary(info.clone()))
+                }
+                (Value::F64(x), Type::F64) => Ok(Value::F64(x)),
+                // rounded toward zero, failing for NaN and the infinities
+                (Value::F64(x), to) if x.is_finite() => int_value(x.trunc() as i128, to.clone())
+                    .map_err(|d| d.with_primary(info.clone())),
+                (Value::F64(x), to) => Err(overflow(x, to).with_primary(info.clone())),
+                (v, to) => Err(type_error(&format!("{} as {}", v, to)).with_primary(info.clone())),
+            },
+            Expr::NamedFunc {
+                name,
+                args_def,
+                block,
+                ..
+            } => {
+                // the closure shares `env`, so the function can call itself
+                let closure = Value::Closure(Rc::new(Closure {
+                    args: args_def.iter().map(|d| d.pattern.clone()).collect(),
+                    block: block.clone(),
+                    env: env.clone(),
+                }));
+                env.define(name.clone(), closure);
+                Ok(Value::Unit)
+            }
+            Expr::StructLit { name, fields, .. } => {
+                let mut vals = Vec::new();
+                for f in fields {
+                    vals.push((f.name.clone(), f.value.eval(env)?));
+                }
+                Ok(Value::Struct(Rc::new(StructValue {
+                    name: name.clone(),
+                    fields: vals,
+                })))
+            }
+            Expr::Field { expr, field, info } => {
+                let v = expr.eval(env)?;
+                let found = match &v {
+                    Value::Struct(s) => s.fields.iter().find(|(n, _)| n == field),
+                    _ => None,
+                };
+                match found {
+                    Some((_, f)) => Ok(f.clone()),
+                    None => Err(type_error(&format!("{}.{}", v, field)).with_primary(info.clone())),
+                }
+            }
+            Expr::Tuple { elems, .. } => {
+                let mut vals = Vec::new();
+                for e in elems {
+                    vals.push(e.eval(env)?);
+                }
+                Ok(Value::Tuple(Rc::new(vals)))
+            }
+            Expr::TupleField { expr, index, info } => {
+                let v = expr.eval(env)?;
+                match &v {
+                    Value::Tuple(elems) if *index < elems.len() => Ok(elems[*index].clone()),
+                    _ => Err(type_error(&format!("{}.{}", v, index)).with_primary(info.clone())),
+                }
+            }
+            Expr::Match {
+                scrutinee,
+                arms,
+                info,
+            } => {
+                let v = scrutinee.eval(env)?;
+                for arm in arms {
+                    let mut bindings = Vec::new();
+                    if arm.pattern.matches(&v, &mut bindings) {
+                        let scope = env.child();
+                        for (name, val) in bindings {
+                            scope.define(name, val);
+                        }
+                        return arm.body.eval(&scope);
+                    }
+                }
+                Err(
+                    Diagnostic::error("E0305", &format!("no arm matches `{}`", v))
+                        .with_primary(info.clone()),
+                )
+            }
+            Expr::FuncApp { callee, args, info } => {
+                let callee = callee.eval(env)?;
+                let mut arg_vals = Vec::new();
+                for arg in args {
+                    arg_vals.push(arg.eval(env)?);
+                }
+                // errors from inside the callee collect the calls they
+                // happened in, innermost first and at most TRACE_LEN
+                callee
+                    .apply(arg_vals, env.depth + 1)
+                    .map_err(|d| match d.primary {
+                        None => d.with_primary(info.clone()),
+                        Some(_) if d.secondary.len() < TRACE_LEN => {
+                            d.with_secondary(info.clone(), "in this call")
+                        }
+                        Some(_) => d,
+                    })
+            }
+        }
+    }
+}
+
+// `n` as a value of the integer type `ty`, which overflows out of its
+// range
+fn int_value(n: i128, ty: Type) -> Result<Value, Diagnostic> {
+    match ty.int_range() {
+        Some((min, max)) if (min as i128..=max as i128).contains(&n) => {
+            Ok(Value::Int(n as i64, ty))
+        }
+        _ => Err(overflow(n, &ty)),
+    }
+}
+
+fn overflow(n: impl fmt::Debug, ty: &Type) -> Diagnostic {
+    Diagnostic::error("E0303", "integer overflow")
+        .with_note(&format!("{:?} is out of the range of {}", n, ty))
+}
+
+// only reachable when the program was not type checked
+fn type_error(what: &str) -> Diagnostic {
+    Diagnostic::error("E0200", &format!("`{}` is ill-typed", what))
+}
+
+// Integer arithmetic is checked: overflow is a runtime error rather than
+// wrapping around, and so is division (or remainder) by zero. Both operands
+// have the same type, and the exact result must be in its range. F64
+// follows IEEE 754 instead, where 1.0 / 0.0 is inf and 0.0 / 0.0 is NaN.
+fn eval_binop(op: BinOp, l: Value, r: Value) -> Result<Value, Diagnostic> {
+    match (op, &l, &r) {
+        (BinOp::Div, Value::Int(..), Value::Int(0, _))
+        | (BinOp::Rem, Value::Int(..), Value::Int(0, _)) => {
+            Err(Diagnostic::error("E0304", "division by zero"))
+        }
+        (BinOp::Add, Value::Int(a, ty), Value::Int(b, _)) => {
+            int_value(*a as i128 + *b as i128, ty.clone())
+        }
+        (BinOp::Sub, Value::Int(a, ty), Value::Int(b, _)) => {
+            int_value(*a as i128 - *b as i128, ty.clone())
+        }
+        (BinOp::Mul, Value::Int(a, ty), Value::Int(b, _)) => {
+            int_value(*a as i128 * *b as i128, ty.clone())
+        }
+        (BinOp::Div, Value::Int(a, ty), Value::Int(b, _)) => {
+            int_value(*a as i128 / *b as i128, ty.clone())
+        }
+        (BinOp::Rem, Value::Int(a, ty), Value::Int(b, _)) => {
+            int_value(*a as i128 % *b as i128, ty.clone())
+        }
+        (BinOp::Lt, Value::Int(a, _), Value::Int(b, _)) => Ok(Value::Bool(a < b)),
+        (BinOp::Le, Value::Int(a, _), Value::Int(b, _)) => Ok(Value::Bool(a <= b)),
+        (BinOp::Gt, Value::Int(a, _), Value::Int(b, _)) => Ok(Value::Bool(a > b)),
+        (BinOp::Ge, Value::Int(a, _), Value::Int(b, _)) => Ok(Value::Bool(a >= b)),
+        (BinOp::Add, Value::F64(a), Value::F64(b)) => Ok(Value::F64(a + b)),
+        (BinOp::Sub, Value::F64(a), Value::F64(b)) => Ok(Value::F64(a - b)),
+        (BinOp::Mul, Value::F64(a), Value::F64(b)) => Ok(Value::F64(a * b)),
+        (BinOp::Div, Value::F64(a), Value::F64(b)) => Ok(Value::F64(a / b)),
+        (BinOp::Rem, Value::F64(a), Value::F64(b)) => Ok(Value::F64(a % b)),
+        (BinOp::Lt, Value::F64(a), Value::F64(b)) => Ok(Value::Bool(a < b)),
+        (BinOp::Le, Value::F64(a), Value::F64(b)) => Ok(Value::Bool(a <= b)),
+        (BinOp::Gt, Value::F64(a), Value::F64(b)) => Ok(Value::Bool(a > b)),
+        (BinOp::Ge, Value::F64(a), Value::F64(b)) => Ok(Value::Bool(a >= b)),
+        (BinOp::And, Value::Bool(_), Value::Bool(_))
+        | (BinOp::Or, Value::Bool(_), Value::Bool(_)) => Ok(r),
+        (BinOp::Eq, _, _) | (BinOp::Ne, _, _) => match l.equals(&r) {
+            Some(eq) => Ok(Value::Bool(eq == (op == BinOp::Eq))),
+            None => Err(Diagnostic::error(
+                "E0206",
+                "Values of function type cannot be compared",
+            )),
+        },
+        _ => Err(type_error(&format!("{} {} {}", l, op, r))),
+    }
+}
+
+impl Value {
+    // None when the values cannot be compared, like functions
+    pub fn equals(&self, other: &Value) -> Option<bool> {
+        match (self, other) {
+            (Value::Int(a, _), Value::Int(b, _)) => Some(a == b),
+            // NaN is equal to nothing, not even itself
+            (Value::F64(a), Value::F64(b)) => Some(a == b),
+            (Value::Bool(a), Value::Bool(b)) => Some(a == b),
+            (Value::Str(a), Value::Str(b)) => Some(a == b),
+            (Value::Char(a), Value::Char(b)) => Some(a == b),
+            (Value::Unit, Value::Unit) => Some(true),
+            (Value::Variant(a), Value::Variant(b)) => {
+                if a.name != b.name {
+                    return Some(false);
+                }
+                let mut eq = true;
+                for (v, w) in a.args.iter().zip(&b.args) {
+                    eq = eq && v.equals(w)?;
+                }
+                Some(eq)
+            }
+            (Value::Tuple(a), Value::Tuple(b)) => {
+                let mut eq = true;
+                for (v, w) in a.iter().zip(b.iter()) {
+                    eq = eq && v.equals(w)?;
+                }
+                Some(eq)
+            }
+            (Value::Struct(a), Value::Struct(b)) => {
+                let mut eq = true;
+                for (name, v) in &a.fields {
+                    let w = b.fields.iter().find(|(n, _)| n == name)?;
+                    eq = eq && v.equals(&w.1)?;
+                }
+                Some(eq)
+            }
+            _ => None,
+        }
+    }
+
+    // Calls a function at the given depth of calls, which each take some
+    // native stack: past MAX_CALL_DEPTH the call fails instead.
+    pub fn apply(&self, args: Vec<Value>, depth: usize) -> Result<Value, Diagnostic> {
+        let closure = match self {
+            Value::Closure(c) => c,
+            Value::Ctor(name, arity) => {
+                if *arity != args.len() {
+                    return Err(Diagnostic::error(
+                        "E0302",
+                        &format!("expected {} args but got {}", arity, args.len()),
+                    ));
+                }
+                return Ok(Value::Variant(Rc::new(VariantValue {
+                    name: name.clone(),
+                    args,
+                })));
+            }
+            v => {
+                return Err(Diagnostic::error(
+                    "E0301",
+                    &format!("`{}` is not a function", v),
+                ))
+            }
+        };
+        if closure.args.len() != args.len() {
+            return Err(Diagnostic::error(
+                "E0302",
+                &format!(
+                    "expected {} args but got {}",
+                    closure.args.len(),
+                    args.len()
+                ),
+            ));
+        }
+        if depth > MAX_CALL_DEPTH {
+            return Err(Diagnostic::error("E0306", "recursion too deep")
+                .with_note(&format!("more than {} calls are nested", MAX_CALL_DEPTH)));
+        }
+        let mut scope = closure.env.child();
+        scope.depth = depth;
+        for (pattern, val) in closure.args.iter().zip(args) {
+            pattern.bind(&val, &scope)?;
+        }
+        closure.block.eval(&scope)
+    }
+}
+
+impl Pattern {
+    // Whether `v` matches, pushing the values of the variables it binds.
+    fn matches(&self, v: &Value, bindings: &mut Vec<(String, Value)>) -> bool {
+        match (self, v) {
+            (Pattern::Wildcard { .. }, _) => true,
+            (Pattern::Bind { name, .. }, v) => {
+                bindings.push((name.clone(), v.clone()));
+                true
+            }
+            (Pattern::Int { val, .. }, Value::Int(n, _)) => val == n,
+            (Pattern::Bool { val, .. }, Value::Bool(b)) => val == b,
+            (Pattern::Char { val, .. }, Value::Char(c)) => val == c,
+            (Pattern::Str { val, .. }, Value::Str(s)) => **val == **s,
+            (Pattern::Unit { .. }, Value::Unit) => true,
+            (Pattern::Ctor { name, args, .. }, Value::Variant(variant)) => {
+                *name == variant.name
+                    && args.len() == variant.args.len()
+                    && args
+                        .iter()
+                        .zip(&variant.args)
+                        .all(|(p, v)| p.matches(v, bindings))
+            }
+            (Pattern::Tuple { elems, .. }, Value::Tuple(vals)) => {
+                elems.len() == vals.len()
+                    && elems
+                        .iter()
+                        .zip(vals.iter())
+                        .all(|(p, v)| p.matches(v, bindings))
+            }
+            _ => false,
+        }
+    }
+
+    // Defines the bindings of a pattern matching every value, like the
+    // one of a let, in `env`.
+    fn bind(&self, v: &Value, env: &Env) -> Result<(), Diagnostic> {
+        let mut bindings = Vec::new();
+        if !self.matches(v, &mut bindings) {
+            return Err(type_error(&format!("{} = {}", self, v)));
+        }
+        for (name, val) in bindings {
+            env.define(name, val);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+// Exhaustiveness of match expressions and reachability of their arms, by
+// the usefulness algorithm of Maranget's "Warnings for pattern matching".
+//
+// A row of patterns is useful against the rows above it when some value
+// matched by it is matched by none of them. An arm is unreachable when its
+// pattern is not useful against the arms before it, and a match is
+// exhaustive when a wildcard would not be useful against all its arms.
+use crate::syntax::{Pattern, TokenInfo};
+use crate::type_def::Type;
+
+#[cfg(test)]
+mod exhaustive_test {
+    use super::*;
+
+    fn info() -> TokenInfo {
+        TokenInfo::default()
+    }
+
+    fn ctor(name: &str, args: Vec<Pattern>) -> Pattern {
+        Pattern::Ctor {
+            name: String::from(name),
+            args,
+            info: info(),
+        }
+    }
+
+    fn wild() -> Pattern {
+        Pattern::Wildcard { info: info() }
+    }
+
+    // enum Option { Some(Bool), None }
+    fn signature(t: &Type) -> Option<Vec<(Ctor, Vec<Type>)>> {
+        match t {
+            Type::UserType { name, .. } if name == "Option" => Some(vec![
+                (Ctor::Variant(String::from("Some")), vec![Type::Bool]),
+                (Ctor::Variant(String::from("None")), vec![]),
+            ]),
+            Type::Bool => Some(vec![
+                (Ctor::Bool(true), vec![]),
+                (Ctor::Bool(false), vec![]),
+            ]),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_missing() {
+        let checker = Checker::new(&signature);
+        let option = Type::UserType {
+            name: String::from("Option"),
+            args: Vec::new(),
+        };
+        let some_true = ctor(
+            "Some",
+            vec![Pattern::Bool {
+                val: true,
+                info: info(),
+            }],
+        );
+        let none = ctor("None", vec![]);
+        let missing = checker.missing(&[&some_true, &none], &option);
+        let missing: Vec<String> = missing.iter().map(|p| p.to_string()).collect();
+        assert_eq!(missing, vec!["Some(false)"]);
+        assert!(checker
+            .missing(&[&some_true, &none, &wild()], &option)
+            .is_empty());
+        let missing = checker.missing(&[], &option);
+        assert_eq!(missing.len(), 2);
+        // integers are never exhausted by literals
+        let one = Pattern::Int {
+            val: 1,
+            ty: Type::I32,
+            info: info(),
+        };
+        assert_eq!(checker.missing(&[&one], &Type::I32)[0].to_string(), "_");
+        // and neither are chars and strings
+        let a = Pattern::Char {
+            val: 'a',
+            info: info(),
+        };
+        assert_eq!(checker.missing(&[&a], &Type::Char)[0].to_string(), "_");
+        let a = Pattern::Str {
+            val: String::from("a"),
+            info: info(),
+        };
+        assert_eq!(checker.missing(&[&a], &Type::String)[0].to_string(), "_");
+        assert!(!checker.is_useful(&[&a], &a, &Type::String));
+    }
+
+    #[test]
+    fn test_useful() {
+        let checker = Checker::new(&signature);
+        let option = Type::UserType {
+            name: String::from("Option"),
+            args: Vec::new(),
+        };
+        let some = ctor("Some", vec![wild()]);
+        let none = ctor("None", vec![]);
+        assert!(checker.is_useful(&[&none], &some, &option));
+        assert!(!checker.is_useful(&[&none, &some], &wild(), &option));
+        assert!(!checker.is_useful(&[&wild()], &none, &option));
+    }
+}
+
+// A way of building values, as far as patterns can tell them apart.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ctor {
+    Variant(String),
+    Bool(bool),
+    Unit,
+    Tuple,
+    Int(i64, Type),
+    Char(char),
+    Str(String),
+}
+
+// The constructors of a type, each with the types of its fields, or None
+// when they cannot be listed, like for the integers, chars and strings.
+pub type Signature<'a> = dyn Fn(&Type) -> Option<Vec<(Ctor, Vec<Type>)>> + 'a;
+
+// Patterns with bindings turned into wildcards.
+#[derive(Clone)]
+enum Pat {
+    Wild,
+    Ctor(Ctor, Vec<Pat>),
+}
+
+impl Pat {
+    fn from(p: &Pattern) -> Pat {
+        match p {
+            Pattern::Wildcard { .. } | Pattern::Bind { .. } => Pat::Wild,
+            Pattern::Int { val, ty, .. } => Pat::Ctor(Ctor::Int(*val, ty.clone()), Vec::new()),
+            Pattern::Bool { val, .. } => Pat::Ctor(Ctor::Bool(*val), Vec::new()),
+            Pattern::Char { val, .. } => Pat::Ctor(Ctor::Char(*val), Vec::new()),
+            Pattern::Str { val, .. } => Pat::Ctor(Ctor::Str(val.clone()), Vec::new()),
+            Pattern::Unit { .. } => Pat::Ctor(Ctor::Unit, Vec::new()),
+            Pattern::Ctor { name, args, .. } => Pat::Ctor(
+                Ctor::Variant(name.clone()),
+                args.iter().map(Pat::from).collect(),
+            ),
+            Pattern::Tuple { elems, .. } => {
+                Pat::Ctor(Ctor::Tuple, elems.iter().map(Pat::from).collect())
+            }
+        }
+    }
+
+    fn into_pattern(self) -> Pattern {
+        let info = TokenInfo::default();
+        match self {
+            Pat::Wild => Pattern::Wildcard { info },
+            Pat::Ctor(Ctor::Variant(name), args) => Pattern::Ctor {
+                name,
+                args: args.into_iter().map(Pat::into_pattern).collect(),
+                info,
+            },
+            Pat::Ctor(Ctor::Bool(val), _) => Pattern::Bool { val, info },
+            Pat::Ctor(Ctor::Unit, _) => Pattern::Unit { info },
+            Pat::Ctor(Ctor::Tuple, elems) => Pattern::Tuple {
+                elems: elems.into_iter().map(Pat::into_pattern).collect(),
+                info,
+            },
+            Pat::Ctor(Ctor::Int(val, ty), _) => Pattern::Int { val, ty, info },
+            Pat::Ctor(Ctor::Char(val), _) => Pattern::Char { val, info },
+            Pat::Ctor(Ctor::Str(val), _) => Pattern::Str { val, info },
+        }
+    }
+}
+
+// Rows matching values built by `c`, with its fields in place of the
+// first column.
+fn specialize(rows: &[Vec<Pat>], c: &Ctor, arity: usize) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter_map(|row| {
+            let mut out = match &row[0] {
+                Pat::Wild => vec![Pat::Wild; arity],
+                Pat::Ctor(d, args) if d == c => args.clone(),
+                Pat::Ctor(..) => return None,
+            };
+            out.extend(row[1..].iter().cloned());
+            Some(out)
+        })
+        .collect()
+}
+
+// Rows matching any value in the first column, without it.
+fn default(rows: &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter(|row| matches!(row[0], Pat::Wild))
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+fn concat(fields: &[Type], rest: &[Type]) -> Vec<Type> {
+    fields.iter().chain(rest).cloned().collect()
+}
+
+pub struct Checker<'a> {
+    signature: &'a Signature<'a>,
+}
+
+impl<'a> Checker<'a> {
+    pub fn new(signature: &'a Signature<'a>) -> Checker<'a> {
+        Checker { signature }
+    }
+
+    // Whether some value of type `ty` matched by `p` is matched by none of
+    // `rows`.
+    pub fn is_useful(&self, rows: &[&Pattern], p: &Pattern, ty: &Type) -> bool {
+        let rows: Vec<Vec<Pat>> = rows.iter().map(|r| vec![Pat::from(r)]).collect();
+        self.useful(&rows, &[Pat::from(p)], std::slice::from_ref(ty))
+    }
+
+    // Patterns for the values of type `ty` matched by none of `rows`, one
+    // for each constructor of `ty` that is not fully covered.
+    pub fn missing(&self, rows: &[&Pattern], ty: &Type) -> Vec<Pattern> {
+        let rows: Vec<Vec<Pat>> = rows.iter().map(|r| vec![Pat::from(r)]).collect();
+        let witnesses: Vec<Vec<Pat>> = match (self.signature)(ty) {
+            Some(sig) => sig
+                .into_iter()
+                .filter_map(|(c, fields)| {
+                    let rows = specialize(&rows, &c, fields.len());
+                    self.witness(&rows, &fields).map(|w| vec![Pat::Ctor(c, w)])
+                })
+                .collect(),
+            None => self
+                .witness(&rows, std::slice::from_ref(ty))
+                .into_iter()
+                .collect(),
+        };
+        witnesses
+            .into_iter()
+            .flatten()
+            .map(Pat::into_pattern)
+            .collect()
+    }
+
+    // The constructors of `ty` if every one of them heads some row.
+    fn complete(&self, rows: &[Vec<Pat>], ty: &Type) -> Option<Vec<(Ctor, Vec<Type>)>> {
+        let sig = (self.signature)(ty)?;
+        let covered = sig.iter().all(|(c, _)| {
+            rows.iter()
+                .any(|row| matches!(&row[0], Pat::Ctor(d, _) if d == c))
+        });
+        if covered {
+            Some(sig)
+        } else {
+            None
+        }
+    }
+
+    fn fields(&self, ty: &Type, c: &Ctor) -> Vec<Type> {
+        (self.signature)(ty)
+            .and_then(|sig| sig.into_iter().find(|(d, _)| d == c))
+            .map(|(_, fields)| fields)
+            .unwrap_or_default()
+    }
+
+    // `q` has a pattern for each column, of the types `tys`.
+    fn useful(&self, rows: &[Vec<Pat>], q: &[Pat], tys: &[Type]) -> bool {
+        let (head, rest) = match q.split_first() {
+            Some(split) => split,
+            None => return rows.is_empty(),
+        };
+        match head {
+            Pat::Ctor(c, args) => {
+                let fields = self.fields(&tys[0], c);
+                let q: Vec<Pat> = args.iter().chain(rest).cloned().collect();
+                let rows = specialize(rows, c, args.len());
+                self.useful(&rows, &q, &concat(&fields, &tys[1..]))
+            }
+            Pat::Wild => match self.complete(rows, &tys[0]) {
+                Some(sig) => sig.iter().any(|(c, fields)| {
+                    let q: Vec<Pat> = vec![Pat::Wild; fields.len()]
+                        .into_iter()
+                        .chain(rest.iter().cloned())
+                        .collect();
+                    let rows = specialize(rows, c, fields.len());
+                    self.useful(&rows, &q, &concat(fields, &tys[1..]))
+                }),
+                None => self.useful(&default(rows), rest, &tys[1..]),
+            },
+        }
+    }
+
+    // Patterns, one for each column of the types `tys`, for values that
+    // no row matches.
+    fn witness(&self, rows: &[Vec<Pat>], tys: &[Type]) -> Option<Vec<Pat>> {
+        let (ty, rest) = match tys.split_first() {
+            Some(split) => split,
+            None => {
+                return if rows.is_empty() {
+                    Some(Vec::new())
+                } else {
+                    None
+                }
+            }
+        };
+        match self.complete(rows, ty) {
+            Some(sig) => sig.into_iter().find_map(|(c, fields)| {
+                let specialized = specialize(rows, &c, fields.len());
+                let mut w = self.witness(&specialized, &concat(&fields, rest))?;
+                let tail = w.split_off(fields.len());
+                let mut out = vec![Pat::Ctor(c, w)];
+                out.extend(tail);
+                Some(out)
+            }),
+            None => {
+                let mut w = self.witness(&default(rows), rest)?;
+                // name a constructor no row starts with, if there is one
+                let uncovered = (self.signature)(ty).and_then(|sig| {
+                    sig.into_iter().find(|(c, _)| {
+                        !rows
+                            .iter()
+                            .any(|row| matches!(&row[0], Pat::Ctor(d, _) if d == c))
+                    })
+                });
+                let head = match uncovered {
+                    Some((c, fields)) => Pat::Ctor(c, vec![Pat::Wild; fields.len()]),
+                    None => Pat::Wild,
+                };
+                w.insert(0, head);
+                Some(w)
+            }
+        }
+    }
+}
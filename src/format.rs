@@ -0,0 +1,671 @@
+// The formatter of `lung fmt`. It prints the concrete syntax tree of a
+// program back as source in one canonical layout, keeping its comments.
+// Nodes are laid out as documents, whose groups are printed on one line
+// when they fit in the width and broken over lines otherwise.
+
+use crate::cst::{self, SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken};
+use crate::diagnostic::Diagnostic;
+use crate::syntax::{TokenKind, TriviaKind};
+
+#[cfg(test)]
+mod format_test {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    fn fmt(src: &str) -> String {
+        format(src, DEFAULT_WIDTH).unwrap()
+    }
+
+    // the tokens of `src`, but the optional semicolons after items
+    fn kinds(src: &str) -> Vec<TokenKind> {
+        let tokens = Lexer::from_string(String::from(src)).lex().unwrap();
+        let mut kinds: Vec<TokenKind> = Vec::new();
+        let mut depth = 0;
+        for t in tokens {
+            match t.kind {
+                TokenKind::LBrace => depth += 1,
+                TokenKind::RBrace => depth -= 1,
+                TokenKind::SemiColon if depth == 0 && kinds.last() == Some(&TokenKind::RBrace) => {
+                    continue
+                }
+                _ => (),
+            }
+            kinds.push(t.kind);
+        }
+        kinds
+    }
+
+    #[test]
+    fn test_format() {
+        let src = "type Num=I32;
+let zero:Num=0;
+fn main()->Unit{
+    function(foo:Unit,bar:Num)->Unit{
+        bar;foo
+    }(unit,zero)
+}";
+        let expected = "type Num = I32;
+let zero: Num = 0;
+fn main() -> Unit {
+    function(foo: Unit, bar: Num) -> Unit { bar; foo }(unit, zero)
+}
+";
+        assert_eq!(fmt(src), expected);
+        let src = "struct P<T>{x:T,y:Fn(T)->(T,Bool)};enum E{A(I32,U8),B}
+fn f(e:E)->I32{match e{A(n,_)=>-n*2,B=>if a.x<1{0}else{let z=(1,2);z.0}}}";
+        let expected = "struct P<T> { x: T, y: Fn(T) -> (T, Bool) }
+enum E { A(I32, U8), B }
+fn f(e: E) -> I32 {
+    match e {
+        A(n, _) => -n * 2,
+        B => if a.x < 1 { 0 } else { let z = (1, 2); z.0 }
+    }
+}
+";
+        assert_eq!(fmt(src), expected);
+        assert_eq!(fmt(""), "");
+        assert_eq!(fmt("fn f() {}"), "fn f() {}\n");
+    }
+
+    #[test]
+    fn test_width() {
+        let src = "fn f() { g(first_argument, second_argument, third_argument) + h(1) }";
+        let expected = "fn f() {
+    g(
+        first_argument,
+        second_argument,
+        third_argument
+    ) +
+        h(1)
+}
+";
+        assert_eq!(format(src, 40).unwrap(), expected);
+        let expected = "fn f() {
+    g(first_argument, second_argument, third_argument) +
+        h(1)
+}
+";
+        assert_eq!(format(src, 60).unwrap(), expected);
+        let src = "fn f() { let p = Point { x: 1, y: 2 }; p }";
+        let expected = "fn f() {
+    let p = Point {
+        x: 1,
+        y: 2
+    };
+    p
+}
+";
+        assert_eq!(format(src, 20).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_comments() {
+        let src = "// header
+
+/// Doc.
+fn f(a: I32, // first
+  b: I32) -> I32 {
+    // leading
+    a + /* inline */ b;
+
+
+    b // trailing
+}
+/* end */";
+        let expected = "// header
+
+/// Doc.
+fn f(
+    a: I32, // first
+    b: I32
+) -> I32 {
+    // leading
+    a + /* inline */ b;
+
+    b // trailing
+}
+/* end */
+";
+        assert_eq!(fmt(src), expected);
+    }
+
+    #[test]
+    fn test_idempotent() {
+        let files = [
+            "src/test/test_eval.txt",
+            "src/test/test_parser.txt",
+            "src/test/test_typing.txt",
+            "src/test/importmain.lung",
+            "src/test/importlib.lung",
+        ];
+        for f in files.iter() {
+            let src = std::fs::read_to_string(f).unwrap();
+            let once = fmt(&src);
+            assert_eq!(fmt(&once), once, "{}", f);
+            assert_eq!(kinds(&once), kinds(&src), "{}", f);
+        }
+        for width in [10, 30, 100].iter() {
+            let src = "fn f(x: I32, y: I32) { // c
+                let t = (x, y) /* t */; match t { (0, _) => x, // zero
+                _ => g(x, y, function(z: I32) { z + x * y }) } }";
+            let once = format(src, *width).unwrap();
+            assert_eq!(format(&once, *width).unwrap(), once, "{}", width);
+            assert_eq!(kinds(&once), kinds(src));
+        }
+        assert!(format("fn f( {", DEFAULT_WIDTH).is_err());
+    }
+}
+
+pub const DEFAULT_WIDTH: usize = 100;
+
+const INDENT: usize = 4;
+
+// A layout to print
+#[derive(Debug)]
+enum Doc {
+    Text(String),
+    // a space, which is dropped at the start of a line
+    Space,
+    // a space, or a newline in a broken group
+    Line,
+    // nothing, or a newline in a broken group
+    SoftLine,
+    HardLine,
+    // a comment on a line of its own
+    OwnLine(String),
+    // an empty line between comments, kept from the source
+    Blank,
+    // a comment ending a line
+    LineComment(String),
+    Nest(Box<Doc>),
+    // with whether it has to be broken, for a newline in it
+    Group(Box<Doc>, bool),
+    Concat(Vec<Doc>),
+}
+
+impl Doc {
+    // Whether a group of the doc has to be broken. Comments before its
+    // first text do not break it, as they go on lines before the group.
+    fn breaks(&self) -> bool {
+        self.breaks_after(&mut false)
+    }
+
+    fn breaks_after(&self, text: &mut bool) -> bool {
+        match self {
+            Doc::Text(_) => {
+                *text = true;
+                false
+            }
+            Doc::HardLine | Doc::LineComment(_) => true,
+            Doc::OwnLine(_) | Doc::Blank => *text,
+            Doc::Nest(doc) => doc.breaks_after(text),
+            Doc::Group(doc, breaks) => *breaks || doc.breaks_after(text),
+            Doc::Concat(docs) => docs.iter().any(|d| d.breaks_after(text)),
+            Doc::Space | Doc::Line | Doc::SoftLine => false,
+        }
+    }
+}
+
+fn group(docs: Vec<Doc>) -> Doc {
+    let doc = Doc::Concat(docs);
+    let breaks = doc.breaks();
+    Doc::Group(Box::new(doc), breaks)
+}
+
+fn nest(docs: Vec<Doc>) -> Doc {
+    Doc::Nest(Box::new(Doc::Concat(docs)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+struct Printer {
+    width: usize,
+    out: String,
+    col: usize,
+    // the indentation of the current line, written with its first text
+    indent: usize,
+    // whether nothing has been written on the current line yet
+    line_start: bool,
+    // whether the line has to end before the next text, after a comment
+    pending: bool,
+}
+
+impl Printer {
+    fn print(doc: &Doc, width: usize) -> String {
+        let mut p = Printer {
+            width,
+            out: String::new(),
+            col: 0,
+            indent: 0,
+            line_start: true,
+            pending: false,
+        };
+        let mut cmds = vec![(0, Mode::Break, doc)];
+        while let Some((indent, mode, doc)) = cmds.pop() {
+            match doc {
+                Doc::Text(s) => p.text(indent, s),
+                Doc::Space => p.space(),
+                Doc::Line if mode == Mode::Flat => p.space(),
+                Doc::SoftLine if mode == Mode::Flat => (),
+                Doc::Line | Doc::SoftLine | Doc::HardLine => p.newline(indent),
+                Doc::OwnLine(s) => {
+                    if !p.line_start {
+                        p.newline(indent);
+                    }
+                    p.text(indent, s);
+                    p.pending = true;
+                }
+                Doc::Blank => {
+                    if !p.line_start {
+                        p.newline(indent);
+                    }
+                    p.newline(indent);
+                }
+                Doc::LineComment(s) => {
+                    p.space();
+                    p.text(indent, s);
+                    p.pending = true;
+                }
+                Doc::Nest(doc) => cmds.push((indent + INDENT, mode, doc)),
+                Doc::Group(doc, breaks) => {
+                    let flat = !breaks && (mode == Mode::Flat || p.fits(doc, &cmds));
+                    let mode = if flat { Mode::Flat } else { Mode::Break };
+                    cmds.push((indent, mode, doc));
+                }
+                Doc::Concat(docs) => cmds.extend(docs.iter().rev().map(|d| (indent, mode, d))),
+            }
+        }
+        p.out
+    }
+
+    fn text(&mut self, indent: usize, s: &str) {
+        if self.pending {
+            self.newline(indent);
+        }
+        if self.line_start {
+            self.out.push_str(&" ".repeat(self.indent));
+            self.line_start = false;
+        }
+        self.out.push_str(s);
+        match s.rfind('\n') {
+            Some(i) => self.col = s[i + 1..].chars().count(),
+            None => self.col += s.chars().count(),
+        }
+    }
+
+    fn space(&mut self) {
+        if !self.line_start && !self.pending {
+            self.out.push(' ');
+            self.col += 1;
+        }
+    }
+
+    fn newline(&mut self, indent: usize) {
+        let len = self.out.trim_end_matches(' ').len();
+        self.out.truncate(len);
+        self.out.push('\n');
+        self.indent = indent;
+        self.col = indent;
+        self.line_start = true;
+        self.pending = false;
+    }
+
+    // Whether `doc` fits on the rest of the line when it is flat, along
+    // with what follows it up to the next newline. `rest` is the stack of
+    // what follows.
+    fn fits(&self, doc: &Doc, rest: &[(usize, Mode, &Doc)]) -> bool {
+        let mut width = self.width as isize - self.col as isize;
+        let mut stack = vec![(Mode::Flat, doc)];
+        let mut rest = rest.iter().rev().map(|(_, mode, doc)| (*mode, *doc));
+        // comments before the first text go on lines of their own
+        let mut text = false;
+        while width >= 0 {
+            let (mode, doc) = match stack.pop().or_else(|| rest.next()) {
+                Some(next) => next,
+                None => return true,
+            };
+            match doc {
+                Doc::Text(s) => {
+                    text = true;
+                    match s.find('\n') {
+                        Some(i) => return s[..i].chars().count() as isize <= width,
+                        None => width -= s.chars().count() as isize,
+                    }
+                }
+                Doc::OwnLine(_) | Doc::Blank if !text => {
+                    width = self.width as isize - self.indent as isize;
+                }
+                Doc::Space => width -= 1,
+                Doc::Line if mode == Mode::Flat => width -= 1,
+                Doc::SoftLine if mode == Mode::Flat => (),
+                Doc::Line | Doc::SoftLine | Doc::HardLine | Doc::OwnLine(_) | Doc::Blank => {
+                    return true
+                }
+                Doc::LineComment(s) => return (s.chars().count() as isize) < width,
+                Doc::Nest(doc) => stack.push((mode, doc)),
+                Doc::Group(doc, breaks) => {
+                    stack.push((if *breaks { Mode::Break } else { mode }, doc));
+                }
+                Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|d| (mode, d))),
+            }
+        }
+        false
+    }
+}
+
+fn is_comment(kind: TriviaKind) -> bool {
+    matches!(kind, TriviaKind::LineComment | TriviaKind::BlockComment)
+}
+
+// A token with the comments around it. Comments before it go on lines of
+// their own and the ones after it stay on its line.
+fn token(t: &SyntaxToken) -> Doc {
+    let mut docs = comments_before(t);
+    match t.kind() {
+        TokenKind::DocComment(_) => docs.push(Doc::OwnLine(String::from(t.text()))),
+        TokenKind::EOF => (),
+        _ => docs.push(Doc::Text(String::from(t.text()))),
+    }
+    docs.extend(comments_after(t));
+    Doc::Concat(docs)
+}
+
+// the comments of a token which is left out
+fn comments(t: &SyntaxToken) -> Doc {
+    let mut docs = comments_before(t);
+    docs.extend(comments_after(t));
+    Doc::Concat(docs)
+}
+
+// The blank lines after the first comment are kept, one at most.
+fn comments_before(t: &SyntaxToken) -> Vec<Doc> {
+    let mut docs = Vec::new();
+    let mut newlines = 0;
+    for (kind, text) in t.leading() {
+        match kind {
+            TriviaKind::Newline => newlines += 1,
+            TriviaKind::Whitespace => (),
+            _ => {
+                if !docs.is_empty() && newlines >= 2 {
+                    docs.push(Doc::Blank);
+                }
+                docs.push(Doc::OwnLine(String::from(text)));
+                newlines = 0;
+            }
+        }
+    }
+    if !docs.is_empty() && newlines >= 2 {
+        docs.push(Doc::Blank);
+    }
+    docs
+}
+
+fn comments_after(t: &SyntaxToken) -> Vec<Doc> {
+    let mut docs = Vec::new();
+    for (kind, text) in t.trailing() {
+        match kind {
+            TriviaKind::LineComment => docs.push(Doc::LineComment(String::from(text))),
+            TriviaKind::BlockComment => {
+                docs.push(Doc::Space);
+                docs.push(Doc::Text(String::from(text)));
+            }
+            _ => (),
+        }
+    }
+    docs
+}
+
+fn has_comments(t: &SyntaxToken) -> bool {
+    let doc = matches!(t.kind(), TokenKind::DocComment(_));
+    doc || t.leading().iter().any(|(kind, _)| is_comment(*kind))
+}
+
+// Whether there is a blank line before the node in the source, which is
+// kept between items and the elements of blocks.
+fn blank_before(n: &SyntaxNode) -> bool {
+    match n.first_token() {
+        Some(t) => {
+            let newlines = t
+                .leading()
+                .into_iter()
+                .take_while(|(kind, _)| !is_comment(*kind))
+                .filter(|(kind, _)| *kind == TriviaKind::Newline)
+                .count();
+            newlines >= 2
+        }
+        None => false,
+    }
+}
+
+fn node(n: &SyntaxNode) -> Doc {
+    let children = n.children();
+    match n.kind() {
+        SyntaxKind::Program => program(&children),
+        SyntaxKind::Block => block(n, &children),
+        SyntaxKind::MatchExpr => match_expr(&children),
+        SyntaxKind::BinExpr => match children.as_slice() {
+            [lhs, op, rhs] => group(vec![
+                element(lhs),
+                Doc::Space,
+                element(op),
+                nest(vec![Doc::Line, element(rhs)]),
+            ]),
+            _ => inline(&children),
+        },
+        // the semicolon after these is left out
+        SyntaxKind::FuncItem | SyntaxKind::StructItem | SyntaxKind::EnumItem => {
+            match children.split_last() {
+                Some((SyntaxElement::Token(t), rest)) if t.kind() == &TokenKind::SemiColon => {
+                    Doc::Concat(vec![inline(rest), comments(t)])
+                }
+                _ => inline(&children),
+            }
+        }
+        _ => inline(&children),
+    }
+}
+
+fn element(e: &SyntaxElement) -> Doc {
+    match e {
+        SyntaxElement::Node(n) => node(n),
+        SyntaxElement::Token(t) => token(t),
+    }
+}
+
+// What an element is, to space it from the one before
+#[derive(Debug, PartialEq)]
+enum Piece<'a> {
+    Token(&'a TokenKind),
+    Node(SyntaxKind),
+}
+
+fn piece(e: &SyntaxElement) -> Piece<'_> {
+    match e {
+        SyntaxElement::Node(n) => Piece::Node(n.kind()),
+        SyntaxElement::Token(t) => Piece::Token(t.kind()),
+    }
+}
+
+// Whether a space goes between two elements of a node laid out on a line:
+// after keywords, commas and colons, and around `->`, `=` and `=>`.
+fn spaced(prev: &Piece, next: &Piece) -> bool {
+    use TokenKind as T;
+    match (prev, next) {
+        (_, Piece::Token(T::Comma | T::Colon | T::SemiColon | T::RParen | T::Dot | T::Gt)) => false,
+        (Piece::Token(T::Comma | T::Colon), _) => true,
+        (Piece::Token(T::Arrow | T::Assign | T::FatArrow), _) => true,
+        (_, Piece::Token(T::Arrow | T::Assign | T::FatArrow)) => true,
+        (
+            Piece::Token(
+                T::Func
+                | T::Let
+                | T::Struct
+                | T::Enum
+                | T::Type
+                | T::Import
+                | T::If
+                | T::Else
+                | T::Match
+                | T::As,
+            ),
+            _,
+        ) => true,
+        (_, Piece::Token(T::Else | T::As | T::LBrace)) => true,
+        (_, Piece::Node(SyntaxKind::Block | SyntaxKind::RetType)) => true,
+        _ => false,
+    }
+}
+
+// The elements of a node on a line, but for the brackets of lists, whose
+// elements go on lines of their own when they do not fit.
+fn inline(children: &[SyntaxElement]) -> Doc {
+    let mut docs = Vec::new();
+    let mut prev: Option<Piece> = None;
+    let mut i = 0;
+    while i < children.len() {
+        let next = piece(&children[i]);
+        if matches!(&prev, Some(prev) if spaced(prev, &next)) {
+            docs.push(Doc::Space);
+        }
+        let close = match next {
+            Piece::Token(TokenKind::LParen) => Some(TokenKind::RParen),
+            Piece::Token(TokenKind::LBrace) => Some(TokenKind::RBrace),
+            _ => None,
+        };
+        let end = close.and_then(|close| {
+            (i + 1..children.len()).find(|j| piece(&children[*j]) == Piece::Token(&close))
+        });
+        match end {
+            Some(end) => {
+                docs.push(list(&children[i..=end]));
+                prev = Some(piece(&children[end]));
+                i = end + 1;
+            }
+            None => {
+                docs.push(element(&children[i]));
+                prev = Some(next);
+                i += 1;
+            }
+        }
+    }
+    Doc::Concat(docs)
+}
+
+// Comma separated elements between brackets. Braces are padded with
+// spaces when the list is on one line, like in `P { x: 1 }`.
+fn list(elems: &[SyntaxElement]) -> Doc {
+    let (open, close) = (&elems[0], &elems[elems.len() - 1]);
+    let inner = &elems[1..elems.len() - 1];
+    if inner.is_empty() {
+        return Doc::Concat(vec![element(open), element(close)]);
+    }
+    let brace = piece(open) == Piece::Token(&TokenKind::LBrace);
+    let line = || if brace { Doc::Line } else { Doc::SoftLine };
+    let mut body = vec![line()];
+    for e in inner {
+        body.push(element(e));
+        if piece(e) == Piece::Token(&TokenKind::Comma) {
+            body.push(Doc::Line);
+        }
+    }
+    group(vec![element(open), nest(body), line(), element(close)])
+}
+
+// The body of a named function is always broken, other blocks only when
+// they do not fit.
+fn block(n: &SyntaxNode, children: &[SyntaxElement]) -> Doc {
+    let (open, close) = (&children[0], &children[children.len() - 1]);
+    let inner = &children[1..children.len() - 1];
+    if inner.is_empty() {
+        return Doc::Concat(vec![element(open), element(close)]);
+    }
+    let func = matches!(n.parent(), Some(p) if p.kind() == SyntaxKind::NamedFunc);
+    let line = || if func { Doc::HardLine } else { Doc::Line };
+    let mut body = vec![line()];
+    for (i, e) in inner.iter().enumerate() {
+        body.push(element(e));
+        if piece(e) == Piece::Token(&TokenKind::SemiColon) {
+            body.push(line());
+            if matches!(inner.get(i + 1), Some(SyntaxElement::Node(n)) if blank_before(n)) {
+                body.push(Doc::HardLine);
+            }
+        }
+    }
+    group(vec![element(open), nest(body), line(), element(close)])
+}
+
+// The arms of a match, each on a line of its own
+fn match_expr(children: &[SyntaxElement]) -> Doc {
+    let open = children
+        .iter()
+        .position(|e| piece(e) == Piece::Token(&TokenKind::LBrace))
+        .unwrap_or(children.len());
+    let (head, arms) = children.split_at(open);
+    let mut docs = vec![inline(head)];
+    if let [open, inner @ .., close] = arms {
+        docs.push(Doc::Space);
+        docs.push(element(open));
+        if !inner.is_empty() {
+            let mut body = vec![Doc::HardLine];
+            for e in inner {
+                body.push(element(e));
+                if piece(e) == Piece::Token(&TokenKind::Comma) {
+                    body.push(Doc::HardLine);
+                }
+            }
+            docs.push(nest(body));
+            docs.push(Doc::HardLine);
+        }
+        docs.push(element(close));
+    }
+    Doc::Concat(docs)
+}
+
+// Items on lines of their own, with the blank lines between them kept
+fn program(children: &[SyntaxElement]) -> Doc {
+    let mut docs = Vec::new();
+    for e in children {
+        let (starts_line, blank) = match e {
+            SyntaxElement::Node(n) => (true, blank_before(n)),
+            // the EOF with the comments at the end, or doc comments there
+            SyntaxElement::Token(t) => {
+                let blank = t
+                    .leading()
+                    .into_iter()
+                    .take_while(|(kind, _)| !is_comment(*kind))
+                    .filter(|(kind, _)| *kind == TriviaKind::Newline)
+                    .count()
+                    >= 2;
+                (has_comments(t), blank)
+            }
+        };
+        if starts_line && !docs.is_empty() {
+            docs.push(Doc::HardLine);
+            if blank {
+                docs.push(Doc::HardLine);
+            }
+        }
+        docs.push(element(e));
+    }
+    Doc::Concat(docs)
+}
+
+// Formats `source` in the layout of `lung fmt`, breaking the lines which
+// would be longer than `width`. A source with errors is not formatted.
+pub fn format(source: &str, width: usize) -> Result<String, Vec<Diagnostic>> {
+    let (tree, _, errors) = cst::parse(source);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let mut out = Printer::print(&node(&tree), width);
+    let len = out.trim_end().len();
+    out.truncate(len);
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
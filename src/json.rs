@@ -0,0 +1,311 @@
+// JSON values, as much as the language server needs to read and write
+// its JSON-RPC messages.
+use std::fmt;
+
+#[cfg(test)]
+mod json_test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let v = Json::parse(
+            r#" {"id": 1, "params": {"text": "a\n\"b\" é😀", "ok": [true, null, -2.5e1]}} "#,
+        )
+        .unwrap();
+        assert_eq!(v.get("id").as_i64(), Some(1));
+        assert_eq!(v.get("params").get("text").as_str(), Some("a\n\"b\" é😀"));
+        let list = v.get("params").get("ok");
+        assert_eq!(list.as_array().unwrap().len(), 3);
+        assert_eq!(list.as_array().unwrap()[2], Json::Num(-25.0));
+        assert_eq!(v.get("missing"), &Json::Null);
+        assert!(Json::parse("{\"a\": }").is_err());
+        assert!(Json::parse("[1, 2").is_err());
+        assert!(Json::parse("1 2").is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        let v = Json::object(vec![
+            ("id", Json::from(3)),
+            ("text", Json::from("tab\t\"q\"\u{1}")),
+            (
+                "list",
+                Json::Array(vec![Json::Bool(false), Json::Null, Json::Num(0.5)]),
+            ),
+        ]);
+        let s = v.to_string();
+        assert_eq!(
+            s,
+            r#"{"id":3,"text":"tab\t\"q\"\u0001","list":[false,null,0.5]}"#
+        );
+        assert_eq!(Json::parse(&s).unwrap(), v);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+    Array(Vec<Json>),
+    // members in order, as written
+    Object(Vec<(String, Json)>),
+}
+
+const NULL: Json = Json::Null;
+
+impl Json {
+    pub fn object(members: Vec<(&str, Json)>) -> Json {
+        Json::Object(
+            members
+                .into_iter()
+                .map(|(k, v)| (String::from(k), v))
+                .collect(),
+        )
+    }
+
+    // The member `key` of an object, null when there is none.
+    pub fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .find(|(k, _)| k == key)
+                .map_or(&NULL, |(_, v)| v),
+            _ => &NULL,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Num(n) if n.fract() == 0.0 => Some(*n as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Json>> {
+        match self {
+            Json::Array(elems) => Some(elems),
+            _ => None,
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Json, String> {
+        let mut p = JsonParser {
+            chars: s.chars().collect(),
+            pos: 0,
+        };
+        let v = p.value()?;
+        p.skip_white();
+        match p.chars.get(p.pos) {
+            None => Ok(v),
+            Some(c) => Err(format!("unexpected `{}` after the value", c)),
+        }
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Json {
+        Json::Str(String::from(s))
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Json {
+        Json::Str(s)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Json {
+        Json::Num(n as f64)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Json {
+        Json::Bool(b)
+    }
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl JsonParser {
+    fn skip_white(&mut self) {
+        while matches!(self.chars.get(self.pos), Some(c) if c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn next(&mut self) -> Result<char, String> {
+        let c = self.chars.get(self.pos).copied();
+        self.pos += 1;
+        c.ok_or_else(|| String::from("unexpected end of input"))
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), String> {
+        for w in word.chars() {
+            if self.next()? != w {
+                return Err(format!("expected `{}`", word));
+            }
+        }
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_white();
+        match self.chars.get(self.pos) {
+            Some('n') => self.expect("null").map(|_| Json::Null),
+            Some('t') => self.expect("true").map(|_| Json::Bool(true)),
+            Some('f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some('"') => self.string().map(Json::Str),
+            Some('[') => {
+                self.pos += 1;
+                let mut elems = Vec::new();
+                self.skip_white();
+                if self.chars.get(self.pos) == Some(&']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(elems));
+                }
+                loop {
+                    elems.push(self.value()?);
+                    self.skip_white();
+                    match self.next()? {
+                        ',' => (),
+                        ']' => return Ok(Json::Array(elems)),
+                        c => return Err(format!("unexpected `{}` in an array", c)),
+                    }
+                }
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                self.skip_white();
+                if self.chars.get(self.pos) == Some(&'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.skip_white();
+                    let key = self.string()?;
+                    self.skip_white();
+                    self.expect(":")?;
+                    members.push((key, self.value()?));
+                    self.skip_white();
+                    match self.next()? {
+                        ',' => (),
+                        '}' => return Ok(Json::Object(members)),
+                        c => return Err(format!("unexpected `{}` in an object", c)),
+                    }
+                }
+            }
+            Some(c) if *c == '-' || c.is_ascii_digit() => {
+                let start = self.pos;
+                while matches!(self.chars.get(self.pos), Some(c) if "+-.eE".contains(*c) || c.is_ascii_digit())
+                {
+                    self.pos += 1;
+                }
+                let text: String = self.chars[start..self.pos].iter().collect();
+                text.parse()
+                    .map(Json::Num)
+                    .map_err(|_| format!("invalid number `{}`", text))
+            }
+            Some(c) => Err(format!("unexpected `{}`", c)),
+            None => Err(String::from("unexpected end of input")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect("\"")?;
+        let mut s = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Ok(s),
+                '\\' => match self.next()? {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    'r' => s.push('\r'),
+                    'b' => s.push('\u{8}'),
+                    'f' => s.push('\u{c}'),
+                    'u' => {
+                        let mut unit = self.hex()?;
+                        // a surrogate pair for a char outside the BMP
+                        if (0xd800..0xdc00).contains(&unit) {
+                            self.expect("\\u")?;
+                            let low = self.hex()?;
+                            unit = 0x10000 + ((unit - 0xd800) << 10) + (low.wrapping_sub(0xdc00));
+                        }
+                        s.push(std::char::from_u32(unit).ok_or("invalid \\u escape")?);
+                    }
+                    c @ ('"' | '\\' | '/') => s.push(c),
+                    c => return Err(format!("invalid escape `\\{}`", c)),
+                },
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32, String> {
+        let mut n = 0;
+        for _ in 0..4 {
+            let d = self.next()?.to_digit(16).ok_or("invalid \\u escape")?;
+            n = n * 16 + d;
+        }
+        Ok(n)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Num(n) => write!(f, "{}", n),
+            Json::Str(s) => {
+                write!(f, "\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\t' => write!(f, "\\t")?,
+                        c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Json::Array(elems) => {
+                write!(f, "[")?;
+                for (i, e) in elems.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", e)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", Json::Str(k.clone()), v)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
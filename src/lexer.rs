@@ -2,56 +2,337 @@ use std::fs::File;
 use std::io::prelude::*;
 use std::str::Chars;
 
-use crate::syntax::{Token, TokenInfo, TokenKind};
+use crate::diagnostic::Diagnostic;
+use crate::syntax::{LosslessToken, Token, TokenInfo, TokenKind, Trivia, TriviaKind};
 
 #[cfg(test)]
+#[allow(clippy::items_after_test_module)]
 mod lexer_test {
     use super::*;
 
-    fn dump(tokens: Vec<Token>) {
-        for t in &tokens {
-            print!("{:?} ", t);
-        }
-        print!("\n");
+    fn kinds(mut lexer: Lexer) -> Vec<TokenKind> {
+        lexer.lex().unwrap().into_iter().map(|t| t.kind).collect()
     }
 
     #[test]
     fn test1() {
-        let mut lexer = Lexer::from_file("src/test/test.txt").unwrap();
-        // dump(lexer.lex().unwrap());
-        // test with --nocapture arg and see output
+        let kinds = kinds(Lexer::from_file("src/test/test.txt").unwrap());
+        use TokenKind::*;
+        let num = |n: &str| Num(String::from(n));
+        let ident = |n: &str| Ident(String::from(n));
+        assert_eq!(
+            kinds,
+            vec![
+                Func,
+                FuncAnon,
+                LParen,
+                RParen,
+                LBrace,
+                RBrace,
+                num("123"),
+                ident("abc"),
+                ident("num123"),
+                num("123"),
+                num("222")
+            ]
+        );
+    }
+    #[test]
+    fn test_operators() {
+        let mut lexer = Lexer::from_string(String::from(
+            "-> - >= > == != ! && || + * / % <= < = => _ a_1",
+        ));
+        let kinds: Vec<TokenKind> = lexer.lex().unwrap().into_iter().map(|t| t.kind).collect();
+        use TokenKind::*;
+        assert_eq!(
+            kinds,
+            vec![
+                Arrow,
+                Minus,
+                Ge,
+                Gt,
+                EqEq,
+                NotEq,
+                Bang,
+                AndAnd,
+                OrOr,
+                Plus,
+                Star,
+                Slash,
+                Percent,
+                Le,
+                Lt,
+                Assign,
+                FatArrow,
+                Underscore,
+                Ident(String::from("a_1"))
+            ]
+        );
+        assert!(Lexer::from_string(String::from("a & b")).lex().is_err());
+    }
+
+    #[test]
+    fn test_string() {
+        let mut lexer = Lexer::from_string(String::from(
+            r#""a\tb\n\\\"\u{48}\u{1F600}" 'x' '\'' '\u{e9}' "" String Char"#,
+        ));
+        let kinds: Vec<TokenKind> = lexer.lex().unwrap().into_iter().map(|t| t.kind).collect();
+        use TokenKind::*;
+        assert_eq!(
+            kinds,
+            vec![
+                Str(String::from("a\tb\n\\\"H\u{1F600}")),
+                Char('x'),
+                Char('\''),
+                Char('é'),
+                Str(String::new()),
+                StringType,
+                CharType
+            ]
+        );
+        let tokens = Lexer::from_string(String::from("\"a\nb\"")).lex().unwrap();
+        assert_eq!(tokens[0].info.to_string(), "1:1-2:2");
+        // an unterminated string is reported at its opening quote
+        let d = Lexer::from_string(String::from("f(1, \"abc);\n"))
+            .lex()
+            .unwrap_err();
+        assert_eq!(d.code, "E0004");
+        assert_eq!(d.primary.as_ref().unwrap().to_string(), "1:6-1:6");
+        let d = Lexer::from_string(String::from(r#" "a\qb" "#))
+            .lex()
+            .unwrap_err();
+        assert_eq!(d.code, "E0003");
+        assert_eq!(d.primary.as_ref().unwrap().to_string(), "1:4-1:5");
+        let errors = [
+            (r#""\u{110000}""#, "E0003"),
+            (r#""\u{}""#, "E0003"),
+            (r#""\u41""#, "E0003"),
+            ("''", "E0005"),
+            ("'ab'", "E0005"),
+            ("'a", "E0005"),
+        ];
+        for (src, code) in errors.iter() {
+            let d = Lexer::from_string(String::from(*src)).lex().unwrap_err();
+            assert_eq!(d.code, *code, "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_number() {
+        let mut lexer = Lexer::from_string(String::from(
+            "0xff_u8 1_000 0b10i64 t.0.1 3.25 1e-3 2.5E+10f64 0xe-1 1.x",
+        ));
+        let kinds: Vec<TokenKind> = lexer.lex().unwrap().into_iter().map(|t| t.kind).collect();
+        use TokenKind::*;
+        assert_eq!(
+            kinds,
+            vec![
+                Num(String::from("0xff_u8")),
+                Num(String::from("1_000")),
+                Num(String::from("0b10i64")),
+                Ident(String::from("t")),
+                Dot,
+                Num(String::from("0")),
+                Dot,
+                Num(String::from("1")),
+                Num(String::from("3.25")),
+                Num(String::from("1e-3")),
+                Num(String::from("2.5E+10f64")),
+                Num(String::from("0xe")),
+                Minus,
+                Num(String::from("1")),
+                Num(String::from("1")),
+                Dot,
+                Ident(String::from("x"))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_comment() {
+        let src = "a // b\n/* c /* d */ e */ f //// g\n/// h\n///i\n1 / 2";
+        let tokens = Lexer::from_string(String::from(src)).lex().unwrap();
+        let kinds: Vec<TokenKind> = tokens.iter().map(|t| t.kind.clone()).collect();
+        use TokenKind::*;
+        assert_eq!(
+            kinds,
+            vec![
+                Ident(String::from("a")),
+                Ident(String::from("f")),
+                DocComment(String::from("h")),
+                DocComment(String::from("i")),
+                Num(String::from("1")),
+                Slash,
+                Num(String::from("2"))
+            ]
+        );
+        assert_eq!(tokens[2].info.to_string(), "3:1-3:5");
+        let d = Lexer::from_string(String::from("a /* b /* c */\n"))
+            .lex()
+            .unwrap_err();
+        assert_eq!(d.code, "E0006");
+        assert_eq!(d.primary.as_ref().unwrap().to_string(), "1:3-1:4");
+        assert!(Lexer::from_string(String::from("a // b")).lex().is_ok());
+    }
+
+    #[test]
+    fn test_recovery() {
+        let src = "a @ b & c\n\"x\\qy\" 'ab' d\n/* e";
+        let (tokens, errors) = Lexer::from_string(String::from(src)).lex_partial();
+        let tokens: Vec<String> = tokens
+            .iter()
+            .map(|t| format!("{} {:?}", t.info, t.kind))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                "1:1-1:1 Ident(\"a\")",
+                "1:3-1:3 Error",
+                "1:5-1:5 Ident(\"b\")",
+                "1:7-1:7 Error",
+                "1:9-1:9 Ident(\"c\")",
+                "2:1-2:6 Error",
+                "2:8-2:11 Error",
+                "2:13-2:13 Ident(\"d\")",
+                "3:1-3:4 Error",
+            ]
+        );
+        let errors: Vec<String> = errors
+            .iter()
+            .map(|d| format!("{} {}", d.code, d.primary.as_ref().unwrap()))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "E0001 1:3-1:3",
+                "E0002 1:7-1:7",
+                "E0003 2:3-2:4",
+                "E0005 2:8-2:10",
+                "E0006 3:1-3:2"
+            ]
+        );
+        // the parser does not report again the end of the input an Error
+        // token runs up to
+        let src = "fn main() { \"abc\n  1\n}\n";
+        let (_, _, errors) = crate::cst::parse(src);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, "E0004");
+    }
+
+    #[test]
+    fn test_lossless() {
+        let src = "a  // c\n/* d */ b\n'é' x";
+        let (tokens, _) = Lexer::from_string(String::from(src)).lex_lossless();
+        use TriviaKind::*;
+        let trivia = |ts: &[Trivia]| -> Vec<(TriviaKind, String)> {
+            ts.iter()
+                .map(|t| (t.kind, String::from(&src[t.range.clone()])))
+                .collect()
+        };
+        assert_eq!(tokens[0].range, 0..1);
+        assert_eq!(tokens[0].leading, vec![]);
+        assert_eq!(
+            trivia(&tokens[0].trailing),
+            vec![
+                (Whitespace, String::from("  ")),
+                (LineComment, String::from("// c"))
+            ]
+        );
+        assert_eq!(
+            trivia(&tokens[1].leading),
+            vec![
+                (Newline, String::from("\n")),
+                (BlockComment, String::from("/* d */")),
+                (Whitespace, String::from(" "))
+            ]
+        );
+        assert_eq!(tokens[1].range, 16..17);
+        assert_eq!(tokens[1].trailing, vec![]);
+        assert_eq!(tokens[2].range, 18..22);
+        assert_eq!(tokens[3].range, 23..24);
+        assert_eq!(tokens[4].token.kind, TokenKind::EOF);
+        assert_eq!(tokens[4].range, 24..24);
+        // everything is in a token or in trivia
+        let mut text = String::new();
+        for t in &tokens {
+            let ranges = t.leading.iter().map(|t| t.range.clone());
+            let ranges = ranges.chain(Some(t.range.clone()));
+            for r in ranges.chain(t.trailing.iter().map(|t| t.range.clone())) {
+                text.push_str(&src[r]);
+            }
+        }
+        assert_eq!(text, src);
     }
+
     #[test]
     fn test2() {
-        let mut lexer = Lexer::from_file("src/test/test_parser.txt").unwrap();
-        // dump(lexer.lex().unwrap());
-        // test with --nocapture arg and see output
+        let kinds = kinds(Lexer::from_file("src/test/test_parser.txt").unwrap());
+        use TokenKind::*;
+        let ident = |n: &str| Ident(String::from(n));
+        assert_eq!(
+            kinds[..10],
+            [
+                Type,
+                ident("Num"),
+                Assign,
+                I32,
+                SemiColon,
+                Let,
+                ident("zero"),
+                Colon,
+                ident("Num"),
+                Assign
+            ]
+        );
+        assert_eq!(kinds.len(), 42);
+        assert_eq!(kinds.last(), Some(&RBrace));
     }
 }
 
 struct Eater<'a> {
-    input: &'a str,
     input_iter: std::iter::Peekable<Chars<'a>>,
     was_newline: bool,
     cc: char,
+    // whether the last token was a `.`, after which a number is a tuple
+    // index: `t.0.1` is not `t` followed by `.` and `0.1`
+    after_dot: bool,
+    // where the last eaten char was, where a failed token ends
+    last: TokenInfo,
+    // lexical errors recovered from so far
+    errors: Vec<Diagnostic>,
+    // byte offsets of the current char and of the char after it
+    offset: usize,
+    end: usize,
+    // where the text not yet put in a token or a trivia starts
+    mark: usize,
+    // trivia since the last token
+    trivia: Vec<Trivia>,
 
     row: usize,
     col: usize,
 }
 
 impl<'a> Eater<'a> {
-    pub fn from_str(input: &str) -> Eater {
+    pub fn from_str(input: &str) -> Eater<'_> {
         Eater {
-            input,
             input_iter: input.chars().peekable(),
             was_newline: false,
             cc: ' ',
+            after_dot: false,
+            last: TokenInfo::default(),
+            errors: Vec::new(),
+            offset: 0,
+            end: 0,
+            mark: 0,
+            trivia: Vec::new(),
             row: 1,
             col: 0,
         }
     }
 
     fn next_char(&mut self) {
+        self.last = self.here();
+        self.offset = self.end;
         if self.was_newline {
             self.col = 0;
             self.row += 1;
@@ -59,26 +340,51 @@ impl<'a> Eater<'a> {
         }
         match self.input_iter.next() {
             Some('\n') => {
+                self.end += 1;
                 self.was_newline = true;
                 self.col += 1;
                 self.cc = '\n';
             }
             Some(c) => {
+                self.end += c.len_utf8();
                 self.col += 1;
                 self.cc = c;
             }
 
-            None => self.cc = '\0',
+            // past the end, so that the last token ends where it should
+            None => {
+                self.col += 1;
+                self.cc = '\0';
+            }
+        }
+    }
+
+    // the text from the mark up to the current char, which moves the mark
+    fn take_range(&mut self) -> std::ops::Range<usize> {
+        let range = self.mark..self.offset;
+        self.mark = self.offset;
+        range
+    }
+
+    fn push_trivia(&mut self, kind: TriviaKind) {
+        let range = self.take_range();
+        if !range.is_empty() {
+            self.trivia.push(Trivia { kind, range });
         }
     }
 
+    // Newlines are trivia of their own, where trailing trivia ends.
     fn skip_white(&mut self) {
-        loop {
-            if !self.cc.is_whitespace() {
-                break;
+        while self.cc.is_whitespace() {
+            if self.cc == '\n' {
+                self.push_trivia(TriviaKind::Whitespace);
+                self.next_char();
+                self.push_trivia(TriviaKind::Newline);
+            } else {
+                self.next_char();
             }
-            self.next_char();
         }
+        self.push_trivia(TriviaKind::Whitespace);
     }
 
     fn eat_alnum_dump(&mut self) -> (String, usize, usize) {
@@ -86,7 +392,7 @@ impl<'a> Eater<'a> {
         let e_row;
         let e_col;
         loop {
-            if !self.cc.is_alphanumeric() {
+            if !self.cc.is_alphanumeric() && self.cc != '_' {
                 e_row = self.row;
                 e_col = self.col - 1;
                 break;
@@ -97,26 +403,325 @@ impl<'a> Eater<'a> {
         (tmp, e_row, e_col)
     }
 
-    fn eat_num_dump(&mut self) -> (String, usize, usize) {
+    // The rest of a number literal starting with `first`, with its prefix,
+    // separators, fraction, exponent and suffix as in `0xff_u8` or
+    // `1.5e-3`. The parser checks the digits.
+    fn eat_num_dump(&mut self, first: char) -> (String, usize, usize) {
+        let decimal = !(first == '0' && matches!(self.cc, 'x' | 'o' | 'b'));
         let mut tmp = String::new();
         let e_row;
         let e_col;
         loop {
-            if !self.cc.is_numeric() {
+            let fraction = self.cc == '.'
+                && !self.after_dot
+                && matches!(self.input_iter.peek(), Some(c) if c.is_ascii_digit());
+            let exponent = matches!(self.cc, 'e' | 'E') && decimal;
+            if fraction && !tmp.contains('.') && !tmp.contains(['e', 'E']) {
+                tmp.push(self.cc);
+                self.next_char();
+                continue;
+            }
+            if !self.cc.is_ascii_alphanumeric() && self.cc != '_' {
                 e_row = self.row;
                 e_col = self.col - 1;
                 break;
             }
             tmp.push(self.cc);
             self.next_char();
+            if exponent && matches!(self.cc, '+' | '-') {
+                tmp.push(self.cc);
+                self.next_char();
+            }
         }
         (tmp, e_row, e_col)
     }
 
-    pub fn eat_token_dump(&mut self) -> Result<Token, &str> {
-        self.skip_white();
+    fn eat_single(&mut self, kind: TokenKind) -> Token {
+        let info = TokenInfo {
+            s_col: self.col,
+            s_row: self.row,
+            e_col: self.col,
+            e_row: self.row,
+        };
+        self.next_char();
+        Token { kind, info }
+    }
+
+    // Eats a symbol which is one of `doubles` when the current char is
+    // followed by its char (like `<=`) and `single` otherwise (like `<`).
+    // When `single` is None the char is only valid as the first half of a
+    // double.
+    fn eat_symbol(
+        &mut self,
+        single: Option<TokenKind>,
+        doubles: &[(char, TokenKind)],
+    ) -> Result<Token, Diagnostic> {
+        let (s_col, s_row) = (self.col, self.row);
+        let first = self.cc;
+        self.next_char();
+        let mut info = TokenInfo {
+            s_col,
+            s_row,
+            e_col: s_col,
+            e_row: s_row,
+        };
+        if let Some((_, double)) = doubles.iter().find(|(c, _)| *c == self.cc) {
+            info.e_col = self.col;
+            info.e_row = self.row;
+            self.next_char();
+            return Ok(Token {
+                kind: double.clone(),
+                info,
+            });
+        }
+        match single {
+            Some(kind) => Ok(Token { kind, info }),
+            None => Err(Diagnostic::error(
+                "E0002",
+                &format!("Expected `{}` after `{}`", doubles[0].0, first),
+            )
+            .with_primary(info)),
+        }
+    }
+
+    fn here(&self) -> TokenInfo {
+        TokenInfo {
+            s_col: self.col,
+            s_row: self.row,
+            e_col: self.col,
+            e_row: self.row,
+        }
+    }
+
+    // A string literal, which may span lines. Unterminated, it is reported
+    // at its opening quote since the rest of the file went into it. A bad
+    // escape is reported once the string is closed, so that its rest is
+    // not read as code.
+    fn eat_string(&mut self) -> Result<Token, Diagnostic> {
+        let open = self.here();
+        self.next_char();
+        let mut val = String::new();
+        let mut error = None;
+        loop {
+            match self.cc {
+                '"' => break,
+                '\0' => {
+                    return Err(Diagnostic::error("E0004", "Unterminated string literal")
+                        .with_primary(open)
+                        .with_note("the string is never closed by a `\"`"))
+                }
+                '\\' => match self.eat_escape() {
+                    Ok(c) => val.push(c),
+                    Err(d) => {
+                        error.get_or_insert(d);
+                    }
+                },
+                c => {
+                    val.push(c);
+                    self.next_char();
+                }
+            }
+        }
+        let info = open.to(&self.here());
+        self.next_char();
+        if let Some(d) = error {
+            return Err(d);
+        }
+        Ok(Token {
+            kind: TokenKind::Str(val),
+            info,
+        })
+    }
+
+    // A char literal. A bad one is eaten up to its closing `'` on the same
+    // line, if any.
+    fn eat_char(&mut self) -> Result<Token, Diagnostic> {
+        let open = self.here();
+        self.next_char();
+        let val = match self.cc {
+            '\\' => match self.eat_escape() {
+                Ok(c) => Some(c),
+                Err(d) => {
+                    self.skip_char_rest();
+                    return Err(d);
+                }
+            },
+            '\'' | '\n' | '\0' => None,
+            c => {
+                self.next_char();
+                Some(c)
+            }
+        };
+        match val {
+            Some(val) if self.cc == '\'' => {
+                let info = open.to(&self.here());
+                self.next_char();
+                Ok(Token {
+                    kind: TokenKind::Char(val),
+                    info,
+                })
+            }
+            _ => {
+                let d = Diagnostic::error("E0005", "Invalid char literal")
+                    .with_primary(open.to(&self.here()))
+                    .with_note("a char literal is a single character between `'`, like `'a'`");
+                self.skip_char_rest();
+                Err(d)
+            }
+        }
+    }
+
+    fn skip_char_rest(&mut self) {
+        while !matches!(self.cc, '\'' | '\n' | '\0') {
+            self.next_char();
+        }
+        if self.cc == '\'' {
+            self.next_char();
+        }
+    }
+
+    // `\n`, `\t`, `\r`, `\0`, `\\`, `\"`, `\'` or `\u{..}` with the code point
+    // in hex
+    fn eat_escape(&mut self) -> Result<char, Diagnostic> {
+        let start = self.here();
+        self.next_char();
+        let c = match self.cc {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            '0' => Some('\0'),
+            '\\' => Some('\\'),
+            '"' => Some('"'),
+            '\'' => Some('\''),
+            'u' => {
+                self.next_char();
+                let mut hex = String::new();
+                if self.cc == '{' {
+                    self.next_char();
+                    while self.cc.is_ascii_hexdigit() && hex.len() < 6 {
+                        hex.push(self.cc);
+                        self.next_char();
+                    }
+                }
+                match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                    Some(c) if self.cc == '}' => Some(c),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        let info = start.to(&self.here());
+        match c {
+            Some(c) => {
+                self.next_char();
+                Ok(c)
+            }
+            None => Err(Diagnostic::error("E0003", "Invalid escape sequence")
+                .with_primary(info)
+                .with_note(
+                    "escapes are \\n, \\t, \\r, \\0, \\\\, \\\", \\' and \\u{..} with up to 6 hex digits",
+                )),
+        }
+    }
+
+    // Skips whitespace and comments up to the next token. A doc comment is
+    // returned as a token of its own.
+    fn skip_trivia(&mut self) -> Result<Option<Token>, Diagnostic> {
+        loop {
+            self.skip_white();
+            if self.cc != '/' {
+                return Ok(None);
+            }
+            match self.input_iter.peek() {
+                Some('/') => {
+                    let start = self.here();
+                    self.next_char();
+                    self.next_char();
+                    // `///` but not `////`
+                    let doc = self.cc == '/' && self.input_iter.peek() != Some(&'/');
+                    if doc {
+                        self.next_char();
+                    }
+                    let mut text = String::new();
+                    let mut end = start.clone();
+                    while self.cc != '\n' && self.cc != '\0' {
+                        text.push(self.cc);
+                        end = self.here();
+                        self.next_char();
+                    }
+                    if doc {
+                        let text = text.strip_prefix(' ').unwrap_or(&text);
+                        return Ok(Some(Token {
+                            kind: TokenKind::DocComment(String::from(text)),
+                            info: start.to(&end),
+                        }));
+                    }
+                    self.push_trivia(TriviaKind::LineComment);
+                }
+                Some('*') => {
+                    self.skip_block_comment()?;
+                    self.push_trivia(TriviaKind::BlockComment);
+                }
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    // `/* .. */`, in which block comments nest
+    fn skip_block_comment(&mut self) -> Result<(), Diagnostic> {
+        let start = self.here();
+        self.next_char();
+        let open = start.to(&self.here());
+        self.next_char();
+        let mut depth = 1;
+        while depth > 0 {
+            match (self.cc, self.input_iter.peek()) {
+                ('\0', _) => {
+                    return Err(Diagnostic::error("E0006", "Unterminated block comment")
+                        .with_primary(open)
+                        .with_note("the comment is never closed by a `*/`"))
+                }
+                ('/', Some('*')) => {
+                    depth += 1;
+                    self.next_char();
+                }
+                ('*', Some('/')) => {
+                    depth -= 1;
+                    self.next_char();
+                }
+                _ => (),
+            }
+            self.next_char();
+        }
+        Ok(())
+    }
+
+    // The next token. When there is none to make of the chars ahead, they are
+    // eaten into an Error token and the error is kept to go on after.
+    fn eat_token(&mut self) -> Token {
+        let token = match self.skip_trivia() {
+            Ok(Some(doc)) => return doc,
+            Ok(None) => {
+                let start = self.here();
+                self.eat_token_dump().map_err(|d| (start, d))
+            }
+            Err(d) => Err((d.primary.clone().unwrap(), d)),
+        };
+        match token {
+            Ok(t) => t,
+            Err((start, d)) => {
+                self.errors.push(d);
+                Token {
+                    kind: TokenKind::Error,
+                    info: start.to(&self.last),
+                }
+            }
+        }
+    }
+
+    fn eat_token_dump(&mut self) -> Result<Token, Diagnostic> {
         match self.cc {
-            c if c.is_alphabetic() => {
+            c if c.is_alphabetic() || c == '_' => {
                 let mut id = String::new();
                 id.push(c);
                 let s_col = self.col;
@@ -136,19 +741,38 @@ impl<'a> Eater<'a> {
                     "Fn" => TokenKind::FuncType,
                     "unit" => TokenKind::UnitVal,
                     "Unit" => TokenKind::UnitType,
+                    "true" => TokenKind::True,
+                    "false" => TokenKind::False,
+                    "Bool" => TokenKind::BoolType,
+                    "if" => TokenKind::If,
+                    "else" => TokenKind::Else,
+                    "let" => TokenKind::Let,
+                    "type" => TokenKind::Type,
+                    "import" => TokenKind::Import,
+                    "struct" => TokenKind::Struct,
+                    "enum" => TokenKind::Enum,
+                    "match" => TokenKind::Match,
+                    "as" => TokenKind::As,
+                    "_" => TokenKind::Underscore,
                     "I32" => TokenKind::I32,
+                    "I64" => TokenKind::I64,
+                    "U8" => TokenKind::U8,
+                    "U32" => TokenKind::U32,
+                    "F64" => TokenKind::F64,
+                    "String" => TokenKind::StringType,
+                    "Char" => TokenKind::CharType,
                     _ => TokenKind::Ident(id),
                 };
                 Ok(Token { kind, info })
             }
 
-            c if c.is_numeric() => {
+            c if c.is_ascii_digit() => {
                 let s_col = self.col;
                 let s_row = self.row;
                 let mut num = String::new();
                 num.push(c);
                 self.next_char();
-                let (rest, e_row, e_col) = self.eat_num_dump();
+                let (rest, e_row, e_col) = self.eat_num_dump(c);
                 let info = TokenInfo {
                     s_col,
                     s_row,
@@ -257,22 +881,23 @@ impl<'a> Eater<'a> {
                     info,
                 })
             }
-            '-' => {
-                let (s_col,s_row) = (self.col,self.row);
-                self.next_char();
-                let e_col;
-                let e_row;
-                let kind = match self.cc {
-                    '>' => {
-                        e_col = self.col;
-                        e_row = self.row;
-                        self.next_char();
-                        TokenKind::Arrow
-                    }
-                    _ => return Err("いやARROWじゃないんかい！（痛烈な突っ込み）")
-                };
-                Ok(Token{kind,info:TokenInfo{s_col,s_row,e_col,e_row}})
-            }
+            '"' => self.eat_string(),
+            '\'' => self.eat_char(),
+            '.' => Ok(self.eat_single(TokenKind::Dot)),
+            '-' => self.eat_symbol(Some(TokenKind::Minus), &[('>', TokenKind::Arrow)]),
+            '+' => Ok(self.eat_single(TokenKind::Plus)),
+            '*' => Ok(self.eat_single(TokenKind::Star)),
+            '/' => Ok(self.eat_single(TokenKind::Slash)),
+            '%' => Ok(self.eat_single(TokenKind::Percent)),
+            '=' => self.eat_symbol(
+                Some(TokenKind::Assign),
+                &[('=', TokenKind::EqEq), ('>', TokenKind::FatArrow)],
+            ),
+            '!' => self.eat_symbol(Some(TokenKind::Bang), &[('=', TokenKind::NotEq)]),
+            '<' => self.eat_symbol(Some(TokenKind::Lt), &[('=', TokenKind::Le)]),
+            '>' => self.eat_symbol(Some(TokenKind::Gt), &[('=', TokenKind::Ge)]),
+            '&' => self.eat_symbol(None, &[('&', TokenKind::AndAnd)]),
+            '|' => self.eat_symbol(None, &[('|', TokenKind::OrOr)]),
 
             '\0' => {
                 let info = TokenInfo {
@@ -287,7 +912,19 @@ impl<'a> Eater<'a> {
                     info,
                 })
             }
-            _ => Err("Error: found unrecognized charactor"),
+            c => {
+                let info = TokenInfo {
+                    s_col: self.col,
+                    s_row: self.row,
+                    e_col: self.col,
+                    e_row: self.row,
+                };
+                self.next_char();
+                Err(
+                    Diagnostic::error("E0001", &format!("Found unrecognized character `{}`", c))
+                        .with_primary(info),
+                )
+            }
         }
     }
 }
@@ -304,21 +941,63 @@ impl Lexer {
         Ok(Lexer { buffer: tmp_str })
     }
 
-    pub fn lex(&mut self) -> Result<Vec<Token>, &str> {
-        let mut tokens = Vec::new();
+    pub fn from_string(buffer: String) -> Lexer {
+        Lexer { buffer }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.buffer
+    }
+
+    // The tokens of the whole input, failing with the first lexical error.
+    pub fn lex(&mut self) -> Result<Vec<Token>, Diagnostic> {
+        let (tokens, mut errors) = self.lex_partial();
+        match errors.is_empty() {
+            true => Ok(tokens),
+            false => Err(errors.remove(0)),
+        }
+    }
+
+    // The tokens of the whole input, going on after lexical errors. Each
+    // error leaves an Error token over the chars it was about.
+    pub fn lex_partial(&mut self) -> (Vec<Token>, Vec<Diagnostic>) {
+        let (tokens, errors) = self.lex_lossless();
+        let tokens = tokens
+            .into_iter()
+            .map(|t| t.token)
+            .filter(|t| t.kind != TokenKind::EOF)
+            .collect();
+        (tokens, errors)
+    }
+
+    // Like lex_partial, but with the trivia around the tokens and an EOF
+    // token at the end, which holds the trivia after the last token.
+    pub fn lex_lossless(&mut self) -> (Vec<LosslessToken>, Vec<Diagnostic>) {
+        let mut tokens: Vec<LosslessToken> = Vec::new();
         let mut eater = Eater::from_str(self.buffer.as_str());
         loop {
-            match eater.eat_token_dump() {
-                Ok(t) => match t.kind {
-                    TokenKind::EOF => break,
-                    _ => tokens.push(t),
-                },
-                Err(s) => {
-                    println!("{}", s);
-                    return Err("Failed to lex");
-                }
+            let token = eater.eat_token();
+            eater.after_dot = token.kind == TokenKind::Dot;
+            let range = eater.take_range();
+            let mut leading = std::mem::take(&mut eater.trivia);
+            if let Some(prev) = tokens.last_mut() {
+                let end = leading
+                    .iter()
+                    .position(|t| t.kind == TriviaKind::Newline)
+                    .unwrap_or(leading.len());
+                prev.trailing = leading.drain(..end).collect();
+            }
+            let eof = token.kind == TokenKind::EOF;
+            tokens.push(LosslessToken {
+                token,
+                range,
+                leading,
+                trailing: Vec::new(),
+            });
+            if eof {
+                break;
             }
         }
-        Ok(tokens)
+        (tokens, eater.errors)
     }
 }
@@ -0,0 +1,752 @@
+// Lowering of the concrete syntax tree into the AST, which is only ever
+// built this way. The parser groups the tokens of every construct into a
+// node, so the AST is read off the nodes and the tokens they hold, while
+// the spans of its nodes are found from the offsets of the tokens.
+//
+// Where the parser recovered from a syntax error the tree has an Error
+// node, or no node at all when nothing could be read, and the AST has an
+// error in its place: an Item::Error, an Expr::Error, or a wildcard
+// pattern for a function argument or a match arm.
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::cst::{SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken};
+use crate::parser::parse_num;
+use crate::syntax::*;
+use crate::type_def::*;
+
+#[cfg(test)]
+#[allow(clippy::items_after_test_module)]
+mod lower_test {
+    use super::*;
+    use crate::cst;
+
+    fn spans(exprs: &[Box<Expr>]) -> Vec<String> {
+        exprs
+            .iter()
+            .map(|e| match **e {
+                Expr::Error { ref info } => format!("error {}", info),
+                ref e => e.info().to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_lower() {
+        // what the parser could not read is an error in its place, and
+        // the tokens skipped after an element belong to no element
+        let src = "fn f(x y, z) { g(1 2, , 3); ; h }
+struct S { x I32 }
+fn main() { }";
+        let (_, program, _) = cst::parse(src);
+        let (args_def, block) = match &program.items[0] {
+            Item::Func(f) => match &**f {
+                Expr::NamedFunc {
+                    args_def, block, ..
+                } => (args_def, block),
+                e => panic!("{:?}", e),
+            },
+            i => panic!("{:?}", i),
+        };
+        let params: Vec<String> = args_def.iter().map(|a| a.pattern.to_string()).collect();
+        assert_eq!(params, vec!["x", "z"]);
+        let exprs = match &**block {
+            Expr::Block { exprs, .. } => exprs,
+            e => panic!("{:?}", e),
+        };
+        assert_eq!(
+            spans(exprs),
+            vec!["1:16-1:26", "error 1:29-1:29", "1:31-1:31"]
+        );
+        match &*exprs[0] {
+            Expr::FuncApp { args, .. } => {
+                assert_eq!(
+                    spans(args),
+                    vec!["1:18-1:18", "error 1:23-1:23", "1:25-1:25"]
+                )
+            }
+            e => panic!("{:?}", e),
+        }
+        match &program.items[1] {
+            Item::Error { info } => assert_eq!(info.to_string(), "2:1-2:18"),
+            i => panic!("{:?}", i),
+        }
+        assert!(matches!(program.items[2], Item::Func(_)));
+    }
+}
+
+// Lowers `tree`, the tree of a whole source, into its AST.
+pub fn lower(tree: &SyntaxNode) -> Program {
+    let lowerer = Lowerer::new(tree.to_string());
+    let mut items = Vec::new();
+    let mut docs = HashMap::new();
+    for node in tree.child_nodes() {
+        let doc: Vec<String> = node
+            .tokens()
+            .into_iter()
+            .map_while(|t| match t.kind() {
+                TokenKind::DocComment(text) => Some(text.clone()),
+                _ => None,
+            })
+            .collect();
+        if !doc.is_empty() {
+            docs.insert(items.len(), doc.join("\n"));
+        }
+        items.push(lowerer.item(&node));
+    }
+    Program { items, docs }
+}
+
+// Lowers `tree`, the tree of a source holding one expression, into it.
+pub fn lower_expr(tree: &SyntaxNode) -> Box<Expr> {
+    let lowerer = Lowerer::new(tree.to_string());
+    match tree.child_nodes().first() {
+        Some(node) => lowerer.expr(node),
+        None => Box::from(Expr::Error {
+            info: TokenInfo::default(),
+        }),
+    }
+}
+
+// The children of a node, but its doc comments, taken in order
+struct Children(std::iter::Peekable<std::vec::IntoIter<SyntaxElement>>);
+
+impl Children {
+    fn of(node: &SyntaxNode) -> Children {
+        let children: Vec<SyntaxElement> = node
+            .children()
+            .into_iter()
+            .filter(|c| !matches!(c, SyntaxElement::Token(t) if is_doc(t)))
+            .collect();
+        Children(children.into_iter().peekable())
+    }
+
+    fn token(&mut self) -> Option<SyntaxToken> {
+        match self.0.peek() {
+            Some(SyntaxElement::Token(_)) => match self.0.next() {
+                Some(SyntaxElement::Token(t)) => Some(t),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn node(&mut self) -> Option<SyntaxNode> {
+        match self.0.peek() {
+            Some(SyntaxElement::Node(_)) => match self.0.next() {
+                Some(SyntaxElement::Node(n)) => Some(n),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // the next node if it is of `kind`
+    fn node_of(&mut self, kind: SyntaxKind) -> Option<SyntaxNode> {
+        match self.0.peek() {
+            Some(SyntaxElement::Node(n)) if n.kind() == kind => self.node(),
+            _ => None,
+        }
+    }
+
+    fn ident(&mut self) -> Option<String> {
+        match self.token()?.kind() {
+            TokenKind::Ident(name) => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    fn nodes(self) -> Vec<SyntaxNode> {
+        self.0
+            .filter_map(|c| match c {
+                SyntaxElement::Node(n) => Some(n),
+                SyntaxElement::Token(_) => None,
+            })
+            .collect()
+    }
+}
+
+fn is_doc(t: &SyntaxToken) -> bool {
+    matches!(t.kind(), TokenKind::DocComment(_))
+}
+
+struct Lowerer {
+    source: String,
+    // the offsets where the lines of the source start, and whether the
+    // lines are ASCII, where columns are found without counting chars
+    lines: Vec<(usize, bool)>,
+}
+
+impl Lowerer {
+    fn new(source: String) -> Lowerer {
+        let mut lines = Vec::new();
+        let mut start = 0;
+        for line in source.split('\n') {
+            lines.push((start, line.is_ascii()));
+            start += line.len() + 1;
+        }
+        Lowerer { source, lines }
+    }
+
+    // the row and the column of the char at `offset`, counted like the
+    // lexer does from 1
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let row = self.lines.partition_point(|(start, _)| *start <= offset);
+        let (start, ascii) = self.lines[row - 1];
+        let col = match ascii {
+            true => offset - start,
+            false => self.source[start..offset].chars().count(),
+        };
+        (row, col + 1)
+    }
+
+    // the span of the text in `range`, which ends at its last char rather
+    // than the one after it
+    fn range_info(&self, range: Range<usize>) -> TokenInfo {
+        let last = match self.source[range.clone()].chars().next_back() {
+            Some(c) => range.end - c.len_utf8(),
+            None => range.start,
+        };
+        let (s_row, s_col) = self.position(range.start);
+        let (e_row, e_col) = self.position(last);
+        TokenInfo {
+            s_col,
+            s_row,
+            e_col,
+            e_row,
+        }
+    }
+
+    fn info(&self, t: &SyntaxToken) -> TokenInfo {
+        self.range_info(t.text_range())
+    }
+
+    // the span of the tokens of `node`, but the doc comments before them
+    fn span(&self, node: &SyntaxNode) -> TokenInfo {
+        let range = node.text_range();
+        match range.is_empty() {
+            true => TokenInfo::default(),
+            false => self.range_info(range),
+        }
+    }
+
+    // the span from the start of `info` to the last token of `node`
+    fn span_from(&self, info: &TokenInfo, node: &SyntaxNode) -> TokenInfo {
+        info.to(&self.span(node))
+    }
+
+    // A node of an unexpected shape, which the parser does not make, is
+    // lowered like one it could not read.
+    fn item(&self, node: &SyntaxNode) -> Item {
+        self.try_item(node).unwrap_or(Item::Error {
+            info: self.span(node),
+        })
+    }
+
+    fn try_item(&self, node: &SyntaxNode) -> Option<Item> {
+        let mut children = Children::of(node);
+        let item = match node.kind() {
+            SyntaxKind::FuncItem => Item::Func(self.expr(&children.node()?)),
+            SyntaxKind::ConstItem => Item::Const(self.expr(&children.node()?)),
+            SyntaxKind::StructItem | SyntaxKind::EnumItem => {
+                let start = self.info(&children.token()?);
+                let name = children.ident()?;
+                let type_params = self.type_params(&mut children)?;
+                children.token()?;
+                let mut decls = Vec::new();
+                let end = loop {
+                    if let Some(decl) = children.node() {
+                        decls.push(decl);
+                    } else {
+                        let t = children.token()?;
+                        if *t.kind() == TokenKind::RBrace {
+                            break self.info(&t);
+                        }
+                    }
+                };
+                let info = start.to(&end);
+                match node.kind() {
+                    SyntaxKind::StructItem => Item::Struct {
+                        name,
+                        type_params,
+                        fields: decls
+                            .iter()
+                            .map(|d| self.field_decl(d))
+                            .collect::<Option<_>>()?,
+                        info,
+                    },
+                    _ => Item::Enum {
+                        name,
+                        type_params,
+                        variants: decls
+                            .iter()
+                            .map(|d| self.variant_decl(d))
+                            .collect::<Option<_>>()?,
+                        info,
+                    },
+                }
+            }
+            SyntaxKind::TypeAliasItem => {
+                let start = self.info(&children.token()?);
+                let name = children.ident()?;
+                children.token()?;
+                let ty = self.type_decl(&children.node()?)?;
+                let info = start.to(&ty.info);
+                Item::TypeAlias { name, ty, info }
+            }
+            SyntaxKind::ImportItem => {
+                let start = self.info(&children.token()?);
+                let name_token = children.token()?;
+                let name = match name_token.kind() {
+                    TokenKind::Ident(name) => name.clone(),
+                    _ => return None,
+                };
+                let info = start.to(&self.info(&name_token));
+                Item::Import { name, info }
+            }
+            _ => return None,
+        };
+        Some(item)
+    }
+
+    // `<T, ..>` after the name of a generic declaration, if any
+    fn type_params(&self, children: &mut Children) -> Option<Vec<String>> {
+        let node = match children.node_of(SyntaxKind::TypeParams) {
+            Some(node) => node,
+            None => return Some(Vec::new()),
+        };
+        let params = node
+            .tokens()
+            .iter()
+            .filter_map(|t| match t.kind() {
+                TokenKind::Ident(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        Some(params)
+    }
+
+    fn field_decl(&self, node: &SyntaxNode) -> Option<FieldDecl> {
+        let mut children = Children::of(node);
+        let name = children.ident()?;
+        children.token()?;
+        let ty = self.type_decl(&children.node()?)?;
+        let info = self.span(node);
+        Some(FieldDecl { name, ty, info })
+    }
+
+    fn variant_decl(&self, node: &SyntaxNode) -> Option<VariantDecl> {
+        let mut children = Children::of(node);
+        let name = children.ident()?;
+        let args = children
+            .nodes()
+            .iter()
+            .map(|n| self.type_decl(n))
+            .collect::<Option<_>>()?;
+        let info = self.span(node);
+        Some(VariantDecl { name, args, info })
+    }
+
+    // The elements of a list up to its closing bracket, like the arguments
+    // of a call or the elements of a block. An element the parser could
+    // not read is an Error node, or nothing before a separator, and is
+    // lowered by `error`. An Error node right after an element holds the
+    // tokens skipped after it.
+    fn list<T>(
+        &self,
+        children: &mut Children,
+        read: impl Fn(&SyntaxNode) -> Option<T>,
+        error: impl Fn(TokenInfo) -> T,
+    ) -> Option<Vec<T>> {
+        let mut elems = Vec::new();
+        let mut expecting = true;
+        loop {
+            if let Some(node) = children.node() {
+                if expecting {
+                    let info = self.span(&node);
+                    let elem = match node.kind() {
+                        SyntaxKind::Error => None,
+                        _ => read(&node),
+                    };
+                    elems.push(elem.unwrap_or_else(|| error(info)));
+                    expecting = false;
+                }
+                continue;
+            }
+            let t = children.token()?;
+            match t.kind() {
+                TokenKind::Comma | TokenKind::SemiColon => {
+                    if expecting {
+                        elems.push(error(self.info(&t)));
+                    }
+                    expecting = true;
+                }
+                TokenKind::RParen | TokenKind::RBrace => {
+                    // an empty list has no element to miss
+                    if expecting && !elems.is_empty() {
+                        elems.push(error(self.info(&t)));
+                    }
+                    return Some(elems);
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    fn expr(&self, node: &SyntaxNode) -> Box<Expr> {
+        self.try_expr(node).unwrap_or_else(|| {
+            Box::from(Expr::Error {
+                info: self.span(node),
+            })
+        })
+    }
+
+    fn try_expr(&self, node: &SyntaxNode) -> Option<Box<Expr>> {
+        let info = self.span(node);
+        let mut children = Children::of(node);
+        let expr = match node.kind() {
+            SyntaxKind::Literal => {
+                let mut t = children.token()?;
+                let negative = *t.kind() == TokenKind::Minus;
+                if negative {
+                    t = children.token()?;
+                }
+                match t.kind() {
+                    TokenKind::Num(text) => return parse_num(text, negative, info).ok(),
+                    TokenKind::Str(val) => Expr::Str {
+                        val: val.clone(),
+                        info,
+                    },
+                    TokenKind::Char(val) => Expr::Char { val: *val, info },
+                    TokenKind::UnitVal => Expr::Unit { info },
+                    TokenKind::True => Expr::Bool { val: true, info },
+                    TokenKind::False => Expr::Bool { val: false, info },
+                    _ => return None,
+                }
+            }
+            SyntaxKind::Var => Expr::Var {
+                name: children.ident()?,
+                info,
+            },
+            SyntaxKind::StructLit => {
+                let name = children.ident()?;
+                let fields = children
+                    .nodes()
+                    .iter()
+                    .map(|n| self.field_init(n))
+                    .collect::<Option<_>>()?;
+                Expr::StructLit { name, fields, info }
+            }
+            SyntaxKind::TupleExpr => Expr::Tuple {
+                elems: children.nodes().iter().map(|n| self.expr(n)).collect(),
+                info,
+            },
+            SyntaxKind::ParenExpr => return Some(self.expr(&children.nodes().into_iter().next()?)),
+            SyntaxKind::Block => {
+                children.token()?;
+                let exprs = self.list(
+                    &mut children,
+                    |n| Some(self.expr(n)),
+                    |info| Box::from(Expr::Error { info }),
+                )?;
+                Expr::Block { exprs, info }
+            }
+            SyntaxKind::LetStmt => {
+                let mut nodes = children.nodes();
+                let init = self.expr(&nodes.pop()?);
+                let vtype = match nodes.len() {
+                    2 => Some(self.type_decl(&nodes[1])?),
+                    _ => None,
+                };
+                let pattern = self.pattern(nodes.first()?)?;
+                Expr::Let {
+                    pattern,
+                    vtype,
+                    init,
+                    info,
+                }
+            }
+            SyntaxKind::NamedFunc => {
+                children.token()?;
+                let name = children.ident()?;
+                let type_params = self.type_params(&mut children)?;
+                let (args_def, ret_decl, block) = self.func_rest(&mut children)?;
+                Expr::NamedFunc {
+                    name,
+                    type_params,
+                    args_def,
+                    ret_decl,
+                    block,
+                    info,
+                }
+            }
+            SyntaxKind::AnonFunc => {
+                children.token()?;
+                let type_params = self.type_params(&mut children)?;
+                let (args_decl, ret_decl, block) = self.func_rest(&mut children)?;
+                Expr::AnonFunc {
+                    type_params,
+                    args_decl,
+                    ret_decl,
+                    block,
+                    info,
+                }
+            }
+            SyntaxKind::IfExpr => {
+                let mut nodes = children.nodes().into_iter();
+                let cond = self.expr(&nodes.next()?);
+                let then_block = self.expr(&nodes.next()?);
+                let else_block = nodes.next().map(|n| self.expr(&n));
+                Expr::If {
+                    cond,
+                    then_block,
+                    else_block,
+                    info,
+                }
+            }
+            SyntaxKind::MatchExpr => {
+                children.token()?;
+                let scrutinee = self.expr(&children.node()?);
+                children.token()?;
+                let arms = self.list(
+                    &mut children,
+                    |n| self.arm(n),
+                    |info| MatchArm {
+                        pattern: Pattern::Wildcard { info: info.clone() },
+                        body: Box::from(Expr::Error { info: info.clone() }),
+                        info,
+                    },
+                )?;
+                Expr::Match {
+                    scrutinee,
+                    arms,
+                    info,
+                }
+            }
+            SyntaxKind::CallExpr => {
+                let callee = self.expr(&children.node()?);
+                let mut args = Children::of(&children.node()?);
+                args.token()?;
+                let args = self.list(
+                    &mut args,
+                    |n| Some(self.expr(n)),
+                    |info| Box::from(Expr::Error { info }),
+                )?;
+                let info = self.span_from(callee.info(), node);
+                Expr::FuncApp { callee, args, info }
+            }
+            SyntaxKind::FieldExpr => {
+                let expr = self.expr(&children.node()?);
+                children.token()?;
+                let info = self.span_from(expr.info(), node);
+                match children.token()?.kind() {
+                    TokenKind::Num(n) => Expr::TupleField {
+                        expr,
+                        index: n.parse().ok()?,
+                        info,
+                    },
+                    TokenKind::Ident(field) => Expr::Field {
+                        expr,
+                        field: field.clone(),
+                        info,
+                    },
+                    _ => return None,
+                }
+            }
+            SyntaxKind::CastExpr => {
+                let expr = self.expr(&children.node()?);
+                children.token()?;
+                let ty = self.type_decl(&children.node()?)?;
+                let info = expr.info().to(&ty.info);
+                Expr::Cast { expr, ty, info }
+            }
+            SyntaxKind::UnaryExpr => {
+                let op = match children.token()?.kind() {
+                    TokenKind::Minus => UnaryOp::Neg,
+                    TokenKind::Bang => UnaryOp::Not,
+                    _ => return None,
+                };
+                let operand = self.expr(&children.node()?);
+                Expr::UnaryOp { op, operand, info }
+            }
+            SyntaxKind::BinExpr => {
+                let lhs = self.expr(&children.node()?);
+                let op = BinOp::from_token(children.token()?.kind())?;
+                let rhs = self.expr(&children.node()?);
+                // the rhs may end with a paren, which it does not span
+                let info = self.span_from(lhs.info(), node);
+                Expr::BinOp { op, lhs, rhs, info }
+            }
+            _ => return None,
+        };
+        Some(Box::from(expr))
+    }
+
+    fn field_init(&self, node: &SyntaxNode) -> Option<FieldInit> {
+        let mut children = Children::of(node);
+        let name = children.ident()?;
+        children.token()?;
+        let value = self.expr(&children.node()?);
+        let info = self.span(node);
+        Some(FieldInit { name, value, info })
+    }
+
+    fn arm(&self, node: &SyntaxNode) -> Option<MatchArm> {
+        let mut nodes = Children::of(node).nodes().into_iter();
+        let pattern = self.pattern(&nodes.next()?)?;
+        let body = self.expr(&nodes.next()?);
+        let info = self.span(node);
+        Some(MatchArm {
+            pattern,
+            body,
+            info,
+        })
+    }
+
+    // `(args) -> T { block }` of a function, with the return type optional
+    fn func_rest(
+        &self,
+        children: &mut Children,
+    ) -> Option<(Vec<ArgDecl>, Option<TypeDecl>, Box<Expr>)> {
+        let mut params = Children::of(&children.node_of(SyntaxKind::ParamList)?);
+        params.token()?;
+        let args = self.list(
+            &mut params,
+            |n| self.param(n),
+            |info| ArgDecl {
+                pattern: Pattern::Wildcard { info: info.clone() },
+                vtype: None,
+                info,
+            },
+        )?;
+        let ret_decl = match children.node_of(SyntaxKind::RetType) {
+            Some(ret) => Some(self.ret_decl(&ret)?),
+            None => None,
+        };
+        let block = self.expr(&children.node_of(SyntaxKind::Block)?);
+        Some((args, ret_decl, block))
+    }
+
+    fn param(&self, node: &SyntaxNode) -> Option<ArgDecl> {
+        let mut nodes = Children::of(node).nodes().into_iter();
+        let pattern = self.pattern(&nodes.next()?)?;
+        let vtype = match nodes.next() {
+            Some(ty) => Some(self.type_decl(&ty)?),
+            None => None,
+        };
+        let info = self.span(node);
+        Some(ArgDecl {
+            pattern,
+            vtype,
+            info,
+        })
+    }
+
+    fn ret_decl(&self, node: &SyntaxNode) -> Option<TypeDecl> {
+        self.type_decl(Children::of(node).nodes().first()?)
+    }
+
+    fn type_decl(&self, node: &SyntaxNode) -> Option<TypeDecl> {
+        let ty = self.ty(node)?;
+        let info = self.span(node);
+        Some(TypeDecl { ty, info })
+    }
+
+    fn ty(&self, node: &SyntaxNode) -> Option<Type> {
+        let mut children = Children::of(node);
+        let ty = match node.kind() {
+            SyntaxKind::Type => match children.token()?.kind() {
+                TokenKind::Ident(name) => Type::UserType {
+                    name: name.clone(),
+                    args: self.type_args(children)?,
+                },
+                TokenKind::I32 => Type::I32,
+                TokenKind::I64 => Type::I64,
+                TokenKind::U8 => Type::U8,
+                TokenKind::U32 => Type::U32,
+                TokenKind::F64 => Type::F64,
+                TokenKind::UnitType => Type::Unit,
+                TokenKind::BoolType => Type::Bool,
+                TokenKind::StringType => Type::String,
+                TokenKind::CharType => Type::Char,
+                _ => return None,
+            },
+            SyntaxKind::ParenType => self.ty(&children.nodes().into_iter().next()?)?,
+            SyntaxKind::TupleType => Type::Tuple(self.type_args(children)?),
+            SyntaxKind::FuncType => {
+                let mut nodes = children.nodes();
+                let ret = Box::from(self.ret_decl(&nodes.pop()?)?.ty);
+                let args = nodes
+                    .iter()
+                    .map(|n| self.ty(n).map(Box::from))
+                    .collect::<Option<_>>()?;
+                Type::Func { args, ret }
+            }
+            _ => return None,
+        };
+        Some(ty)
+    }
+
+    #[allow(clippy::vec_box)]
+    fn type_args(&self, children: Children) -> Option<Vec<Box<Type>>> {
+        children
+            .nodes()
+            .iter()
+            .map(|n| self.ty(n).map(Box::from))
+            .collect()
+    }
+
+    fn pattern(&self, node: &SyntaxNode) -> Option<Pattern> {
+        let info = self.span(node);
+        let mut children = Children::of(node);
+        let pattern = match node.kind() {
+            SyntaxKind::Pattern => {
+                let mut t = children.token()?;
+                let negative = *t.kind() == TokenKind::Minus;
+                if negative {
+                    t = children.token()?;
+                }
+                match t.kind() {
+                    TokenKind::Underscore => Pattern::Wildcard { info },
+                    TokenKind::Ident(name) => Pattern::Bind {
+                        name: name.clone(),
+                        info,
+                    },
+                    TokenKind::Num(text) => match *parse_num(text, negative, info).ok()? {
+                        Expr::Int { val, ty, info } => Pattern::Int { val, ty, info },
+                        _ => return None,
+                    },
+                    TokenKind::True => Pattern::Bool { val: true, info },
+                    TokenKind::False => Pattern::Bool { val: false, info },
+                    TokenKind::Char(val) => Pattern::Char { val: *val, info },
+                    TokenKind::Str(val) => Pattern::Str {
+                        val: val.clone(),
+                        info,
+                    },
+                    TokenKind::UnitVal => Pattern::Unit { info },
+                    _ => return None,
+                }
+            }
+            SyntaxKind::CtorPattern => Pattern::Ctor {
+                name: children.ident()?,
+                args: children
+                    .nodes()
+                    .iter()
+                    .map(|n| self.pattern(n))
+                    .collect::<Option<_>>()?,
+                info,
+            },
+            SyntaxKind::ParenPattern => self.pattern(&children.nodes().into_iter().next()?)?,
+            SyntaxKind::TuplePattern => Pattern::Tuple {
+                elems: children
+                    .nodes()
+                    .iter()
+                    .map(|n| self.pattern(n))
+                    .collect::<Option<_>>()?,
+                info,
+            },
+            _ => return None,
+        };
+        Some(pattern)
+    }
+}
@@ -0,0 +1,859 @@
+// The language server of `lung lsp`. It speaks JSON-RPC over stdin and
+// stdout, keeps the open documents analyzed, and answers with what the
+// lexer, the parser and the typer found out about them.
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::cst::{self, SyntaxElement, SyntaxNode};
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::json::Json;
+use crate::lexer::Lexer;
+use crate::syntax::*;
+use crate::type_def::*;
+use crate::typing::{Context, Index};
+
+#[cfg(test)]
+#[allow(clippy::items_after_test_module)]
+mod lsp_test {
+    use super::*;
+
+    const SOURCE: &str = "/// Doubles.
+fn double(x: I32) -> I32 { x * 2 }
+fn main() -> I32 {
+    let y = double(4);
+    fn inner(z: I32) -> I32 { z + y };
+    inner(y)
+}
+";
+
+    // A client sending `requests` one after another, returning what the
+    // server sent back and how it exited
+    fn session(requests: Vec<Json>) -> (Vec<Json>, bool) {
+        let mut input = Vec::new();
+        for r in requests {
+            let body = r.to_string();
+            write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        }
+        let mut output = Vec::new();
+        let shut_down = Server::new(input.as_slice(), &mut output).run().unwrap();
+        let mut output = output.as_slice();
+        let mut messages = Vec::new();
+        while let Some(body) = read_message(&mut output).unwrap() {
+            messages.push(Json::parse(&body).unwrap());
+        }
+        (messages, shut_down)
+    }
+
+    fn request(id: usize, method: &str, params: Json) -> Json {
+        Json::object(vec![
+            ("jsonrpc", Json::from("2.0")),
+            ("id", Json::from(id)),
+            ("method", Json::from(method)),
+            ("params", params),
+        ])
+    }
+
+    fn notification(method: &str, params: Json) -> Json {
+        Json::object(vec![
+            ("jsonrpc", Json::from("2.0")),
+            ("method", Json::from(method)),
+            ("params", params),
+        ])
+    }
+
+    fn open(uri: &str, text: &str) -> Json {
+        let doc = Json::object(vec![
+            ("uri", Json::from(uri)),
+            ("languageId", Json::from("lung")),
+            ("version", Json::from(1)),
+            ("text", Json::from(text)),
+        ]);
+        notification(
+            "textDocument/didOpen",
+            Json::object(vec![("textDocument", doc)]),
+        )
+    }
+
+    fn at(id: usize, method: &str, uri: &str, line: usize, character: usize) -> Json {
+        let params = Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", Json::from(uri))])),
+            (
+                "position",
+                Json::object(vec![
+                    ("line", Json::from(line)),
+                    ("character", Json::from(character)),
+                ]),
+            ),
+        ]);
+        request(id, method, params)
+    }
+
+    fn result(messages: &[Json], id: i64) -> &Json {
+        let response = messages.iter().find(|m| m.get("id").as_i64() == Some(id));
+        response.unwrap().get("result")
+    }
+
+    fn range(r: &Json) -> String {
+        let pos = |p: &Json| {
+            let line = p.get("line").as_i64().unwrap();
+            format!("{}:{}", line, p.get("character").as_i64().unwrap())
+        };
+        format!("{}-{}", pos(r.get("start")), pos(r.get("end")))
+    }
+
+    #[test]
+    fn test_session() {
+        let uri = "untitled:a.lung";
+        let doc = Json::object(vec![(
+            "textDocument",
+            Json::object(vec![("uri", Json::from(uri))]),
+        )]);
+        let (messages, shut_down) = session(vec![
+            request(1, "initialize", Json::object(vec![])),
+            notification("initialized", Json::object(vec![])),
+            open(uri, SOURCE),
+            at(2, "textDocument/hover", uri, 3, 13),
+            at(3, "textDocument/hover", uri, 3, 19),
+            at(4, "textDocument/definition", uri, 5, 10),
+            at(5, "textDocument/definition", uri, 5, 5),
+            request(6, "textDocument/documentSymbol", doc),
+            at(7, "textDocument/completion", uri, 5, 6),
+            at(8, "textDocument/hover", uri, 0, 0),
+            request(9, "frobnicate", Json::Null),
+            request(10, "shutdown", Json::Null),
+            notification("exit", Json::Null),
+        ]);
+        assert!(shut_down);
+        let caps = result(&messages, 1).get("capabilities");
+        assert_eq!(caps.get("hoverProvider"), &Json::Bool(true));
+        assert_eq!(caps.get("textDocumentSync").as_i64(), Some(1));
+
+        let published = messages
+            .iter()
+            .find(|m| m.get("method").as_str() == Some("textDocument/publishDiagnostics"))
+            .unwrap();
+        assert_eq!(published.get("params").get("uri").as_str(), Some(uri));
+        let diagnostics = published.get("params").get("diagnostics");
+        assert_eq!(diagnostics.as_array().map(|d| d.len()), Some(0));
+
+        let hover = result(&messages, 2);
+        let expected = "```lung\ndouble: Fn(I32) -> I32\n```\n\nDoubles.";
+        assert_eq!(hover.get("contents").get("value").as_str(), Some(expected));
+        assert_eq!(range(hover.get("range")), "3:12-3:18");
+        let hover = result(&messages, 3).get("contents").get("value");
+        assert_eq!(hover.as_str(), Some("```lung\nI32\n```"));
+
+        let def = result(&messages, 4);
+        assert_eq!(def.get("uri").as_str(), Some(uri));
+        assert_eq!(range(def.get("range")), "3:8-3:9");
+        assert_eq!(range(result(&messages, 5).get("range")), "4:4-4:37");
+
+        let symbols = result(&messages, 6).as_array().unwrap();
+        let names: Vec<&str> = symbols
+            .iter()
+            .map(|s| s.get("name").as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["double", "main"]);
+        assert_eq!(range(symbols[0].get("selectionRange")), "1:3-1:9");
+        let inner = &symbols[1].get("children").as_array().unwrap()[0];
+        assert_eq!(inner.get("name").as_str(), Some("inner"));
+        assert_eq!(range(inner.get("range")), "4:4-4:37");
+
+        let items = result(&messages, 7).as_array().unwrap();
+        let labels: Vec<&str> = items
+            .iter()
+            .map(|i| i.get("label").as_str().unwrap())
+            .collect();
+        assert_eq!(labels, ["double", "inner", "main", "y"]);
+        assert_eq!(items[0].get("detail").as_str(), Some("Fn(I32) -> I32"));
+
+        assert_eq!(result(&messages, 8), &Json::Null);
+        let error = messages.iter().find(|m| m.get("id").as_i64() == Some(9));
+        assert_eq!(
+            error.unwrap().get("error").get("code").as_i64(),
+            Some(-32601)
+        );
+        assert_eq!(result(&messages, 10), &Json::Null);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let diagnostics = |text: &str| {
+            let (messages, _) = session(vec![open("untitled:b.lung", text)]);
+            let params = messages[0].get("params").get("diagnostics").clone();
+            let codes: Vec<String> = params
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|d| {
+                    format!(
+                        "{} {}",
+                        d.get("code").as_str().unwrap(),
+                        range(d.get("range"))
+                    )
+                })
+                .collect();
+            codes
+        };
+        assert_eq!(
+            diagnostics("fn main() -> I32 { true }"),
+            ["E0200 0:19-0:23"]
+        );
+        assert_eq!(
+            diagnostics("fn main() -> I32 { 1 $ 2 }\nfn f( -> I32 { 1 }"),
+            ["E0001 0:21-0:22", "E0100 1:6-1:8", "E0100 1:17-1:18"]
+        );
+        let (_, shut_down) = session(vec![notification("exit", Json::Null)]);
+        assert!(!shut_down);
+    }
+
+    #[test]
+    fn test_positions() {
+        let text = "a\n  é😀b\n";
+        assert_eq!(to_position(text, 2, 5), (1, 5));
+        assert_eq!(from_position(text, 1, 5), (2, 5));
+        assert_eq!(from_position(text, 1, 3), (2, 4));
+        assert_eq!(offset_position(text, text.find('b').unwrap()), (1, 5));
+    }
+}
+
+// JSON-RPC error codes
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_REQUEST: i64 = -32600;
+const PARSE_ERROR: i64 = -32700;
+
+// LSP kinds of symbols and completions
+const SYMBOL_FUNCTION: usize = 12;
+const COMPLETION_FUNCTION: usize = 3;
+const COMPLETION_VARIABLE: usize = 6;
+const COMPLETION_CONSTRUCTOR: usize = 4;
+
+// An open document, analyzed anew at each change
+struct Document {
+    text: String,
+    tree: SyntaxNode,
+    program: Program,
+    diagnostics: Vec<Diagnostic>,
+    index: Index,
+    // the top level bindings, those of the imported files included
+    top_level: Vec<(String, Scheme)>,
+}
+
+impl Document {
+    fn new(uri: &str, text: String) -> Document {
+        let (tree, program, mut diagnostics) = cst::parse(&text);
+        let mut cxt = Context::new();
+        // the imported files are typed first like `lung check` does,
+        // unless the imports cannot be read for syntax errors
+        if diagnostics.is_empty() {
+            diagnostics.extend(check_imports(uri, &text, &mut cxt));
+            cxt.take_warnings();
+        }
+        cxt.start_index();
+        let checked = cxt.check_program(&program);
+        // a partial tree is typed only for its index, its type errors may
+        // well come from the syntax errors
+        if diagnostics.is_empty() {
+            diagnostics.extend(checked.err());
+            diagnostics.extend(cxt.take_warnings());
+        }
+        Document {
+            index: cxt.take_index(),
+            top_level: cxt.top_level(),
+            text,
+            tree,
+            program,
+            diagnostics,
+        }
+    }
+}
+
+// Types the files imported by the document `uri` in `cxt`. Errors in those
+// files are reported at the start of the document.
+fn check_imports(uri: &str, text: &str, cxt: &mut Context) -> Vec<Diagnostic> {
+    let fname = uri_path(uri);
+    let in_file = |fname: &str, mut d: Diagnostic| {
+        d.message = format!("{}: {}", fname, d.message);
+        d.primary = None;
+        d.secondary.clear();
+        d
+    };
+    let files = match crate::load(fname.clone(), Lexer::from_string(String::from(text))) {
+        Ok(files) => files,
+        Err(reports) => {
+            return reports
+                .into_iter()
+                .map(|r| match r.fname == fname {
+                    true => r.diagnostic,
+                    false => in_file(&r.fname, r.diagnostic),
+                })
+                .collect()
+        }
+    };
+    for f in files.split_last().map_or(&[][..], |(_, imported)| imported) {
+        if let Err(d) = cxt.check_program(&f.program) {
+            return vec![in_file(&f.fname, d)];
+        }
+    }
+    Vec::new()
+}
+
+// The path of a `file:` URI, or the URI itself for other schemes
+fn uri_path(uri: &str) -> String {
+    let path = match uri.strip_prefix("file://") {
+        Some(path) => path,
+        None => return String::from(uri),
+    };
+    let mut bytes = Vec::new();
+    let mut rest = path.as_bytes();
+    while let Some((b, tail)) = rest.split_first() {
+        let hex = tail.get(..2).and_then(|h| std::str::from_utf8(h).ok());
+        match hex.map(|h| u8::from_str_radix(h, 16)) {
+            Some(Ok(decoded)) if *b == b'%' => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(*b);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// LSP positions count lines from 0 and UTF-16 code units in a line, while
+// rows and columns of TokenInfo count lines and chars from 1.
+
+// The position before the char at `row` and `col`, as (line, character)
+fn to_position(text: &str, row: usize, col: usize) -> (usize, usize) {
+    let line = text.lines().nth(row.saturating_sub(1)).unwrap_or("");
+    let character = line
+        .chars()
+        .take(col.saturating_sub(1))
+        .map(char::len_utf16)
+        .sum();
+    (row.saturating_sub(1), character)
+}
+
+// The row and column of the char after a position
+fn from_position(text: &str, line: usize, character: usize) -> (usize, usize) {
+    let mut units = 0;
+    let chars = text.lines().nth(line).unwrap_or("").chars();
+    let col = chars
+        .take_while(|c| {
+            units += c.len_utf16();
+            units <= character
+        })
+        .count();
+    (line + 1, col + 1)
+}
+
+// The position at a byte offset
+fn offset_position(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count();
+    (line, before[line_start..].encode_utf16().count())
+}
+
+fn position(pos: (usize, usize)) -> Json {
+    Json::object(vec![
+        ("line", Json::from(pos.0)),
+        ("character", Json::from(pos.1)),
+    ])
+}
+
+fn range(text: &str, info: &TokenInfo) -> Json {
+    Json::object(vec![
+        ("start", position(to_position(text, info.s_row, info.s_col))),
+        (
+            "end",
+            position(to_position(text, info.e_row, info.e_col + 1)),
+        ),
+    ])
+}
+
+fn byte_range(text: &str, range: std::ops::Range<usize>) -> Json {
+    Json::object(vec![
+        ("start", position(offset_position(text, range.start))),
+        ("end", position(offset_position(text, range.end))),
+    ])
+}
+
+// whether the char at `at` is in the span
+fn contains(info: &TokenInfo, at: (usize, usize)) -> bool {
+    (info.s_row, info.s_col) <= at && at <= (info.e_row, info.e_col)
+}
+
+fn diagnostic(uri: &str, text: &str, d: &Diagnostic) -> Json {
+    let empty = TokenInfo {
+        s_col: 1,
+        s_row: 1,
+        e_col: 0,
+        e_row: 1,
+    };
+    let mut message = d.message.clone();
+    for note in &d.notes {
+        message.push_str(&format!("\n{}", note));
+    }
+    let related = d
+        .secondary
+        .iter()
+        .map(|(info, label)| {
+            Json::object(vec![
+                (
+                    "location",
+                    Json::object(vec![("uri", Json::from(uri)), ("range", range(text, info))]),
+                ),
+                ("message", Json::from(label.as_str())),
+            ])
+        })
+        .collect();
+    let severity = match d.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+    };
+    Json::object(vec![
+        ("range", range(text, d.primary.as_ref().unwrap_or(&empty))),
+        ("severity", Json::from(severity)),
+        ("code", Json::from(d.code)),
+        ("source", Json::from("lung")),
+        ("message", Json::from(message)),
+        ("relatedInformation", Json::Array(related)),
+    ])
+}
+
+fn item_info(item: &Item) -> &TokenInfo {
+    match item {
+        Item::Func(e) | Item::Const(e) => e.info(),
+        Item::Struct { info, .. }
+        | Item::Enum { info, .. }
+        | Item::TypeAlias { info, .. }
+        | Item::Import { info, .. }
+        | Item::Error { info } => info,
+    }
+}
+
+// The named functions in `node`, with those defined in them as children
+fn symbols(text: &str, node: &SyntaxNode) -> Vec<Json> {
+    let mut found = Vec::new();
+    for child in node.children() {
+        let child = match child {
+            SyntaxElement::Node(n) => n,
+            SyntaxElement::Token(_) => continue,
+        };
+        if child.kind() != cst::SyntaxKind::NamedFunc {
+            found.extend(symbols(text, &child));
+            continue;
+        }
+        let tokens = child.tokens();
+        let name = match tokens
+            .iter()
+            .find(|t| matches!(t.kind(), TokenKind::Ident(_)))
+        {
+            Some(name) => name,
+            None => continue,
+        };
+        found.push(Json::object(vec![
+            ("name", Json::from(name.text())),
+            ("kind", Json::from(SYMBOL_FUNCTION)),
+            ("range", byte_range(text, child.text_range())),
+            ("selectionRange", byte_range(text, name.text_range())),
+            ("children", Json::Array(symbols(text, &child))),
+        ]));
+    }
+    found
+}
+
+fn subexprs(e: &Expr) -> Vec<&Expr> {
+    match e {
+        Expr::NamedFunc { block, .. } | Expr::AnonFunc { block, .. } => vec![block],
+        Expr::Block { exprs, .. } | Expr::Tuple { elems: exprs, .. } => {
+            exprs.iter().map(|e| &**e).collect()
+        }
+        Expr::Let { init, .. } => vec![init],
+        Expr::StructLit { fields, .. } => fields.iter().map(|f| &*f.value).collect(),
+        Expr::Field { expr, .. } | Expr::TupleField { expr, .. } | Expr::Cast { expr, .. } => {
+            vec![expr]
+        }
+        Expr::UnaryOp { operand, .. } => vec![operand],
+        Expr::Match {
+            scrutinee, arms, ..
+        } => {
+            let bodies = arms.iter().map(|a| &*a.body);
+            std::iter::once(&**scrutinee).chain(bodies).collect()
+        }
+        Expr::FuncApp { callee, args, .. } => std::iter::once(&**callee)
+            .chain(args.iter().map(|a| &**a))
+            .collect(),
+        Expr::If {
+            cond,
+            then_block,
+            else_block,
+            ..
+        } => {
+            let mut exprs = vec![&**cond, &**then_block];
+            exprs.extend(else_block.as_deref());
+            exprs
+        }
+        Expr::BinOp { lhs, rhs, .. } => vec![lhs, rhs],
+        Expr::Int { .. }
+        | Expr::Float { .. }
+        | Expr::Unit { .. }
+        | Expr::Bool { .. }
+        | Expr::Str { .. }
+        | Expr::Char { .. }
+        | Expr::Var { .. }
+        | Expr::Error { .. } => Vec::new(),
+    }
+}
+
+// Adds the names bound around `at` by `e` and the expressions in it: the
+// arguments of the functions, the lets before it in blocks, the functions
+// of blocks and the bindings of match arms.
+fn locals(e: &Expr, at: (usize, usize), names: &mut Vec<String>) {
+    if !contains(e.info(), at) {
+        return;
+    }
+    match e {
+        Expr::NamedFunc { args_def: args, .. }
+        | Expr::AnonFunc {
+            args_decl: args, ..
+        } => {
+            names.extend(args.iter().flat_map(|a| a.pattern.bindings()).cloned());
+        }
+        Expr::Block { exprs, .. } => {
+            for e in exprs {
+                match &**e {
+                    Expr::NamedFunc { name, .. } => names.push(name.clone()),
+                    Expr::Let { pattern, info, .. } if (info.e_row, info.e_col) < at => {
+                        names.extend(pattern.bindings().into_iter().cloned())
+                    }
+                    _ => (),
+                }
+            }
+        }
+        Expr::Match { arms, .. } => {
+            for arm in arms.iter().filter(|a| contains(a.body.info(), at)) {
+                names.extend(arm.pattern.bindings().into_iter().cloned());
+            }
+        }
+        _ => (),
+    }
+    for e in subexprs(e) {
+        locals(e, at, names);
+    }
+}
+
+// Serves one client, reading its messages from `input` and writing to
+// `output`.
+pub struct Server<R, W> {
+    input: R,
+    output: W,
+    documents: HashMap<String, Document>,
+    shut_down: bool,
+}
+
+// A message after its `Content-Length` header, None at the end of input
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(n) = line.strip_prefix("Content-Length:") {
+            length = n.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+impl<R: BufRead, W: Write> Server<R, W> {
+    pub fn new(input: R, output: W) -> Server<R, W> {
+        Server {
+            input,
+            output,
+            documents: HashMap::new(),
+            shut_down: false,
+        }
+    }
+
+    // Serves until the client sends `exit` or closes the input. Returns
+    // whether it asked to shut down first, as it should.
+    pub fn run(&mut self) -> io::Result<bool> {
+        while let Some(body) = read_message(&mut self.input)? {
+            let msg = match Json::parse(&body) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    self.error(&Json::Null, PARSE_ERROR, &e)?;
+                    continue;
+                }
+            };
+            if msg.get("method").as_str() == Some("exit") {
+                break;
+            }
+            self.handle(&msg)?;
+        }
+        Ok(self.shut_down)
+    }
+
+    fn send(&mut self, msg: Json) -> io::Result<()> {
+        let body = msg.to_string();
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+        self.output.flush()
+    }
+
+    fn respond(&mut self, id: &Json, result: Json) -> io::Result<()> {
+        self.send(Json::object(vec![
+            ("jsonrpc", Json::from("2.0")),
+            ("id", id.clone()),
+            ("result", result),
+        ]))
+    }
+
+    fn error(&mut self, id: &Json, code: i64, message: &str) -> io::Result<()> {
+        let error = Json::object(vec![
+            ("code", Json::Num(code as f64)),
+            ("message", Json::from(message)),
+        ]);
+        self.send(Json::object(vec![
+            ("jsonrpc", Json::from("2.0")),
+            ("id", id.clone()),
+            ("error", error),
+        ]))
+    }
+
+    fn handle(&mut self, msg: &Json) -> io::Result<()> {
+        let id = msg.get("id");
+        let params = msg.get("params");
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+        let method = match msg.get("method").as_str() {
+            Some(m) => m,
+            // a response to a request of ours, which we never make
+            None => return Ok(()),
+        };
+        if self.shut_down && *id != Json::Null {
+            return self.error(id, INVALID_REQUEST, "the server is shut down");
+        }
+        let result = match method {
+            "initialize" => capabilities(),
+            "shutdown" => {
+                self.shut_down = true;
+                Json::Null
+            }
+            "textDocument/didOpen" => {
+                let text = params
+                    .get("textDocument")
+                    .get("text")
+                    .as_str()
+                    .unwrap_or("");
+                return self.open(uri, String::from(text));
+            }
+            "textDocument/didChange" => {
+                // the whole text is sent at each change
+                let changes = params.get("contentChanges").as_array();
+                let text = changes.and_then(|c| c.last()).map(|c| c.get("text"));
+                return match text.and_then(Json::as_str) {
+                    Some(text) => self.open(uri, String::from(text)),
+                    None => Ok(()),
+                };
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return self.publish(uri, Json::Array(Vec::new()));
+            }
+            "textDocument/hover" => self.at(params, hover),
+            "textDocument/definition" => self.at(params, definition),
+            "textDocument/completion" => self.at(params, completion),
+            "textDocument/documentSymbol" => match self.documents.get(uri) {
+                Some(doc) => Json::Array(symbols(&doc.text, &doc.tree)),
+                None => Json::Null,
+            },
+            // notifications need no answer
+            _ if *id == Json::Null => return Ok(()),
+            m => {
+                let message = format!("Method not found: {}", m);
+                return self.error(id, METHOD_NOT_FOUND, &message);
+            }
+        };
+        self.respond(id, result)
+    }
+
+    fn open(&mut self, uri: &str, text: String) -> io::Result<()> {
+        let doc = Document::new(uri, text);
+        let diagnostics = doc
+            .diagnostics
+            .iter()
+            .map(|d| diagnostic(uri, &doc.text, d))
+            .collect();
+        self.documents.insert(String::from(uri), doc);
+        self.publish(uri, Json::Array(diagnostics))
+    }
+
+    fn publish(&mut self, uri: &str, diagnostics: Json) -> io::Result<()> {
+        let params = Json::object(vec![("uri", Json::from(uri)), ("diagnostics", diagnostics)]);
+        self.send(Json::object(vec![
+            ("jsonrpc", Json::from("2.0")),
+            ("method", Json::from("textDocument/publishDiagnostics")),
+            ("params", params),
+        ]))
+    }
+
+    // Answers a request about a position in a document with `f`
+    fn at(&self, params: &Json, f: fn(&str, &Document, (usize, usize)) -> Json) -> Json {
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+        let pos = params.get("position");
+        let doc = self.documents.get(uri);
+        match (doc, pos.get("line").as_i64(), pos.get("character").as_i64()) {
+            (Some(doc), Some(line), Some(character)) if line >= 0 && character >= 0 => {
+                let at = from_position(&doc.text, line as usize, character as usize);
+                f(uri, doc, at)
+            }
+            _ => Json::Null,
+        }
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object(vec![
+        (
+            "capabilities",
+            Json::object(vec![
+                // full text at each change
+                ("textDocumentSync", Json::from(1)),
+                ("hoverProvider", Json::from(true)),
+                ("definitionProvider", Json::from(true)),
+                ("documentSymbolProvider", Json::from(true)),
+                ("completionProvider", Json::object(vec![])),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::object(vec![("name", Json::from("lung"))]),
+        ),
+    ])
+}
+
+// The type of the innermost expression at `at`. For a variable, its name
+// and the doc comment of the item defining it come along.
+fn hover(_: &str, doc: &Document, at: (usize, usize)) -> Json {
+    // the index has the expressions in them before those around them
+    let (info, ty) = match doc.index.types.iter().find(|(info, _)| contains(info, at)) {
+        Some(found) => found,
+        None => return Json::Null,
+    };
+    let ty = Scheme {
+        vars: ty.free_vars(),
+        ty: ty.clone(),
+    };
+    let var = doc.index.vars.iter().find(|(var, _, _)| var == info);
+    let mut value = match var {
+        Some((_, name, _)) => format!("```lung\n{}: {}\n```", name, ty),
+        None => format!("```lung\n{}\n```", ty),
+    };
+    let item = var.and_then(|(_, _, def)| def.as_ref()).and_then(|def| {
+        let items = doc.program.items.iter();
+        items
+            .map(item_info)
+            .position(|i| contains(i, (def.s_row, def.s_col)))
+    });
+    if let Some(text) = item.and_then(|i| doc.program.doc(i)) {
+        value.push_str(&format!("\n\n{}", text));
+    }
+    Json::object(vec![
+        (
+            "contents",
+            Json::object(vec![
+                ("kind", Json::from("markdown")),
+                ("value", Json::from(value)),
+            ]),
+        ),
+        ("range", range(&doc.text, info)),
+    ])
+}
+
+// Where the variable at `at` is bound
+fn definition(uri: &str, doc: &Document, at: (usize, usize)) -> Json {
+    let var = doc.index.vars.iter().find(|(var, _, _)| contains(var, at));
+    match var.and_then(|(_, _, def)| def.as_ref()) {
+        Some(def) => Json::object(vec![
+            ("uri", Json::from(uri)),
+            ("range", range(&doc.text, def)),
+        ]),
+        None => Json::Null,
+    }
+}
+
+// The names in scope at `at`, the top level ones with their types
+fn completion(_: &str, doc: &Document, at: (usize, usize)) -> Json {
+    // the cursor is after what was typed, so the scope is the one of the
+    // char before it
+    let at = (at.0, at.1.saturating_sub(1).max(1));
+    let mut names: Vec<(String, Option<&Scheme>)> = doc
+        .top_level
+        .iter()
+        .map(|(name, s)| (name.clone(), Some(s)))
+        .collect();
+    // the items typing did not get to have no type
+    let mut items = Vec::new();
+    let mut local = Vec::new();
+    for item in &doc.program.items {
+        match item {
+            Item::Func(e) | Item::Const(e) => {
+                match &**e {
+                    Expr::NamedFunc { name, .. } => items.push(name.clone()),
+                    Expr::Let { pattern, .. } => {
+                        items.extend(pattern.bindings().into_iter().cloned())
+                    }
+                    _ => (),
+                }
+                locals(e, at, &mut local);
+            }
+            Item::Enum { variants, .. } => items.extend(variants.iter().map(|v| v.name.clone())),
+            _ => (),
+        }
+    }
+    for name in items {
+        if !names.iter().any(|(n, _)| *n == name) {
+            names.push((name, None));
+        }
+    }
+    for name in local {
+        // a local shadows the top level binding of the same name
+        names.retain(|(n, _)| *n != name);
+        names.push((name, None));
+    }
+    names.sort_by(|a, b| a.0.cmp(&b.0));
+    let items = names
+        .into_iter()
+        .map(|(name, scheme)| {
+            let kind = match scheme.map(|s| &s.ty) {
+                _ if name.starts_with(char::is_uppercase) => COMPLETION_CONSTRUCTOR,
+                Some(Type::Func { .. }) => COMPLETION_FUNCTION,
+                _ => COMPLETION_VARIABLE,
+            };
+            let mut item = vec![("label", Json::from(name)), ("kind", Json::from(kind))];
+            item.extend(scheme.map(|s| ("detail", Json::from(s.to_string()))));
+            Json::object(item)
+        })
+        .collect();
+    Json::Array(items)
+}
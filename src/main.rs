@@ -1,9 +1,506 @@
+mod cst;
+mod diagnostic;
+mod eval;
+mod exhaustive;
+mod format;
+mod json;
 mod lexer;
+mod lower;
+mod lsp;
 mod parser;
+mod render;
+mod repl;
 mod syntax;
 mod type_def;
 mod typing;
 
+use std::io::{IsTerminal, Read};
+use std::path::Path;
+use std::process;
+use std::thread;
+
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Lexer;
+use crate::syntax::*;
+
+#[cfg(test)]
+mod main_test {
+    use super::*;
+
+    fn args(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| String::from(*s)).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let cmd = Command::from_args(args(&["run", "a.lung"])).unwrap();
+        assert_eq!(cmd, Command::Run(Some(String::from("a.lung"))));
+        let cmd = Command::from_args(args(&["check", "-"])).unwrap();
+        assert_eq!(cmd, Command::Check(None));
+        let cmd = Command::from_args(args(&["tokens"])).unwrap();
+        assert_eq!(cmd, Command::Tokens(None));
+        let cmd = Command::from_args(args(&["tree", "a.lung"])).unwrap();
+        assert_eq!(cmd, Command::Tree(Some(String::from("a.lung"))));
+        let cmd = Command::from_args(args(&["fmt", "--check", "--width", "80", "a.lung"])).unwrap();
+        let file = Some(String::from("a.lung"));
+        assert_eq!(
+            cmd,
+            Command::Fmt {
+                file,
+                check: true,
+                width: 80
+            }
+        );
+        let cmd = Command::from_args(args(&["fmt"])).unwrap();
+        let width = format::DEFAULT_WIDTH;
+        assert_eq!(
+            cmd,
+            Command::Fmt {
+                file: None,
+                check: false,
+                width
+            }
+        );
+        assert!(Command::from_args(args(&["fmt", "--width", "x"])).is_err());
+        assert!(Command::from_args(args(&["fmt", "--frobnicate"])).is_err());
+        let cmd = Command::from_args(args(&["lsp"])).unwrap();
+        assert_eq!(cmd, Command::Lsp);
+        assert!(Command::from_args(args(&["lsp", "a.lung"])).is_err());
+        let cmd = Command::from_args(args(&["repl"])).unwrap();
+        assert_eq!(cmd, Command::Repl);
+        assert!(Command::from_args(args(&["repl", "a.lung"])).is_err());
+        assert!(Command::from_args(args(&["frobnicate"])).is_err());
+        assert!(Command::from_args(args(&["run", "a", "b"])).is_err());
+    }
+
+    fn load_file(fname: &str) -> Result<Vec<SourceFile>, Vec<Report>> {
+        let lexer = open_source(&Some(String::from(fname))).unwrap();
+        load(String::from(fname), lexer)
+    }
+
+    #[test]
+    fn test_run() {
+        let files = load_file("src/test/test_eval.txt").unwrap();
+        assert_eq!(run(&files, &mut Vec::new()).unwrap().to_string(), "1");
+    }
+
+    #[test]
+    fn test_import() {
+        let files = load_file("src/test/importmain.lung").unwrap();
+        let names: Vec<&str> = files.iter().map(|f| f.fname.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["src/test/importlib.lung", "src/test/importmain.lung"]
+        );
+        assert_eq!(run(&files, &mut Vec::new()).unwrap().to_string(), "42");
+        let f = load_file("src/test/importcycle.lung").unwrap_err();
+        assert_eq!(f[0].diagnostic.code, "E0401");
+        assert_eq!(f[0].fname, "src/test/importcycle.lung");
+        // the `main` of an imported file is not the entry point
+        let files = load_file("src/test/importnomain.lung").unwrap();
+        let r = run(&files, &mut Vec::new()).unwrap_err();
+        assert_eq!(r.diagnostic.code, "E0209");
+        assert_eq!(r.fname, "src/test/importnomain.lung");
+    }
+}
+
+// exit codes of the lung binary
+const EXIT_OK: i32 = 0;
+// lexing, parsing, typing or runtime error in the Lung program
+const EXIT_FAILURE: i32 = 1;
+// bad command line, unreadable input or unwritable output
+const EXIT_USAGE: i32 = 2;
+// a bug in lung, like the exit code of a panicking Rust program
+const EXIT_PANIC: i32 = 101;
+
+const USAGE: &str = "\
+usage: lung <command> [FILE]
+       lung fmt [--check] [--width N] [FILE]
+       lung repl
+       lung lsp
+
+commands:
+    tokens   print the tokens of FILE
+    parse    print the syntax tree of FILE
+    tree     print the concrete syntax tree of FILE, with every token
+    check    type check FILE and print the types of its items
+    run      type check FILE and call its `main` function
+    fmt      format FILE in place, or stdin to stdout; with --check, only
+             fail when it is not formatted. Lines are broken at N columns,
+             100 by default
+    repl     read and evaluate expressions interactively
+    lsp      serve the Language Server Protocol over stdin and stdout
+
+FILE defaults to stdin, as does `-`.";
+
+// `None` as the file means stdin.
+#[derive(Debug, PartialEq)]
+enum Command {
+    Tokens(Option<String>),
+    Parse(Option<String>),
+    Tree(Option<String>),
+    Check(Option<String>),
+    Run(Option<String>),
+    Fmt {
+        file: Option<String>,
+        check: bool,
+        width: usize,
+    },
+    Repl,
+    Lsp,
+    Help,
+}
+
+impl Command {
+    fn from_args(args: Vec<String>) -> Result<Command, String> {
+        let mut args = args.into_iter();
+        let cmd = match args.next() {
+            Some(c) => c,
+            None => return Err(String::from("missing command")),
+        };
+        if cmd == "fmt" {
+            return Command::fmt_from_args(args);
+        }
+        let file = match args.next() {
+            Some(ref f) if f == "-" => None,
+            f => f,
+        };
+        if let Some(extra) = args.next() {
+            return Err(format!("unexpected argument `{}`", extra));
+        }
+        match cmd.as_str() {
+            "repl" | "lsp" => match file {
+                None if cmd == "repl" => Ok(Command::Repl),
+                None => Ok(Command::Lsp),
+                Some(f) => Err(format!("unexpected argument `{}`", f)),
+            },
+            "tokens" => Ok(Command::Tokens(file)),
+            "parse" => Ok(Command::Parse(file)),
+            "tree" => Ok(Command::Tree(file)),
+            "check" => Ok(Command::Check(file)),
+            "run" => Ok(Command::Run(file)),
+            "help" | "-h" | "--help" => Ok(Command::Help),
+            c => Err(format!("unknown command `{}`", c)),
+        }
+    }
+
+    fn fmt_from_args(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+        let (mut file, mut check, mut width) = (None, false, format::DEFAULT_WIDTH);
+        let mut files = 0;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--check" => check = true,
+                "--width" => {
+                    width = match args.next().map(|w| w.parse::<usize>()) {
+                        Some(Ok(w)) if w > 0 => w,
+                        _ => return Err(String::from("--width needs a positive number")),
+                    }
+                }
+                "-" if files == 0 => files += 1,
+                f if files == 0 && !f.starts_with("--") => {
+                    files += 1;
+                    file = Some(String::from(f));
+                }
+                a => return Err(format!("unexpected argument `{}`", a)),
+            }
+        }
+        Ok(Command::Fmt { file, check, width })
+    }
+}
+
+fn open_source(file: &Option<String>) -> Result<Lexer, String> {
+    match file {
+        Some(fname) => Lexer::from_file(fname).map_err(|e| format!("{}: {}", fname, e)),
+        None => {
+            let mut buffer = String::new();
+            std::io::stdin()
+                .read_to_string(&mut buffer)
+                .map_err(|e| format!("<stdin>: {}", e))?;
+            Ok(Lexer::from_string(buffer))
+        }
+    }
+}
+
+// A parsed file of the program.
+#[derive(Debug)]
+struct SourceFile {
+    fname: String,
+    source: String,
+    program: Program,
+}
+
+// A diagnostic together with the file it is about.
+#[derive(Debug)]
+struct Report {
+    fname: String,
+    source: String,
+    diagnostic: Diagnostic,
+}
+
+impl Report {
+    fn new(fname: &str, lexer: &Lexer, diagnostic: Diagnostic) -> Report {
+        Report {
+            fname: String::from(fname),
+            source: String::from(lexer.source()),
+            diagnostic,
+        }
+    }
+}
+
+impl SourceFile {
+    fn report(&self, diagnostic: Diagnostic) -> Report {
+        Report {
+            fname: self.fname.clone(),
+            source: self.source.clone(),
+            diagnostic,
+        }
+    }
+}
+
+// Reports of the errors found in a file, if any
+fn reports(fname: &str, lexer: &Lexer, errors: Vec<Diagnostic>) -> Result<(), Vec<Report>> {
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors
+            .into_iter()
+            .map(|d| Report::new(fname, lexer, d))
+            .collect()),
+    }
+}
+
+fn tokens(lexer: &mut Lexer) -> (Vec<Token>, Vec<Diagnostic>) {
+    lexer.lex_partial()
+}
+
+// The tree of the file read by `lexer`, as far as it could be parsed, and
+// the lexical and syntax errors in it.
+fn parse(lexer: &Lexer) -> (Program, Vec<Diagnostic>) {
+    let (_, program, errors) = cst::parse(lexer.source());
+    (program, errors)
+}
+
+// Parses the file read by `lexer` and the files it imports. Every file
+// comes after the files it imports, and each is loaded once. Fails with
+// all the syntax errors of the first file having some.
+fn load(fname: String, lexer: Lexer) -> Result<Vec<SourceFile>, Vec<Report>> {
+    let mut files = Vec::new();
+    load_into(fname, lexer, &mut files, &mut Vec::new())?;
+    Ok(files)
+}
+
+// `loading` holds the files whose imports are being loaded, to find
+// cycles.
+fn load_into(
+    fname: String,
+    lexer: Lexer,
+    files: &mut Vec<SourceFile>,
+    loading: &mut Vec<String>,
+) -> Result<(), Vec<Report>> {
+    let (program, errors) = parse(&lexer);
+    reports(&fname, &lexer, errors)?;
+    loading.push(fname.clone());
+    for item in &program.items {
+        if let Item::Import { name, info } = item {
+            // imports are relative to the importing file
+            let dir = Path::new(&fname).parent().unwrap_or_else(|| Path::new(""));
+            let path = dir.join(format!("{}.lung", name));
+            let path = path.to_string_lossy().into_owned();
+            if files.iter().any(|f| f.fname == path) {
+                continue;
+            }
+            if loading.contains(&path) {
+                let d = Diagnostic::error("E0401", &format!("Import of `{}` is cyclic", name))
+                    .with_primary(info.clone())
+                    .with_note(&format!("{} is still importing this file", path));
+                return Err(vec![Report::new(&fname, &lexer, d)]);
+            }
+            match Lexer::from_file(&path) {
+                Ok(l) => load_into(path, l, files, loading)?,
+                Err(e) => {
+                    let d = Diagnostic::error("E0400", &format!("Could not import `{}`", name))
+                        .with_primary(info.clone())
+                        .with_note(&format!("{}: {}", path, e));
+                    return Err(vec![Report::new(&fname, &lexer, d)]);
+                }
+            }
+        }
+    }
+    loading.pop();
+    files.push(SourceFile {
+        fname,
+        source: String::from(lexer.source()),
+        program,
+    });
+    Ok(())
+}
+
+// Types the files in order in one context, so that each sees the items of
+// the files loaded before it. Returns the bindings of the last file.
+fn check(
+    files: &[SourceFile],
+    warnings: &mut Vec<Report>,
+) -> Result<(typing::Context, Vec<(String, type_def::Scheme)>), Report> {
+    let mut cxt = typing::Context::new();
+    let mut bindings = Vec::new();
+    for f in files {
+        let checked = cxt.check_program(&f.program);
+        warnings.extend(cxt.take_warnings().into_iter().map(|d| f.report(d)));
+        bindings = checked.map_err(|d| f.report(d))?;
+    }
+    Ok((cxt, bindings))
+}
+
+fn run(files: &[SourceFile], warnings: &mut Vec<Report>) -> Result<eval::Value, Report> {
+    let (mut cxt, _) = check(files, warnings)?;
+    let (main_file, imported) = match files.split_last() {
+        Some(f) => f,
+        None => return Ok(eval::Value::Unit),
+    };
+    cxt.check_main(&main_file.program)
+        .map_err(|d| main_file.report(d))?;
+    let mut env = eval::Env::new();
+    for f in imported {
+        env = f.program.define(&env).map_err(|d| f.report(d))?;
+    }
+    main_file.program.run(&env).map_err(|d| main_file.report(d))
+}
+
+fn exec(cmd: Command) -> i32 {
+    let file = match &cmd {
+        Command::Help => {
+            println!("{}", USAGE);
+            return EXIT_OK;
+        }
+        Command::Repl => {
+            return match repl::Repl::new().run() {
+                Ok(()) => EXIT_OK,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    EXIT_USAGE
+                }
+            };
+        }
+        Command::Lsp => {
+            let (stdin, stdout) = (std::io::stdin(), std::io::stdout());
+            return match lsp::Server::new(stdin.lock(), stdout.lock()).run() {
+                // exiting without a shutdown request is a failure
+                Ok(true) => EXIT_OK,
+                Ok(false) => EXIT_FAILURE,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    EXIT_USAGE
+                }
+            };
+        }
+        Command::Tokens(f)
+        | Command::Parse(f)
+        | Command::Tree(f)
+        | Command::Check(f)
+        | Command::Run(f)
+        | Command::Fmt { file: f, .. } => f,
+    };
+    let mut lexer = match open_source(file) {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_USAGE;
+        }
+    };
+    let fname = match file {
+        Some(f) => f.clone(),
+        None => String::from("<stdin>"),
+    };
+    let mut warnings = Vec::new();
+    let result = match cmd {
+        // the tokens and the tree are printed even with errors, as far as
+        // they go
+        Command::Tokens(_) => {
+            let (ts, errors) = tokens(&mut lexer);
+            for t in ts {
+                println!("{}\t{:?}", t.info, t.kind);
+            }
+            reports(&fname, &lexer, errors)
+        }
+        Command::Parse(_) => {
+            let (p, errors) = parse(&lexer);
+            println!("{:#?}", p);
+            reports(&fname, &lexer, errors)
+        }
+        Command::Tree(_) => {
+            let (tree, _, errors) = cst::parse(lexer.source());
+            print!("{}", tree.dump());
+            reports(&fname, &lexer, errors)
+        }
+        Command::Check(_) => load(fname, lexer)
+            .and_then(|files| check(&files, &mut warnings).map_err(|r| vec![r]))
+            .map(|(_, bindings)| {
+                for (name, scheme) in bindings {
+                    println!("{}: {}", name, scheme);
+                }
+            }),
+        Command::Run(_) => load(fname, lexer)
+            .and_then(|files| run(&files, &mut warnings).map_err(|r| vec![r]))
+            .map(|v| match v {
+                // `fn main() -> Unit` prints nothing
+                eval::Value::Unit => (),
+                v => println!("{}", v),
+            }),
+        Command::Fmt { check, width, .. } => match format::format(lexer.source(), width) {
+            Err(errors) => reports(&fname, &lexer, errors),
+            Ok(out) if check && out != lexer.source() => {
+                eprintln!("{} is not formatted", fname);
+                return EXIT_FAILURE;
+            }
+            Ok(_) if check => Ok(()),
+            Ok(out) => match file {
+                // a formatted file is left untouched
+                Some(_) if out == lexer.source() => Ok(()),
+                Some(f) => {
+                    if let Err(e) = std::fs::write(f, out) {
+                        eprintln!("{}: {}", f, e);
+                        return EXIT_USAGE;
+                    }
+                    Ok(())
+                }
+                None => {
+                    print!("{}", out);
+                    Ok(())
+                }
+            },
+        },
+        Command::Repl | Command::Lsp | Command::Help => Ok(()),
+    };
+    let color = render::use_color(std::io::stderr().is_terminal());
+    let render = |r: &Report| {
+        let renderer = render::Renderer::new(&r.fname, &r.source, color);
+        eprint!("{}", renderer.render(&r.diagnostic));
+    };
+    warnings.iter().for_each(render);
+    match result {
+        Ok(()) => EXIT_OK,
+        Err(reports) => {
+            reports.iter().for_each(render);
+            EXIT_FAILURE
+        }
+    }
+}
+
 fn main() {
-    let lexer = lexer::Lexer::from_file("test.txt");
+    let code = match Command::from_args(std::env::args().skip(1).collect()) {
+        // evaluation takes native stack for every call, more than the main
+        // thread has
+        Ok(cmd) => thread::Builder::new()
+            .stack_size(eval::STACK_SIZE)
+            .spawn(move || exec(cmd))
+            .map(|t| t.join().unwrap_or(EXIT_PANIC))
+            .unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                EXIT_USAGE
+            }),
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            EXIT_USAGE
+        }
+    };
+    process::exit(code);
 }
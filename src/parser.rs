@@ -1,16 +1,368 @@
-use crate::lexer::*;
+use crate::cst::{Event, SyntaxKind};
+use crate::diagnostic::Diagnostic;
 use crate::syntax::*;
 use crate::type_def::*;
 
 #[cfg(test)]
+#[allow(clippy::items_after_test_module)]
 mod parser_test {
     use super::*;
+    use crate::cst::{self, parse_expr};
+
+    fn parse_str(src: &str) -> Result<Program, Diagnostic> {
+        cst::parse_program(src)
+    }
+
+    // the items read from `src` and the syntax errors recovered from
+    fn parse_errors(src: &str) -> (Program, Vec<String>) {
+        let (_, program, errors) = cst::parse(src);
+        let errors = errors
+            .iter()
+            .map(|d| format!("{} {} {}", d.code, d.primary.as_ref().unwrap(), d.message))
+            .collect();
+        (program, errors)
+    }
 
     #[test]
     fn test() {
-        let mut lexer = Lexer::from_file("src/test/test_parser.txt").unwrap();
-        let mut parser = Parser::new(lexer.lex().unwrap());
-        parser.parse_program().unwrap();
+        let src = std::fs::read_to_string("src/test/test_parser.txt").unwrap();
+        let program = parse_str(&src).unwrap();
+        assert!(matches!(
+            program.items.as_slice(),
+            [Item::TypeAlias { .. }, Item::Const(_), Item::Func(_)]
+        ));
+    }
+
+    #[test]
+    fn test_program() {
+        let program = parse_str("import util; fn f() { 1 }; fn g() { 2 } let x = 1;").unwrap();
+        assert_eq!(program.items.len(), 4);
+        assert!(parse_str("").unwrap().items.is_empty());
+        // every token must belong to an item
+        let d = parse_str("fn main() { 1 } o").unwrap_err();
+        assert_eq!(d.primary.as_ref().unwrap().to_string(), "1:17-1:17");
+        assert!(parse_str("1 + 2").is_err());
+        assert!(parse_str("let x = 1").is_err());
+        assert!(parse_str("type T I32;").is_err());
+    }
+
+    #[test]
+    fn test_recovery() {
+        let src = "fn f(x y) { x }
+fn g() { let = 1; 2 + ; h(1 2, 3) }
+struct S { x I32 }
+fn main() { g() }";
+        let (_, program, errors) = cst::parse(src);
+        let errors: Vec<String> = errors
+            .iter()
+            .map(|d| format!("{} {}", d.primary.as_ref().unwrap(), d.message))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "1:8-1:8 Expected [COMMA,RPAREN]",
+                "2:14-2:14 Expected PATTERN",
+                "2:23-2:23 Expected EXPR",
+                "2:29-2:29 Expected [COMMA,RPAREN]",
+                "3:14-3:16 Expected COLON",
+            ]
+        );
+        assert!(matches!(
+            program.items.as_slice(),
+            [
+                Item::Func(_),
+                Item::Func(_),
+                Item::Error { .. },
+                Item::Func(_)
+            ]
+        ));
+        match &program.items[1] {
+            Item::Func(f) => match &**f {
+                Expr::NamedFunc { block, .. } => match &**block {
+                    Expr::Block { exprs, .. } => {
+                        assert!(matches!(*exprs[0], Expr::Error { .. }));
+                        assert!(matches!(*exprs[1], Expr::Error { .. }));
+                        assert_eq!(exprs[0].info().to_string(), "2:10-2:16");
+                    }
+                    e => panic!("{:?}", e),
+                },
+                e => panic!("{:?}", e),
+            },
+            i => panic!("{:?}", i),
+        }
+        // an Error token has been reported by the lexer
+        let (_, _, errors) = cst::parse("fn f(@) { 1 + @ }");
+        let codes: Vec<&str> = errors.iter().map(|d| d.code).collect();
+        assert_eq!(codes, vec!["E0001", "E0001"]);
+        // a stray bracket is reported once
+        let (_, program, errors) = cst::parse("fn f() { a) }\nfn main() { }");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(program.items.len(), 2);
+    }
+
+    #[test]
+    fn test_resync() {
+        // items start outside of braces, wherever they are on their line,
+        // and a `let` at the start of a line in a block is a statement
+        let src = "fn f() {
+  1 2
+let x = 1;
+  x
+}
+  struct S { x I32 }
+  fn main() { f() }";
+        let (program, errors) = parse_errors(src);
+        assert_eq!(
+            errors,
+            vec![
+                "E0100 2:5-2:5 Expected [SEMICOLON,RBRACE]",
+                "E0100 6:16-6:18 Expected COLON",
+            ]
+        );
+        match program.items.as_slice() {
+            [Item::Func(f), Item::Error { info }, Item::Func(_)] => {
+                assert_eq!(info.to_string(), "6:3-6:20");
+                match &**f {
+                    Expr::NamedFunc { block, .. } => match &**block {
+                        Expr::Block { exprs, .. } => assert_eq!(exprs.len(), 2),
+                        e => panic!("{:?}", e),
+                    },
+                    e => panic!("{:?}", e),
+                }
+            }
+            items => panic!("{:?}", items),
+        }
+    }
+
+    #[test]
+    fn test_skip_to_item() {
+        // a skip stops at the next item, where the error of the enclosing
+        // rules is the same
+        let src = "fn g( { 1 }\nfn h() -> I32 { 1 }\nfn main() { 1 + }";
+        let (program, errors) = parse_errors(src);
+        assert_eq!(
+            errors,
+            vec![
+                "E0100 1:7-1:7 Expected PATTERN",
+                "E0100 3:17-3:17 Expected EXPR",
+            ]
+        );
+        match program.items.as_slice() {
+            [Item::Error { info }, Item::Func(h), Item::Func(_)] => {
+                assert_eq!(info.to_string(), "1:1-1:11");
+                assert!(matches!(&**h, Expr::NamedFunc { name, .. } if name == "h"));
+            }
+            items => panic!("{:?}", items),
+        }
+    }
+
+    #[test]
+    fn test_match_arm_recovery() {
+        // a match arm that cannot be read is an error arm, and the arms
+        // after it are read
+        let src = "fn main() { match 1 { 99999999999 => 1, _ => 0 } }";
+        let (program, errors) = parse_errors(src);
+        assert_eq!(
+            errors,
+            vec!["E0102 1:23-1:33 Number literal out of range for I32"]
+        );
+        match &program.items[..] {
+            [Item::Func(f)] => match &**f {
+                Expr::NamedFunc { block, .. } => match &**block {
+                    Expr::Block { exprs, .. } => match &*exprs[0] {
+                        Expr::Match { arms, .. } => {
+                            assert_eq!(arms.len(), 2);
+                            assert!(matches!(*arms[0].body, Expr::Error { .. }));
+                            assert_eq!(arms[0].body.info().to_string(), "1:23-1:38");
+                            assert!(matches!(arms[1].pattern, Pattern::Wildcard { .. }));
+                        }
+                        e => panic!("{:?}", e),
+                    },
+                    e => panic!("{:?}", e),
+                },
+                e => panic!("{:?}", e),
+            },
+            i => panic!("{:?}", i),
+        }
+    }
+
+    #[test]
+    fn test_error_token_at_end() {
+        // the lexer reports a string running up to the end of the input,
+        // which the parser does not report again as unexpected
+        let src = "fn main() { 1 }\nfn f() { \"abc";
+        let (program, errors) = parse_errors(src);
+        assert_eq!(errors, vec!["E0004 2:10-2:10 Unterminated string literal"]);
+        match program.items.as_slice() {
+            [Item::Func(_), Item::Error { info }] => assert_eq!(info.to_string(), "2:1-2:13"),
+            items => panic!("{:?}", items),
+        }
+    }
+
+    #[test]
+    fn test_struct() {
+        let src = "struct Point { x: I32, y: I32 }
+            fn main() { let p = Point { x: 1, y: 2 }; if p.x < p.y { p } else { Point {} } }";
+        let program = parse_str(src).unwrap();
+        match &program.items[0] {
+            Item::Struct { fields, .. } => assert_eq!(fields.len(), 2),
+            i => panic!("{:?}", i),
+        }
+        // in the condition of an if the brace opens the block
+        let expr = parse_expr("if p { Point { x: 1 }.x } else { 0 }").unwrap();
+        match *expr {
+            Expr::If { cond, .. } => assert!(matches!(*cond, Expr::Var { .. })),
+            e => panic!("{:?}", e),
+        }
+        assert!(parse_str("struct P { x }").is_err());
+        assert!(parse_str("fn main() { P { x 1 } }").is_err());
+    }
+
+    #[test]
+    fn test_tuple() {
+        // parens group a single expression
+        assert!(matches!(*parse_expr("(1)").unwrap(), Expr::Int { .. }));
+        match *parse_expr("(1, (2, 3)).1.0").unwrap() {
+            Expr::TupleField { expr, index, .. } => {
+                assert_eq!(index, 0);
+                assert!(matches!(*expr, Expr::TupleField { index: 1, .. }));
+            }
+            e => panic!("{:?}", e),
+        }
+        match *parse_expr("let (a, (b, _)): (I32, (Fn(I32) -> I32, (Bool))) = t").unwrap() {
+            Expr::Let { pattern, vtype, .. } => {
+                assert_eq!(pattern.to_string(), "(a, (b, _))");
+                assert_eq!(
+                    vtype.unwrap().ty.to_string(),
+                    "(I32, (Fn(I32) -> I32, Bool))"
+                );
+            }
+            e => panic!("{:?}", e),
+        }
+        assert!(parse_expr("function((x, y), z) { x }").is_ok());
+        assert!(parse_expr("()").is_err());
+        assert!(parse_expr("(1, )").is_err());
+        assert!(parse_expr("let x: () = unit").is_err());
+        assert!(parse_expr("t.-1").is_err());
+    }
+
+    #[test]
+    fn test_number() {
+        let literals = [
+            ("1_000", 1000, Type::I32),
+            ("0xff_u8", 255, Type::U8),
+            ("0o17", 15, Type::I32),
+            ("0b1010i64", 10, Type::I64),
+            ("4_294_967_295u32", 4294967295, Type::U32),
+            ("-2147483648", -2147483648, Type::I32),
+        ];
+        for (src, n, t) in literals.iter() {
+            match *parse_expr(src).unwrap() {
+                Expr::Int { val, ty, .. } => assert_eq!((val, ty), (*n, t.clone()), "{}", src),
+                e => panic!("{:?}", e),
+            }
+        }
+        match *parse_expr("-x as I64 * 2i64").unwrap() {
+            Expr::BinOp { lhs, .. } => assert!(matches!(*lhs, Expr::Cast { .. })),
+            e => panic!("{:?}", e),
+        }
+        let errors = [
+            ("99999999999", "E0102"),
+            ("2147483648", "E0102"),
+            ("256u8", "E0102"),
+            ("-1u8", "E0102"),
+            ("0xfg", "E0101"),
+            ("0b102", "E0101"),
+            ("1u16", "E0101"),
+            ("0x", "E0101"),
+            ("match x { -129u8 => 1 }", "E0102"),
+        ];
+        for (src, code) in errors.iter() {
+            assert_eq!(parse_expr(src).unwrap_err().code, *code, "{}", src);
+        }
+        let d = parse_expr("300u8").unwrap_err();
+        assert_eq!(d.notes, vec!["values of type U8 range from 0 to 255"]);
+    }
+
+    #[test]
+    fn test_float() {
+        let literals = [
+            ("1.5", 1.5),
+            ("2e3", 2000.0),
+            ("1_000.25", 1000.25),
+            ("-0.5E-1", -0.05),
+            ("3f64", 3.0),
+            ("1.5e+2_f64", 150.0),
+        ];
+        for (src, x) in literals.iter() {
+            match *parse_expr(src).unwrap() {
+                Expr::Float { val, .. } => assert_eq!(val, *x, "{}", src),
+                e => panic!("{:?}", e),
+            }
+        }
+        match *parse_expr("t.0.1").unwrap() {
+            Expr::TupleField { expr, index, .. } => {
+                assert_eq!(index, 1);
+                assert!(matches!(*expr, Expr::TupleField { index: 0, .. }));
+            }
+            e => panic!("{:?}", e),
+        }
+        let errors = [
+            ("1.5u8", "E0101"),
+            ("0b1f64", "E0101"),
+            ("1e", "E0101"),
+            ("1e400", "E0102"),
+            ("match x { 1.5 => 1 }", "E0100"),
+        ];
+        for (src, code) in errors.iter() {
+            assert_eq!(parse_expr(src).unwrap_err().code, *code, "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_doc() {
+        let src = "/// Adds one.
+            /// Really.
+            fn inc(x) {
+                /// ignored inside a block
+                x + 1
+            }
+            // not a doc comment
+            struct P { }
+            /// A point.
+            struct Q { }";
+        let program = parse_str(src).unwrap();
+        assert_eq!(program.doc(0), Some("Adds one.\nReally."));
+        assert_eq!(program.doc(1), None);
+        assert_eq!(program.doc(2), Some("A point."));
+    }
+
+    #[test]
+    fn test_generics() {
+        let src = "enum Option<T> { Some(T), None }
+            struct Pair<A, B> { a: A, b: Option<Option<B>> }
+            fn id<T>(x: T) -> T { function<U>(y: U) { y }(x) }";
+        let program = parse_str(src).unwrap();
+        match &program.items[1] {
+            Item::Struct {
+                type_params,
+                fields,
+                ..
+            } => {
+                assert_eq!(type_params, &vec!["A", "B"]);
+                assert_eq!(fields[1].ty.ty.to_string(), "Option<Option<B>>");
+            }
+            i => panic!("{:?}", i),
+        }
+        match &program.items[2] {
+            Item::Func(f) => assert!(
+                matches!(&**f, Expr::NamedFunc { type_params, .. } if type_params.len() == 1)
+            ),
+            i => panic!("{:?}", i),
+        }
+        assert!(parse_str("fn f<>() { }").is_err());
+        assert!(parse_str("fn f<T() { }").is_err());
+        assert!(parse_str("fn f(x: Option<I32) { }").is_err());
     }
 }
 
@@ -18,6 +370,23 @@ pub struct Parser {
     tokens: std::vec::IntoIter<Token>,
     ctk: TokenKind,
     cti: TokenInfo,
+    // whether the last consumed token is an Error token
+    pt_error: bool,
+    // the number of braces opened and not closed before the current token
+    brace_depth: usize,
+    // whether `Name {` starts a struct literal. It does not in the
+    // condition of an if, where the brace opens the then block.
+    struct_lit: bool,
+    // syntax errors recovered from so far
+    errors: Vec<Diagnostic>,
+    // the start of an item where a skip stopped, which the rules around
+    // the skipped tokens cannot go on with either
+    skipped_to: Option<TokenInfo>,
+    // what has been read, for the concrete syntax tree
+    events: Vec<Event>,
+    // tokens taken from the stream but not read yet: the current one and
+    // the doc comments before it
+    pending: usize,
 }
 
 impl Parser {
@@ -25,74 +394,591 @@ impl Parser {
         Parser {
             tokens: tokens.into_iter(),
             ctk: TokenKind::EOF,
-            cti: TokenInfo {
-                s_col: 0,
-                s_row: 0,
-                e_col: 0,
-                e_row: 0,
-            },
+            cti: TokenInfo::default(),
+            pt_error: false,
+            brace_depth: 0,
+            struct_lit: true,
+            errors: Vec::new(),
+            skipped_to: None,
+            events: Vec::new(),
+            pending: 0,
+        }
+    }
+
+    // The events of what has been read, from which the concrete syntax
+    // tree of the tokens is built.
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    // Where a node starting at the current token would start, which is
+    // only known once read.
+    fn mark(&mut self) -> usize {
+        self.events.push(Event::Start {
+            kind: None,
+            forward_parent: None,
+        });
+        self.events.len() - 1
+    }
+
+    // Makes what has been read since `mark` a node of `kind`. Nodes are
+    // only made once read, so that a failed rule leaves none open. Where a
+    // node has been made at `mark` already the new one goes around it,
+    // and its mark is returned for the next one around both.
+    fn wrap(&mut self, mark: usize, kind: SyntaxKind) -> usize {
+        let end = self.events.len();
+        let mut start = mark;
+        let made = loop {
+            match &mut self.events[start] {
+                Event::Start { kind: k @ None, .. } => {
+                    *k = Some(kind);
+                    break start;
+                }
+                Event::Start {
+                    forward_parent: Some(parent),
+                    ..
+                } => start = *parent,
+                Event::Start { forward_parent, .. } => {
+                    *forward_parent = Some(end);
+                    break end;
+                }
+                _ => unreachable!(),
+            }
+        };
+        if made == end {
+            self.events.push(Event::Start {
+                kind: Some(kind),
+                forward_parent: None,
+            });
+        }
+        self.events.push(Event::Finish);
+        made
+    }
+
+    // makes what has been skipped since `mark` an Error node, if anything
+    fn wrap_error(&mut self, mark: usize) {
+        if self.events[mark..].iter().rev().any(|e| *e == Event::Token) {
+            self.wrap(mark, SyntaxKind::Error);
         }
     }
 
-    fn make_error(&mut self, expectation: &str) -> String {
+    fn make_error(&mut self, expectation: &str) -> Diagnostic {
         // Lung parser use form of Error like the following
-        // Error at [s_row:s_col-e_row:e_col] : Expected ~~
-        let msg = format!(
-            "Error at {} : Expected {}",
-            self.cti.to_string(),
-            expectation
-        );
-        String::from(msg)
+        // Expected ~~ at [s_row:s_col-e_row:e_col]
+        let found = match self.ctk {
+            TokenKind::EOF => String::from("EOF"),
+            ref t => format!("{:?}", t),
+        };
+        Diagnostic::error("E0100", &format!("Expected {}", expectation))
+            .with_primary(self.cti.clone())
+            .with_note(&format!("found {}", found))
     }
 
-    pub fn parse_program(&mut self) -> Result<Box<Expr>, String> {
+    // Reads top level items up to the end of the input, going on after
+    // syntax errors, and returns every error. The parts that could not be
+    // read are Error nodes of the tree.
+    pub fn parse_program(&mut self) -> Vec<Diagnostic> {
         self.next_token();
-        self.read_expr()
+        while !self.ct_check(TokenKind::EOF) {
+            let start = self.cti.clone();
+            let mark = self.mark();
+            if let Err(d) = self.read_item() {
+                self.report(d);
+                // the item keyword itself starts no other item
+                if self.cti == start {
+                    self.next_token();
+                }
+                self.skip_to_item();
+                self.wrap_error(mark);
+            }
+        }
+        // the doc comments before the EOF belong to no item, but are read
+        // all the same
+        self.emit_pending();
+        std::mem::take(&mut self.errors)
     }
 
-    fn next_token(&mut self) {
-        match self.tokens.next() {
-            Some(Token {
-                kind: TokenKind::EOF,
-                info: _,
-            }) => {
-                self.next_token();
+    // Reads one expression which must span the whole input, optionally
+    // followed by a semicolon, failing with the first syntax error. Used
+    // by the REPL.
+    pub fn parse_expr(&mut self) -> Result<(), Diagnostic> {
+        self.next_token();
+        let read = self.read_stmt();
+        if !self.errors.is_empty() {
+            return Err(self.errors.remove(0));
+        }
+        read?;
+        if self.ct_check(TokenKind::SemiColon) {
+            self.next_token();
+        }
+        if !self.ct_check(TokenKind::EOF) {
+            return Err(self.make_error("EOF"));
+        }
+        self.emit_pending();
+        Ok(())
+    }
+
+    // Records a syntax error to go on after. An error where the last one
+    // was is the same mistake seen again by an enclosing rule, like the
+    // block around a statement that could not be recovered from, and so
+    // is one at the item a skip stopped at. An Error token has been
+    // reported by the lexer already, and so has the end of the input when
+    // an Error token runs up to it, like an unterminated string.
+    fn report(&mut self, d: Diagnostic) {
+        if self.ctk == TokenKind::Error && d.primary.as_ref() == Some(&self.cti) {
+            return;
+        }
+        if self.ctk == TokenKind::EOF && self.pt_error {
+            return;
+        }
+        if self.skipped_to.is_some() && d.primary == self.skipped_to {
+            return;
+        }
+        if self.errors.last().map(|e| &e.primary) != Some(&d.primary) {
+            self.errors.push(d);
+        }
+    }
+
+    // Skips tokens up to one of `stops` outside of the brackets opened on
+    // the way. A closing bracket which is not a stop ends the skip too,
+    // since it closes some enclosing rule, and so do EOF and the start of
+    // the next top level item.
+    fn skip_to(&mut self, stops: &[TokenKind]) {
+        let mut depth = 0;
+        loop {
+            match self.ctk {
+                TokenKind::EOF => return,
+                _ if self.at_item() => {
+                    self.skipped_to = Some(self.cti.clone());
+                    return;
+                }
+                ref t if depth == 0 && stops.contains(t) => return,
+                TokenKind::RParen | TokenKind::RBrace if depth == 0 => return,
+                TokenKind::LParen | TokenKind::LBrace => depth += 1,
+                TokenKind::RParen | TokenKind::RBrace => depth -= 1,
+                _ => (),
+            }
+            self.next_token();
+        }
+    }
+
+    // Whether the current token starts a top level item, or is EOF. The
+    // keywords of items only count outside of braces, where `fn` and `let`
+    // do not start an element of a block.
+    fn at_item(&self) -> bool {
+        match self.ctk {
+            TokenKind::EOF => true,
+            TokenKind::Func
+            | TokenKind::Let
+            | TokenKind::Struct
+            | TokenKind::Enum
+            | TokenKind::Type
+            | TokenKind::Import => self.brace_depth == 0,
+            _ => false,
+        }
+    }
+
+    // skips tokens up to the next top level item
+    fn skip_to_item(&mut self) {
+        while !self.at_item() {
+            self.next_token();
+        }
+    }
+
+    // Runs `read`, which should stop at one of `stops`. When it fails the
+    // error is reported and the tokens up to the next stop are skipped
+    // into an Error node, in place of what `read` left, or after what it
+    // read when it only stopped early.
+    fn read_until(
+        &mut self,
+        stops: &[TokenKind],
+        read: impl FnOnce(&mut Parser) -> Result<(), Diagnostic>,
+    ) {
+        let mark = self.mark();
+        let ret = read(self);
+        let read_to = self.mark();
+        let at_stop = stops.contains(&self.ctk) || self.ctk == TokenKind::EOF;
+        let skipped_from = match ret {
+            Ok(()) if at_stop => return,
+            Ok(()) => {
+                let expectation: Vec<String> = stops.iter().map(|s| format!("{:?}", s)).collect();
+                let d = self.make_error(&format!("[{}]", expectation.join(",").to_uppercase()));
+                self.report(d);
+                read_to
+            }
+            Err(d) => {
+                self.report(d);
+                mark
             }
-            Some(t) => {
-                self.ctk = t.kind;
-                self.cti = t.info;
+        };
+        self.skip_to(stops);
+        self.wrap_error(skipped_from);
+    }
+
+    // Doc comments are read along with the token they precede, the parser
+    // never sees them otherwise.
+    fn next_token(&mut self) {
+        self.pt_error = self.ctk == TokenKind::Error;
+        match self.ctk {
+            TokenKind::LBrace => self.brace_depth += 1,
+            TokenKind::RBrace => self.brace_depth = self.brace_depth.saturating_sub(1),
+            _ => (),
+        }
+        self.emit_pending();
+        loop {
+            let token = self.tokens.next();
+            if token.is_some() {
+                self.pending += 1;
             }
-            None => {
-                self.ctk = TokenKind::EOF;
+            match token {
+                Some(Token {
+                    kind: TokenKind::EOF | TokenKind::DocComment(_),
+                    info: _,
+                }) => (),
+                Some(t) => {
+                    self.ctk = t.kind;
+                    self.cti = t.info;
+                    break;
+                }
+                None => {
+                    self.ctk = TokenKind::EOF;
+                    break;
+                }
             }
         }
     }
 
+    // Reads the tokens taken from the stream so far. Every token, skipped
+    // after a syntax error or not, is read this way, so that it goes to
+    // the node being read.
+    fn emit_pending(&mut self) {
+        for _ in 0..self.pending {
+            self.events.push(Event::Token);
+        }
+        self.pending = 0;
+    }
+
     fn ct_check(&mut self, token: TokenKind) -> bool {
         match &self.ctk {
-            TokenKind::Num(_) => match token {
-                TokenKind::Num(_) => true,
-                _ => false,
-            },
-            TokenKind::Ident(_) => match token {
-                TokenKind::Ident(_) => true,
-                _ => false,
-            },
+            TokenKind::Num(_) => matches!(token, TokenKind::Num(_)),
+            TokenKind::Ident(_) => matches!(token, TokenKind::Ident(_)),
+            TokenKind::Str(_) => matches!(token, TokenKind::Str(_)),
+            TokenKind::Char(_) => matches!(token, TokenKind::Char(_)),
             t => t == &token,
         }
     }
 
-    fn read_args(&mut self) -> Result<Vec<Box<Expr>>, String> {
-        let mut tmp = Vec::new();
-        if self.ctk == TokenKind::LParen {
+    fn read_item(&mut self) -> Result<(), Diagnostic> {
+        let mark = self.mark();
+        let kind = match self.ctk {
+            TokenKind::Func => {
+                self.next_token();
+                self.read_named_func()?;
+                let func = self.wrap(mark, SyntaxKind::NamedFunc);
+                // like in a block, a function may be followed by a semicolon
+                if self.ct_check(TokenKind::SemiColon) {
+                    self.next_token();
+                }
+                self.wrap(func, SyntaxKind::FuncItem);
+                return Ok(());
+            }
+            TokenKind::Struct => {
+                self.next_token();
+                self.read_ident()?;
+                self.read_type_params()?;
+                if !self.ct_check(TokenKind::LBrace) {
+                    return Err(self.make_error("LBRACE"));
+                }
+                self.next_token();
+                self.read_field_decls()?;
+                if self.ct_check(TokenKind::SemiColon) {
+                    self.next_token();
+                }
+                self.wrap(mark, SyntaxKind::StructItem);
+                return Ok(());
+            }
+            TokenKind::Enum => {
+                self.next_token();
+                self.read_ident()?;
+                self.read_type_params()?;
+                if !self.ct_check(TokenKind::LBrace) {
+                    return Err(self.make_error("LBRACE"));
+                }
+                self.next_token();
+                self.read_variant_decls()?;
+                if self.ct_check(TokenKind::SemiColon) {
+                    self.next_token();
+                }
+                self.wrap(mark, SyntaxKind::EnumItem);
+                return Ok(());
+            }
+            TokenKind::Let => {
+                self.read_stmt()?;
+                SyntaxKind::ConstItem
+            }
+            TokenKind::Type => {
+                self.next_token();
+                self.read_ident()?;
+                if !self.ct_check(TokenKind::Assign) {
+                    return Err(self.make_error("ASSIGN"));
+                }
+                self.next_token();
+                self.read_type()?;
+                SyntaxKind::TypeAliasItem
+            }
+            TokenKind::Import => {
+                self.next_token();
+                self.read_ident()?;
+                SyntaxKind::ImportItem
+            }
+            _ => return Err(self.make_error("[FN,STRUCT,ENUM,LET,TYPE,IMPORT]")),
+        };
+        if !self.ct_check(TokenKind::SemiColon) {
+            return Err(self.make_error("SEMICOLON"));
+        }
+        self.next_token();
+        self.wrap(mark, kind);
+        Ok(())
+    }
+
+    // the fields of a struct declaration, after the LBRACE
+    fn read_field_decls(&mut self) -> Result<(), Diagnostic> {
+        if self.ct_check(TokenKind::RBrace) {
+            self.next_token();
+            return Ok(());
+        }
+        loop {
+            let mark = self.mark();
+            self.read_ident()?;
+            if !self.ct_check(TokenKind::Colon) {
+                return Err(self.make_error("COLON"));
+            }
+            self.next_token();
+            self.read_type()?;
+            self.wrap(mark, SyntaxKind::FieldDecl);
+            match self.ctk {
+                TokenKind::RBrace => {
+                    self.next_token();
+                    break;
+                }
+                TokenKind::Comma => self.next_token(),
+                _ => return Err(self.make_error("[COMMA,RBRACE]")),
+            }
+        }
+        Ok(())
+    }
+
+    // the variants of an enum declaration, after the LBRACE
+    fn read_variant_decls(&mut self) -> Result<(), Diagnostic> {
+        if self.ct_check(TokenKind::RBrace) {
             self.next_token();
-            return Ok(tmp);
+            return Ok(());
         }
         loop {
-            if !Parser::lead_expr(self.ctk.clone()) {
-                return Err(self.make_error("EXPR"));
+            let mark = self.mark();
+            self.read_ident()?;
+            if self.ct_check(TokenKind::LParen) {
+                self.next_token();
+                loop {
+                    self.read_type()?;
+                    match self.ctk {
+                        TokenKind::Comma => self.next_token(),
+                        TokenKind::RParen => break,
+                        _ => return Err(self.make_error("[COMMA,RPAREN]")),
+                    }
+                }
+                self.next_token();
             }
-            tmp.push(self.read_expr()?);
+            self.wrap(mark, SyntaxKind::VariantDecl);
+            match self.ctk {
+                TokenKind::RBrace => {
+                    self.next_token();
+                    break;
+                }
+                TokenKind::Comma => self.next_token(),
+                _ => return Err(self.make_error("[LPAREN,COMMA,RBRACE]")),
+            }
+        }
+        Ok(())
+    }
+
+    // after the `match` keyword
+    fn read_match(&mut self) -> Result<(), Diagnostic> {
+        self.with_struct_lit(false, |p| p.read_expr())?;
+        if !self.ct_check(TokenKind::LBrace) {
+            return Err(self.make_error("LBRACE"));
+        }
+        self.next_token();
+        if self.ct_check(TokenKind::RBrace) {
+            self.next_token();
+            return Ok(());
+        }
+        loop {
+            self.read_until(&[TokenKind::Comma, TokenKind::RBrace], |p| {
+                let mark = p.mark();
+                p.read_pattern()?;
+                if !p.ct_check(TokenKind::FatArrow) {
+                    return Err(p.make_error("FATARROW"));
+                }
+                p.next_token();
+                p.with_struct_lit(true, |p| p.read_expr())?;
+                p.wrap(mark, SyntaxKind::MatchArm);
+                Ok(())
+            });
+            match self.ctk {
+                TokenKind::RBrace => {
+                    self.next_token();
+                    break;
+                }
+                TokenKind::Comma => self.next_token(),
+                _ => return Err(self.make_error("[COMMA,RBRACE]")),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_pattern(&mut self) -> Result<(), Diagnostic> {
+        let start = self.cti.clone();
+        let mark = self.mark();
+        let kind = match self.ctk.clone() {
+            TokenKind::Ident(name) if name.starts_with(char::is_uppercase) => {
+                self.next_token();
+                if self.ct_check(TokenKind::LParen) {
+                    self.next_token();
+                    loop {
+                        self.read_pattern()?;
+                        match self.ctk {
+                            TokenKind::Comma => self.next_token(),
+                            TokenKind::RParen => break,
+                            _ => return Err(self.make_error("[COMMA,RPAREN]")),
+                        }
+                    }
+                    self.next_token();
+                }
+                SyntaxKind::CtorPattern
+            }
+            TokenKind::Underscore
+            | TokenKind::Ident(_)
+            | TokenKind::True
+            | TokenKind::False
+            | TokenKind::UnitVal
+            | TokenKind::Char(_)
+            | TokenKind::Str(_) => {
+                self.next_token();
+                SyntaxKind::Pattern
+            }
+            TokenKind::Num(_) => {
+                self.read_int_pattern(false, start)?;
+                SyntaxKind::Pattern
+            }
+            TokenKind::Minus => {
+                self.next_token();
+                self.read_int_pattern(true, start)?;
+                SyntaxKind::Pattern
+            }
+            // a tuple, or a single pattern in parens
+            TokenKind::LParen => {
+                self.next_token();
+                let mut elems = 0;
+                loop {
+                    self.read_pattern()?;
+                    elems += 1;
+                    match self.ctk {
+                        TokenKind::Comma => self.next_token(),
+                        TokenKind::RParen => break,
+                        _ => return Err(self.make_error("[COMMA,RPAREN]")),
+                    }
+                }
+                self.next_token();
+                match elems {
+                    1 => SyntaxKind::ParenPattern,
+                    _ => SyntaxKind::TuplePattern,
+                }
+            }
+            _ => return Err(self.make_error("PATTERN")),
+        };
+        self.wrap(mark, kind);
+        Ok(())
+    }
+
+    // after the struct name
+    fn read_struct_lit(&mut self) -> Result<(), Diagnostic> {
+        // LBRACE
+        self.next_token();
+        if self.ct_check(TokenKind::RBrace) {
+            self.next_token();
+            return Ok(());
+        }
+        loop {
+            let mark = self.mark();
+            self.read_ident()?;
+            if !self.ct_check(TokenKind::Colon) {
+                return Err(self.make_error("COLON"));
+            }
+            self.next_token();
+            self.with_struct_lit(true, |p| p.read_expr())?;
+            self.wrap(mark, SyntaxKind::FieldInit);
+            match self.ctk {
+                TokenKind::RBrace => {
+                    self.next_token();
+                    break;
+                }
+                TokenKind::Comma => self.next_token(),
+                _ => return Err(self.make_error("[COMMA,RBRACE]")),
+            }
+        }
+        Ok(())
+    }
+
+    fn with_struct_lit<T>(&mut self, allowed: bool, read: impl FnOnce(&mut Parser) -> T) -> T {
+        let saved = std::mem::replace(&mut self.struct_lit, allowed);
+        let ret = read(self);
+        self.struct_lit = saved;
+        ret
+    }
+
+    // `<T, ..>` after the name of a generic declaration, if any
+    fn read_type_params(&mut self) -> Result<(), Diagnostic> {
+        if !self.ct_check(TokenKind::Lt) {
+            return Ok(());
+        }
+        let mark = self.mark();
+        self.next_token();
+        loop {
+            self.read_ident()?;
+            match self.ctk {
+                TokenKind::Comma => self.next_token(),
+                TokenKind::Gt => break,
+                _ => return Err(self.make_error("[COMMA,GT]")),
+            }
+        }
+        self.next_token();
+        self.wrap(mark, SyntaxKind::TypeParams);
+        Ok(())
+    }
+
+    fn read_ident(&mut self) -> Result<(), Diagnostic> {
+        match self.ctk {
+            TokenKind::Ident(_) => {
+                self.next_token();
+                Ok(())
+            }
+            _ => Err(self.make_error("IDENT")),
+        }
+    }
+
+    fn read_args(&mut self) -> Result<(), Diagnostic> {
+        if self.ctk == TokenKind::RParen {
+            self.next_token();
+            return Ok(());
+        }
+        loop {
+            self.read_until(&[TokenKind::Comma, TokenKind::RParen], |p| {
+                if !Parser::lead_expr(p.ctk.clone()) {
+                    return Err(p.make_error("EXPR"));
+                }
+                p.read_expr()
+            });
             match self.ctk {
                 TokenKind::RParen => break,
                 TokenKind::Comma => {
@@ -104,22 +990,31 @@ impl Parser {
             }
         }
         self.next_token();
-        Ok(tmp)
+        Ok(())
+    }
+
+    // a block from its LBRACE
+    fn read_block(&mut self) -> Result<(), Diagnostic> {
+        let mark = self.mark();
+        self.next_token();
+        self.with_struct_lit(true, |p| p.read_block_elems())?;
+        self.wrap(mark, SyntaxKind::Block);
+        Ok(())
     }
 
-    fn read_block(&mut self) -> Result<Box<Expr>, String> {
-        let mut exprs = vec![self.read_expr()?];
+    fn read_block_elems(&mut self) -> Result<(), Diagnostic> {
+        if self.ct_check(TokenKind::RBrace) {
+            self.next_token();
+            return Ok(());
+        }
+        let stops = [TokenKind::SemiColon, TokenKind::RBrace];
+        self.read_until(&stops, Parser::read_stmt);
 
         loop {
             match self.ctk {
                 TokenKind::SemiColon => {
                     self.next_token();
-                    exprs.push(match self.read_expr() {
-                        Ok(e) => e,
-                        error => {
-                            return error;
-                        }
-                    });
+                    self.read_until(&stops, Parser::read_stmt);
                 }
 
                 TokenKind::RBrace => {
@@ -129,27 +1024,46 @@ impl Parser {
                 _ => return Err(self.make_error("[SEMICOLON,RBRACE]")),
             }
         }
-        Ok(Box::from(Expr::Block { exprs }))
+        Ok(())
+    }
+
+    // an element of a block: a let binding or an expression
+    fn read_stmt(&mut self) -> Result<(), Diagnostic> {
+        if !self.ct_check(TokenKind::Let) {
+            return self.read_expr();
+        }
+        let mark = self.mark();
+        self.next_token();
+        self.read_pattern()?;
+        if self.ct_check(TokenKind::Colon) {
+            self.next_token();
+            self.read_type()?;
+        }
+        if !self.ct_check(TokenKind::Assign) {
+            return Err(self.make_error("[COLON,ASSIGN]"));
+        }
+        self.next_token();
+        self.read_expr()?;
+        self.wrap(mark, SyntaxKind::LetStmt);
+        Ok(())
     }
 
-    fn read_args_decl(&mut self) -> Result<Vec<ArgDecl>, String> {
-        let mut args_def = Vec::new();
+    fn read_args_decl(&mut self) -> Result<(), Diagnostic> {
+        if self.ctk == TokenKind::RParen {
+            self.next_token();
+            return Ok(());
+        }
         loop {
-            let vname = match self.ctk.clone() {
-                TokenKind::Ident(s) => {
-                    self.next_token();
-                    s
+            self.read_until(&[TokenKind::Comma, TokenKind::RParen], |p| {
+                let mark = p.mark();
+                p.read_pattern()?;
+                if p.ct_check(TokenKind::Colon) {
+                    p.next_token();
+                    p.read_type()?;
                 }
-                _ => {
-                    return Err(self.make_error("IDENT"));
-                }
-            };
-            match self.ctk {
-                TokenKind::Colon => self.next_token(),
-                _ => return Err(self.make_error("COLON")),
-            }
-            let vtype = self.read_type()?;
-            args_def.push(ArgDecl { vname, vtype });
+                p.wrap(mark, SyntaxKind::Param);
+                Ok(())
+            });
             match self.ctk {
                 TokenKind::RParen => {
                     self.next_token();
@@ -159,18 +1073,23 @@ impl Parser {
                     self.next_token();
                 }
                 _ => {
-                    return Err(self.make_error("[RPAREN,COMMA]"));
+                    return Err(self.make_error("[COLON,RPAREN,COMMA]"));
                 }
             }
         }
-        Ok(args_def)
+        Ok(())
     }
 
-    fn read_type_args(&mut self) -> Result<Vec<Box<Type>>, String> {
-        let mut args = Vec::new();
+    // the types in parens after the LPAREN, returning how many there are
+    fn read_type_args(&mut self) -> Result<usize, Diagnostic> {
+        if self.ctk == TokenKind::RParen {
+            self.next_token();
+            return Ok(0);
+        }
+        let mut args = 0;
         loop {
-            let tmp = Box::from(self.read_type()?);
-            args.push(tmp);
+            self.read_type()?;
+            args += 1;
             match self.ctk {
                 TokenKind::Comma => self.next_token(),
                 TokenKind::RParen => break,
@@ -181,43 +1100,112 @@ impl Parser {
         Ok(args)
     }
 
-    fn read_type(&mut self) -> Result<Type, String> {
-        let ret;
-        ret = match self.ctk.clone() {
-            TokenKind::Ident(name) => {
+    fn read_type(&mut self) -> Result<(), Diagnostic> {
+        let mark = self.mark();
+        let kind = match self.ctk {
+            TokenKind::Ident(_) => {
                 self.next_token();
-                Type::UserType { name }
+                if self.ct_check(TokenKind::Lt) {
+                    self.next_token();
+                    loop {
+                        self.read_type()?;
+                        match self.ctk {
+                            TokenKind::Comma => self.next_token(),
+                            TokenKind::Gt => break,
+                            _ => return Err(self.make_error("[COMMA,GT]")),
+                        }
+                    }
+                    self.next_token();
+                }
+                SyntaxKind::Type
             }
-            TokenKind::I32 => {
+            TokenKind::I32
+            | TokenKind::I64
+            | TokenKind::U8
+            | TokenKind::U32
+            | TokenKind::F64
+            | TokenKind::UnitType
+            | TokenKind::BoolType
+            | TokenKind::StringType
+            | TokenKind::CharType => {
                 self.next_token();
-                Type::I32
+                SyntaxKind::Type
             }
-            TokenKind::UnitType => {
+            // a tuple, or a single type in parens
+            TokenKind::LParen => {
                 self.next_token();
-                Type::Unit
+                match self.read_type_args()? {
+                    0 => return Err(self.make_error("TYPE")),
+                    1 => SyntaxKind::ParenType,
+                    _ => SyntaxKind::TupleType,
+                }
             }
             TokenKind::FuncType => {
+                self.next_token();
                 if !self.ct_check(TokenKind::LParen) {
                     return Err(self.make_error("LPAREN"));
                 }
-                let args = self.read_type_args()?;
-                let ret = Box::from(self.read_type()?);
-                Type::Func { args, ret }
+                self.next_token();
+                self.read_type_args()?;
+                self.read_ret_decl()?;
+                SyntaxKind::FuncType
             }
             _ => return Err(self.make_error("TYPE")),
         };
-        Ok(ret)
+        self.wrap(mark, kind);
+        Ok(())
+    }
+
+    // Reads a number literal, negated when it follows a `-` at `start`,
+    // and checks its value.
+    fn read_num(&mut self, negative: bool, start: TokenInfo) -> Result<Box<Expr>, Diagnostic> {
+        let text = match self.ctk.clone() {
+            TokenKind::Num(text) => text,
+            _ => return Err(self.make_error("NUM")),
+        };
+        let info = start.to(&self.cti);
+        self.next_token();
+        parse_num(&text, negative, info)
+    }
+
+    // A number literal in a pattern, which must be an integer since floats
+    // are not compared exactly.
+    fn read_int_pattern(&mut self, negative: bool, start: TokenInfo) -> Result<(), Diagnostic> {
+        match *self.read_num(negative, start)? {
+            Expr::Int { .. } => Ok(()),
+            e => Err(Diagnostic::error("E0100", "Expected PATTERN")
+                .with_primary(e.info().clone())
+                .with_note("float literals cannot be patterns, compare with `<` and `>` instead")),
+        }
     }
 
-    fn read_ret_decl(&mut self) -> Result<Type, String> {
+    fn read_ret_decl(&mut self) -> Result<(), Diagnostic> {
+        let mark = self.mark();
         match self.ctk {
             TokenKind::Arrow => self.next_token(),
             _ => return Err(self.make_error("ARROW")),
         }
-        Ok(self.read_type()?)
+        self.read_type()?;
+        self.wrap(mark, SyntaxKind::RetType);
+        Ok(())
+    }
+
+    // after the `function` keyword
+    fn read_anon_func(&mut self) -> Result<(), Diagnostic> {
+        self.read_type_params()?;
+        self.read_func_rest()
     }
 
-    fn read_anon_func(&mut self) -> Result<Box<Expr>, String> {
+    // after the `fn` keyword
+    fn read_named_func(&mut self) -> Result<(), Diagnostic> {
+        self.read_ident()?;
+        self.read_type_params()?;
+        self.read_func_rest()
+    }
+
+    // `(args) -> T { block }` of a function, with the return type optional
+    fn read_func_rest(&mut self) -> Result<(), Diagnostic> {
+        let mark = self.mark();
         match self.ctk {
             TokenKind::LParen => {
                 self.next_token();
@@ -226,113 +1214,336 @@ impl Parser {
                 return Err(self.make_error("LPAREN"));
             }
         }
-        let args_decl = self.read_args_decl()?;
-        let ret_decl = self.read_ret_decl()?;
-        let block = match self.ctk {
-            TokenKind::LBrace => {
-                self.next_token();
-                self.read_block()?
+        self.read_args_decl()?;
+        self.wrap(mark, SyntaxKind::ParamList);
+        if self.ctk == TokenKind::Arrow {
+            self.read_ret_decl()?;
+        }
+        match self.ctk {
+            TokenKind::LBrace => self.read_block(),
+            _ => Err(self.make_error("[ARROW,BLOCK]")),
+        }
+    }
+
+    // after the `if` keyword
+    fn read_if(&mut self) -> Result<(), Diagnostic> {
+        self.with_struct_lit(false, |p| p.read_expr())?;
+        self.read_brace_block()?;
+        if self.ctk == TokenKind::Else {
+            self.next_token();
+            match self.ctk {
+                // else if ...
+                TokenKind::If => {
+                    let mark = self.mark();
+                    self.next_token();
+                    self.read_if()?;
+                    self.wrap(mark, SyntaxKind::IfExpr);
+                }
+                _ => self.read_brace_block()?,
             }
-            _ => return Err(self.make_error("BLOCK")),
-        };
-        Ok(Box::from(Expr::AnonFunc {
-            args_decl,
-            ret_decl,
-            block,
-        }))
+        }
+        Ok(())
+    }
+
+    fn read_brace_block(&mut self) -> Result<(), Diagnostic> {
+        if !self.ct_check(TokenKind::LBrace) {
+            return Err(self.make_error("BLOCK"));
+        }
+        self.read_block()
     }
 
     fn lead_expr(token: TokenKind) -> bool {
         match token {
-            ref t if Parser::lead_simple_expr(t.clone()) => true,
-            _ => false,
+            TokenKind::Minus | TokenKind::Bang => true,
+            t => Parser::lead_simple_expr(t),
         }
     }
 
-    fn read_expr(&mut self) -> Result<Box<Expr>, String> {
-        let mut ret_expr: Box<Expr>;
-        match self.ctk.clone() {
-            ref t if Parser::lead_simple_expr(t.clone()) => {
-                ret_expr = self.read_simple_expr()?;
+    fn read_expr(&mut self) -> Result<(), Diagnostic> {
+        self.read_binary(0)
+    }
+
+    // Precedence climbing: reads unary expressions joined by binary
+    // operators binding tighter than `min_prec`.
+    fn read_binary(&mut self, min_prec: u8) -> Result<(), Diagnostic> {
+        let mut mark = self.mark();
+        self.read_cast()?;
+        while let Some(op) = BinOp::from_token(&self.ctk) {
+            if op.precedence() <= min_prec {
+                break;
             }
-            _ => return Err(self.make_error("EXPR")),
+            self.next_token();
+            self.read_binary(op.precedence())?;
+            mark = self.wrap(mark, SyntaxKind::BinExpr);
+        }
+        Ok(())
+    }
+
+    // a unary expression converted by any number of `as T`, which binds
+    // tighter than the binary operators
+    fn read_cast(&mut self) -> Result<(), Diagnostic> {
+        let mut mark = self.mark();
+        self.read_unary()?;
+        while self.ct_check(TokenKind::As) {
+            self.next_token();
+            self.read_type()?;
+            mark = self.wrap(mark, SyntaxKind::CastExpr);
+        }
+        Ok(())
+    }
+
+    fn read_unary(&mut self) -> Result<(), Diagnostic> {
+        let op = match self.ctk {
+            TokenKind::Minus => UnaryOp::Neg,
+            TokenKind::Bang => UnaryOp::Not,
+            _ => return self.read_app(),
+        };
+        let start = self.cti.clone();
+        let mark = self.mark();
+        self.next_token();
+        // a negative literal, so that the smallest value of a type can be
+        // written
+        if op == UnaryOp::Neg && self.ct_check(TokenKind::Num(String::new())) {
+            self.read_num(true, start)?;
+            self.wrap(mark, SyntaxKind::Literal);
+            return Ok(());
+        }
+        self.read_unary()?;
+        self.wrap(mark, SyntaxKind::UnaryExpr);
+        Ok(())
+    }
+
+    // a simple expression followed by any number of argument lists and
+    // field accesses
+    fn read_app(&mut self) -> Result<(), Diagnostic> {
+        let mut mark = self.mark();
+        if !Parser::lead_simple_expr(self.ctk.clone()) {
+            return Err(self.make_error("EXPR"));
         }
+        self.read_simple_expr()?;
 
         loop {
             match self.ctk {
                 TokenKind::LParen => {
+                    let args_mark = self.mark();
                     self.next_token();
-                    let args = self.read_args()?;
-                    ret_expr = Box::from(Expr::FuncApp {
-                        callee: ret_expr,
-                        args: args,
-                    })
+                    self.with_struct_lit(true, |p| p.read_args())?;
+                    self.wrap(args_mark, SyntaxKind::ArgList);
+                    mark = self.wrap(mark, SyntaxKind::CallExpr);
                 }
-
-                _ => {
-                    break;
+                TokenKind::Dot => {
+                    self.next_token();
+                    if let TokenKind::Num(n) = &self.ctk {
+                        if n.parse::<usize>().is_err() {
+                            return Err(self.make_error("INDEX"));
+                        }
+                        self.next_token();
+                    } else {
+                        self.read_ident()?;
+                    }
+                    mark = self.wrap(mark, SyntaxKind::FieldExpr);
                 }
+                _ => break,
             }
         }
-        Ok(ret_expr)
+        Ok(())
     }
 
     fn lead_simple_expr(token: TokenKind) -> bool {
-        match &token {
-            TokenKind::Num(_) => true,
-            TokenKind::Ident(_) => true,
-            TokenKind::Func => true,
-            TokenKind::FuncAnon => true,
-            TokenKind::LParen => true,
-            TokenKind::RBrace => true,
-            TokenKind::UnitVal => true,
-            _ => false,
-        }
+        matches!(
+            token,
+            TokenKind::Num(_)
+                | TokenKind::Str(_)
+                | TokenKind::Char(_)
+                | TokenKind::Ident(_)
+                | TokenKind::Func
+                | TokenKind::FuncAnon
+                | TokenKind::LParen
+                | TokenKind::LBrace
+                | TokenKind::UnitVal
+                | TokenKind::True
+                | TokenKind::False
+                | TokenKind::If
+                | TokenKind::Match
+                | TokenKind::Error
+        )
     }
 
-    fn read_simple_expr(&mut self) -> Result<Box<Expr>, String> {
-        let ct = self.ctk.clone();
-        let ret_expr;
-        match ct {
-            TokenKind::Num(s) => {
+    fn read_simple_expr(&mut self) -> Result<(), Diagnostic> {
+        let info = self.cti.clone();
+        let mark = self.mark();
+        let kind = match self.ctk {
+            TokenKind::Num(_) => {
+                self.read_num(false, info)?;
+                SyntaxKind::Literal
+            }
+
+            TokenKind::Ident(_) => {
                 self.next_token();
-                ret_expr = Box::from(Expr::I32 {
-                    val: s.parse().unwrap(),
-                });
+                if self.struct_lit && self.ct_check(TokenKind::LBrace) {
+                    self.read_struct_lit()?;
+                    SyntaxKind::StructLit
+                } else {
+                    SyntaxKind::Var
+                }
             }
 
-            TokenKind::Ident(s) => {
+            TokenKind::Str(_)
+            | TokenKind::Char(_)
+            | TokenKind::UnitVal
+            | TokenKind::True
+            | TokenKind::False => {
                 self.next_token();
-                ret_expr = Box::from(Expr::Var { name: s.clone() })
+                SyntaxKind::Literal
             }
 
-            TokenKind::UnitVal => {
+            TokenKind::Error => {
                 self.next_token();
-                ret_expr = Box::from(Expr::Unit)
+                SyntaxKind::Error
             }
 
             TokenKind::FuncAnon => {
                 self.next_token();
-                ret_expr = self.read_anon_func()?;
+                self.read_anon_func()?;
+                SyntaxKind::AnonFunc
             }
 
-            TokenKind::LParen => {
-                ret_expr = self.read_expr()?;
-                if !self.ct_check(TokenKind::RParen) {
-                    return Err(self.make_error("RPAREN"));
-                }
+            TokenKind::Func => {
+                self.next_token();
+                self.read_named_func()?;
+                SyntaxKind::NamedFunc
+            }
+
+            TokenKind::If => {
                 self.next_token();
+                self.read_if()?;
+                SyntaxKind::IfExpr
             }
 
-            TokenKind::LBrace => {
+            TokenKind::Match => {
                 self.next_token();
+                self.read_match()?;
+                SyntaxKind::MatchExpr
+            }
 
-                ret_expr = self.read_block()?;
+            // a tuple, or a single expression in parens
+            TokenKind::LParen => {
+                self.next_token();
+                let mut elems = 0;
+                loop {
+                    self.with_struct_lit(true, |p| p.read_expr())?;
+                    elems += 1;
+                    match self.ctk {
+                        TokenKind::Comma => self.next_token(),
+                        TokenKind::RParen => break,
+                        _ => {
+                            return Err(self
+                                .make_error("[COMMA,RPAREN]")
+                                .with_secondary(info, "to match this LPAREN"))
+                        }
+                    }
+                }
+                self.next_token();
+                match elems {
+                    1 => SyntaxKind::ParenExpr,
+                    _ => SyntaxKind::TupleExpr,
+                }
             }
 
+            TokenKind::LBrace => return self.read_block(),
+
             _ => return Err(self.make_error("EXPR")),
-        }
+        };
+
+        self.wrap(mark, kind);
+        Ok(())
+    }
+}
 
-        Ok(ret_expr)
+// The number literal `text`, like `0xff_u8` or `1.5e-3`, spanning `info`
+// and negated when it follows a `-`. An integer must fit in its type,
+// which is I32 unless the literal has a suffix.
+pub fn parse_num(text: &str, negative: bool, info: TokenInfo) -> Result<Box<Expr>, Diagnostic> {
+    let (radix, body) = match text.get(..2) {
+        Some("0x") => (16, &text[2..]),
+        Some("0o") => (8, &text[2..]),
+        Some("0b") => (2, &text[2..]),
+        _ => (10, text),
+    };
+    // neither `i` nor `u` is a hex digit, but `f` is
+    let suffixes: &[char] = if radix == 16 {
+        &['i', 'u']
+    } else {
+        &['i', 'u', 'f']
+    };
+    let (digits, suffix) = body.split_at(body.find(suffixes).unwrap_or(body.len()));
+    let invalid = |message: &str| {
+        Err(Diagnostic::error("E0101", message)
+            .with_primary(info.clone())
+            .with_note("numbers are written like `42`, `0xff`, `0b1010`, `1_000_u8` or `1.5e-3`"))
+    };
+    let digits: String = digits.chars().filter(|c| *c != '_').collect();
+    let float = radix == 10 && digits.contains(['.', 'e', 'E']);
+    let ty = match suffix {
+        "" if float => Type::F64,
+        "" => Type::I32,
+        s => match Type::from_suffix(s) {
+            Some(ty) => ty,
+            None => return invalid(&format!("Invalid suffix `{}` for a number literal", s)),
+        },
+    };
+    if ty == Type::F64 {
+        if radix != 10 {
+            return invalid(&format!(
+                "Invalid suffix `f64` for a base {} literal",
+                radix
+            ));
+        }
+        let val: f64 = match digits.parse() {
+            Ok(val) => val,
+            Err(_) => return invalid(&format!("Invalid float literal `{}`", text)),
+        };
+        if val.is_infinite() {
+            return Err(
+                Diagnostic::error("E0102", "Number literal out of range for F64")
+                    .with_primary(info)
+                    .with_note(&format!("values of type F64 range up to {:e}", f64::MAX)),
+            );
+        }
+        let val = if negative { -val } else { val };
+        return Ok(Box::from(Expr::Float { val, info }));
+    }
+    if float {
+        return invalid(&format!("Invalid suffix `{}` for a float literal", suffix));
+    }
+    if let Some(c) = digits.chars().find(|c| !c.is_digit(radix)) {
+        return invalid(&format!(
+            "Invalid digit `{}` in a base {} literal",
+            c, radix
+        ));
+    }
+    if digits.is_empty() {
+        return invalid("Missing digits in a number literal");
+    }
+    let (min, max) = ty.int_range().unwrap_or_default();
+    // past u64 the digits are out of every range
+    let val = u64::from_str_radix(&digits, radix)
+        .ok()
+        .map(|n| if negative { -(n as i128) } else { n as i128 })
+        .filter(|n| (min as i128..=max as i128).contains(n));
+    match val {
+        Some(n) => Ok(Box::from(Expr::Int {
+            val: n as i64,
+            ty,
+            info,
+        })),
+        None => Err(
+            Diagnostic::error("E0102", &format!("Number literal out of range for {}", ty))
+                .with_primary(info)
+                .with_note(&format!(
+                    "values of type {} range from {} to {}",
+                    ty, min, max
+                )),
+        ),
     }
 }
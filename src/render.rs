@@ -0,0 +1,192 @@
+// Renders diagnostics rustc-style, quoting the offending source lines and
+// marking spans with carets.
+use crate::diagnostic::*;
+use crate::syntax::TokenInfo;
+
+#[cfg(test)]
+#[allow(clippy::items_after_test_module)]
+mod render_test {
+    use super::*;
+
+    fn span(s_row: usize, s_col: usize, e_row: usize, e_col: usize) -> TokenInfo {
+        TokenInfo {
+            s_col,
+            s_row,
+            e_col,
+            e_row,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let src = "(foo\n  bar)";
+        let d = Diagnostic::error("E0100", "Expected RPAREN")
+            .with_primary(span(2, 3, 2, 5))
+            .with_secondary(span(1, 1, 1, 1), "to match this LPAREN")
+            .with_note("found Ident(\"bar\")");
+        let expected = "\
+error[E0100]: Expected RPAREN
+ --> test.lung:2:3
+  |
+1 | (foo
+  | - to match this LPAREN
+2 |   bar)
+  |   ^^^
+  = note: found Ident(\"bar\")
+";
+        assert_eq!(Renderer::new("test.lung", src, false).render(&d), expected);
+    }
+
+    #[test]
+    fn test_render_multiline() {
+        let src = "a\n{ b\nc }\n";
+        let d = Diagnostic::error("E0200", "mismatch").with_primary(span(2, 1, 3, 3));
+        let expected = "\
+error[E0200]: mismatch
+ --> f:2:1
+  |
+2 | { b
+  | ^^^
+3 | c }
+  | ^^^
+";
+        assert_eq!(Renderer::new("f", src, false).render(&d), expected);
+    }
+
+    #[test]
+    fn test_render_without_span() {
+        let d = Diagnostic::error("E0201", "Could not find variable `x`");
+        assert_eq!(
+            Renderer::new("f", "x", true).render(&d),
+            "\x1b[1;31merror[E0201]\x1b[0m\x1b[1m: Could not find variable `x`\x1b[0m\n"
+        );
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+// ANSI colors are used only on a terminal, and never when NO_COLOR is set.
+pub fn use_color(is_terminal: bool) -> bool {
+    is_terminal && std::env::var_os("NO_COLOR").is_none()
+}
+
+pub struct Renderer<'a> {
+    fname: &'a str,
+    lines: Vec<&'a str>,
+    color: bool,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(fname: &'a str, src: &'a str, color: bool) -> Renderer<'a> {
+        Renderer {
+            fname,
+            lines: src.lines().collect(),
+            color,
+        }
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            String::from(text)
+        }
+    }
+
+    pub fn render(&self, d: &Diagnostic) -> String {
+        let severity_style = match d.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        };
+        let mut out = format!(
+            "{}{}\n",
+            self.paint(severity_style, &format!("{}[{}]", d.severity, d.code)),
+            self.paint(BOLD, &format!(": {}", d.message))
+        );
+
+        // (span, label, is primary), shown in source order
+        let mut spans: Vec<(&TokenInfo, &str, bool)> = Vec::new();
+        if let Some(span) = &d.primary {
+            spans.push((span, "", true));
+        }
+        for (span, label) in &d.secondary {
+            spans.push((span, label, false));
+        }
+        spans.sort_by_key(|(s, _, _)| (s.s_row, s.s_col));
+
+        let gutter_width = spans
+            .iter()
+            .map(|(s, _, _)| s.e_row.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let gutter = |row: &str| self.paint(BLUE, &format!("{:>w$} |", row, w = gutter_width));
+
+        if let Some(span) = d.primary.as_ref().or_else(|| spans.first().map(|s| s.0)) {
+            out.push_str(&format!(
+                "{}{}:{}:{}\n",
+                self.paint(BLUE, &format!("{:>w$}--> ", "", w = gutter_width)),
+                self.fname,
+                span.s_row,
+                span.s_col
+            ));
+            out.push_str(&gutter(""));
+            out.push('\n');
+        }
+
+        let mut last_row = 0;
+        for (span, label, primary) in &spans {
+            for row in span.s_row..=span.e_row {
+                let line = match self.lines.get(row.wrapping_sub(1)) {
+                    Some(l) => *l,
+                    None => "",
+                };
+                if row != last_row {
+                    out.push_str(&format!("{} {}\n", gutter(&row.to_string()), line));
+                    last_row = row;
+                }
+                let line_len = line.chars().count();
+                let from = if row == span.s_row { span.s_col } else { 1 };
+                let to = if row == span.e_row {
+                    span.e_col
+                } else {
+                    line_len
+                };
+                // keep tabs so the carets line up with the quoted line
+                let indent: String = line
+                    .chars()
+                    .chain(std::iter::repeat(' '))
+                    .take(from.saturating_sub(1))
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+                let marker = if *primary { "^" } else { "-" };
+                let marks = marker.repeat(to.max(from) - from + 1);
+                let mut annotation = marks;
+                if row == span.e_row && !label.is_empty() {
+                    annotation = format!("{} {}", annotation, label);
+                }
+                let style = if *primary { severity_style } else { BLUE };
+                out.push_str(&format!(
+                    "{} {}{}\n",
+                    gutter(""),
+                    indent,
+                    self.paint(style, &annotation)
+                ));
+            }
+        }
+
+        for note in &d.notes {
+            out.push_str(&format!(
+                "{:>w$} {} note: {}\n",
+                "",
+                self.paint(BLUE, "="),
+                note,
+                w = gutter_width
+            ));
+        }
+        out
+    }
+}
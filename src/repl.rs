@@ -0,0 +1,254 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use crate::cst;
+use crate::diagnostic::Diagnostic;
+use crate::eval::*;
+use crate::lexer::*;
+use crate::render::{use_color, Renderer};
+use crate::syntax::*;
+use crate::type_def::*;
+use crate::typing::*;
+
+#[cfg(test)]
+#[allow(clippy::items_after_test_module)]
+mod repl_test {
+    use super::*;
+
+    #[test]
+    fn test_eval_input() {
+        let mut repl = Repl::new();
+        let (v, t) = repl.eval_input("function(x:I32) -> I32 { x }(3)").unwrap();
+        assert_eq!(v.to_string(), "3");
+        assert_eq!(t.ty, Type::I32);
+        let (_, t) = repl.eval_input("function(x:I32) -> Unit { unit }").unwrap();
+        assert_eq!(t.to_string(), "Fn(I32) -> Unit");
+        let (v, _) = repl
+            .eval_input("let twice = function(f, x) { f(f(x)) };")
+            .unwrap();
+        assert_eq!(v.to_string(), "unit");
+        let (v, t) = repl.eval_input("twice(function(x) { x * 2 }, 5)").unwrap();
+        assert_eq!(v.to_string(), "20");
+        assert_eq!(t.ty, Type::I32);
+        assert!(repl.eval_input("1 2").is_err());
+        assert!(repl.eval_input("nowhere").is_err());
+    }
+
+    #[test]
+    fn test_failed_input() {
+        let mut repl = Repl::new();
+        let d = repl.eval_input("let x = 1 / 0;").unwrap_err();
+        assert_eq!(d.code, "E0304");
+        // `x` was never bound, which the typer has to know
+        let d = repl.eval_input("x").unwrap_err();
+        assert_eq!(d.code, "E0201");
+        repl.eval_input("let y = true;").unwrap();
+        assert!(repl.eval_input("let y = 1 + y;").is_err());
+        let (v, t) = repl.eval_input("y").unwrap();
+        assert_eq!(
+            (v.to_string(), t.to_string()),
+            (String::from("true"), String::from("Bool"))
+        );
+    }
+
+    #[test]
+    fn test_shadowed_input() {
+        let mut repl = Repl::new();
+        repl.eval_input("let x = 1;").unwrap();
+        repl.eval_input("let f = function() { x };").unwrap();
+        repl.eval_input("let x = true;").unwrap();
+        let (v, t) = repl.eval_input("f()").unwrap();
+        assert_eq!(
+            (v.to_string(), t.to_string()),
+            (String::from("1"), String::from("I32"))
+        );
+        repl.eval_input("fn g() { 1 }").unwrap();
+        repl.eval_input("fn h() { g() + 1 }").unwrap();
+        repl.eval_input("fn g() { false }").unwrap();
+        let (v, t) = repl.eval_input("(h(), g())").unwrap();
+        assert_eq!(
+            (v.to_string(), t.to_string()),
+            (String::from("(2, false)"), String::from("(I32, Bool)"))
+        );
+    }
+
+    #[test]
+    fn test_items() {
+        let mut repl = Repl::new();
+        let (v, t) = repl.eval_input("struct P { x: I32, y: I32 }").unwrap();
+        assert_eq!(
+            (v.to_string(), t.to_string()),
+            (String::from("unit"), String::from("Unit"))
+        );
+        repl.eval_input("enum Shape { Square(I32), Dot }").unwrap();
+        repl.eval_input("type Side = I32;").unwrap();
+        let (v, t) = repl
+            .eval_input("function(s: Shape, p: P) -> Side { match s { Square(n) => n * p.x, Dot => 0 } }(Square(3), P { x: 2, y: 0 })")
+            .unwrap();
+        assert_eq!(
+            (v.to_string(), t.to_string()),
+            (String::from("6"), String::from("I32"))
+        );
+        // a failed item defines nothing, not even the items before it
+        let d = repl.eval_input("enum E { A } struct P { }").unwrap_err();
+        assert_eq!(d.code, "E0213");
+        assert_eq!(repl.eval_input("A").unwrap_err().code, "E0201");
+        assert_eq!(
+            repl.eval_input("struct Q { x I32 }").unwrap_err().code,
+            "E0100"
+        );
+    }
+
+    #[test]
+    fn test_needs_more() {
+        assert!(needs_more("function(x:I32) -> I32 {"));
+        assert!(needs_more("function(x:I32,"));
+        assert!(!needs_more("function(x:I32) -> I32 { x }(1)"));
+        // let the parser report stray closing brackets
+        assert!(!needs_more("1 }"));
+    }
+}
+
+const PROMPT: &str = "lung> ";
+const CONTINUE_PROMPT: &str = "  ... ";
+
+// Typing context and runtime environment kept alive across inputs, so that
+// bindings made by one input are visible to the next.
+pub struct Repl {
+    cxt: Context,
+    env: Env,
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl {
+            cxt: Context::new(),
+            env: Env::new(),
+        }
+    }
+
+    pub fn eval_input(&mut self, src: &str) -> Result<(Value, Scheme), Diagnostic> {
+        if starts_item(src) {
+            return self.define_items(src);
+        }
+        let expr = cst::parse_expr(src)?;
+        // each input binds in a scope of its own, like a let in a block, so
+        // that closures keep seeing what they captured when a later input
+        // shadows it. An input failing at runtime binds nothing, so its
+        // bindings are taken back from the typing context too.
+        let snapshot = self.cxt.clone();
+        let scope = self.env.child();
+        let result = self
+            .cxt
+            .infer((*expr).clone())
+            .and_then(|scheme| Ok((expr.eval(&scope)?, scheme)));
+        match result {
+            Ok(_) => self.env = scope,
+            Err(_) => self.cxt.restore(snapshot),
+        }
+        result
+    }
+
+    // Reads the items of `src`, which are typed and defined like those of
+    // a file, binding nothing when one of them fails.
+    fn define_items(&mut self, src: &str) -> Result<(Value, Scheme), Diagnostic> {
+        let program = cst::parse_program(src)?;
+        let snapshot = self.cxt.clone();
+        let result = self
+            .cxt
+            .check_program(&program)
+            .and_then(|_| program.define(&self.env));
+        match result {
+            Ok(env) => {
+                self.env = env;
+                Ok((Value::Unit, Scheme::mono(Type::Unit)))
+            }
+            Err(d) => {
+                self.cxt.restore(snapshot);
+                Err(d)
+            }
+        }
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        let mut input = String::new();
+        let color = use_color(io::stdout().is_terminal());
+        loop {
+            print!(
+                "{}",
+                if input.is_empty() {
+                    PROMPT
+                } else {
+                    CONTINUE_PROMPT
+                }
+            );
+            io::stdout().flush()?;
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => break,
+            };
+            if input.is_empty() {
+                match line.trim() {
+                    "" => continue,
+                    ":quit" | ":q" => break,
+                    _ => (),
+                }
+            }
+            input.push_str(&line);
+            input.push('\n');
+            if needs_more(&input) {
+                continue;
+            }
+            let result = self.eval_input(&input);
+            let renderer = Renderer::new("<repl>", &input, color);
+            for w in self.cxt.take_warnings() {
+                print!("{}", renderer.render(&w));
+            }
+            match result {
+                Ok((v, t)) => println!("{} : {}", v, t),
+                Err(d) => print!("{}", renderer.render(&d)),
+            }
+            input.clear();
+        }
+        println!();
+        Ok(())
+    }
+}
+
+// Whether `src` starts with a declaration which can only be an item, like
+// a struct, rather than an expression.
+fn starts_item(src: &str) -> bool {
+    let tokens = match Lexer::from_string(String::from(src)).lex() {
+        Ok(ts) => ts,
+        Err(_) => return false,
+    };
+    let first = tokens
+        .into_iter()
+        .find(|t| !matches!(t.kind, TokenKind::DocComment(_)));
+    matches!(
+        first.map(|t| t.kind),
+        Some(TokenKind::Struct | TokenKind::Enum | TokenKind::Type)
+    )
+}
+
+// Whether `src` has an unclosed `{` or `(` and more lines should be read
+// before evaluating it.
+fn needs_more(src: &str) -> bool {
+    let tokens = match Lexer::from_string(String::from(src)).lex() {
+        Ok(ts) => ts,
+        Err(_) => return false,
+    };
+    let mut depth: isize = 0;
+    for t in tokens {
+        match t.kind {
+            TokenKind::LParen | TokenKind::LBrace => depth += 1,
+            TokenKind::RParen | TokenKind::RBrace => depth -= 1,
+            _ => (),
+        }
+        if depth < 0 {
+            return false;
+        }
+    }
+    depth > 0
+}
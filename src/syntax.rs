@@ -1,12 +1,42 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
 use crate::type_def::*;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Token {
     pub kind: TokenKind,
     pub info: TokenInfo,
 }
 
-#[derive(Debug)]
+// The text between tokens, which the parser never sees
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriviaKind {
+    Whitespace,
+    Newline,
+    LineComment,
+    BlockComment,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub range: Range<usize>,
+}
+
+// A token along with the trivia around it, so that the tokens of a source
+// together cover all of its text. Ranges are byte offsets in the source.
+// The trailing trivia runs up to the end of the line of the token, the
+// leading trivia is the rest of what comes before it.
+#[derive(Debug, Clone)]
+pub struct LosslessToken {
+    pub token: Token,
+    pub range: Range<usize>,
+    pub leading: Vec<Trivia>,
+    pub trailing: Vec<Trivia>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct TokenInfo {
     pub s_col: usize,
     pub s_row: usize,
@@ -14,12 +44,25 @@ pub struct TokenInfo {
     pub e_row: usize,
 }
 
-impl std::string::ToString for TokenInfo {
-    fn to_string(&self) -> String {
-        String::from(format!(
+impl TokenInfo {
+    // span from the start of self to the end of `end`
+    pub fn to(&self, end: &TokenInfo) -> TokenInfo {
+        TokenInfo {
+            s_col: self.s_col,
+            s_row: self.s_row,
+            e_col: end.e_col,
+            e_row: end.e_row,
+        }
+    }
+}
+
+impl std::fmt::Display for TokenInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
             "{}:{}-{}:{}",
             self.s_row, self.s_col, self.e_row, self.e_col
-        ))
+        )
     }
 }
 
@@ -34,69 +77,536 @@ pub enum TokenKind {
     LBrace,
     Comma,
     Colon,
+    Dot,
     SemiColon,
     Arrow,
+    FatArrow,
+    Underscore,
+
+    // operators
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    EqEq,
+    NotEq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    AndAnd,
+    OrOr,
+    Bang,
 
     // premitive values
     Num(String),
     Ident(String),
+    // the value of a string or char literal, with escapes replaced
+    Str(String),
+    Char(char),
+    // `/// text`, documenting the declaration after it
+    DocComment(String),
     UnitVal,
+    True,
+    False,
+
+    // keywords
+    If,
+    Else,
+    Let,
+    Assign,
+    Type,
+    Import,
+    Struct,
+    Enum,
+    Match,
+    As,
 
     // types
     // for function type we will use syntax like (Type,...)->Type
     // so we don't need any token for function type.
     I32,
+    I64,
+    U8,
+    U32,
+    F64,
     UnitType,
+    BoolType,
+    StringType,
+    CharType,
     FuncType,
 
+    // chars which make no token, already reported by the lexer
+    Error,
+
     // EOF
+    #[allow(clippy::upper_case_acronyms)]
     EOF,
 }
 
+// A source file: its top level items in source order. They are defined
+// one after another like the elements of a block, and running the program
+// calls its `main` function.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub items: Vec<Item>,
+    // the doc comments of the documented items, by index in `items`
+    pub docs: HashMap<usize, String>,
+}
+
+impl Program {
+    // The doc comment of the item at `index`, its `///` lines joined.
+    pub fn doc(&self, index: usize) -> Option<&str> {
+        self.docs.get(&index).map(|d| d.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Item {
+    // always an Expr::NamedFunc
+    Func(Box<Expr>),
+    // `let pattern: T = init;`, always an Expr::Let
+    Const(Box<Expr>),
+    // `struct Name<T, ..> { field: T, .. }`
+    Struct {
+        name: String,
+        type_params: Vec<String>,
+        fields: Vec<FieldDecl>,
+        info: TokenInfo,
+    },
+    // `enum Name<T, ..> { Variant(T, ..), .. }`
+    Enum {
+        name: String,
+        type_params: Vec<String>,
+        variants: Vec<VariantDecl>,
+        info: TokenInfo,
+    },
+    // `type Name = T;`
+    TypeAlias {
+        name: String,
+        ty: TypeDecl,
+        #[allow(dead_code)]
+        info: TokenInfo,
+    },
+    // `import name;`, the items of name.lung next to this file
+    Import {
+        name: String,
+        info: TokenInfo,
+    },
+    // the tokens skipped after a syntax error in an item
+    Error {
+        #[allow(dead_code)]
+        info: TokenInfo,
+    },
+}
+
+// Every node carries the span of the tokens it was parsed from.
 #[derive(Debug, Clone)]
+#[allow(clippy::vec_box)]
 pub enum Expr {
     // Literals
-    I32 {
-        val: i32,
+    // an integer of type `ty`, which is I32 unless the literal has a
+    // suffix like the `u8` of `255u8`
+    Int {
+        val: i64,
+        ty: Type,
+        info: TokenInfo,
     },
+    // an F64, written like `1.5`, `2e10` or `3f64`
+    Float {
+        val: f64,
+        info: TokenInfo,
+    },
+    // `fn name<T, ..>(args) -> T { block }`, binding `name` in the
+    // enclosing scope. Evaluates to unit.
     NamedFunc {
         name: String,
+        type_params: Vec<String>,
         args_def: Vec<ArgDecl>,
-        ret_decl: Type,
+        ret_decl: Option<TypeDecl>,
         block: Box<Expr>,
+        info: TokenInfo,
+    },
+    Unit {
+        info: TokenInfo,
+    },
+    Bool {
+        val: bool,
+        info: TokenInfo,
+    },
+    Str {
+        val: String,
+        info: TokenInfo,
+    },
+    Char {
+        val: char,
+        info: TokenInfo,
     },
-    Unit,
     AnonFunc {
+        type_params: Vec<String>,
         args_decl: Vec<ArgDecl>,
-        ret_decl: Type,
+        ret_decl: Option<TypeDecl>,
         block: Box<Expr>,
+        info: TokenInfo,
     },
 
     // Block
     Block {
         exprs: Vec<Box<Expr>>,
+        info: TokenInfo,
     },
 
     // Variable
     Var {
         name: String,
+        info: TokenInfo,
+    },
+
+    // `let pattern: vtype = init`, only as an element of a block. The
+    // pattern must match every value of its type, like `(x, y)`. Its
+    // bindings are visible in the rest of the block and it evaluates to
+    // unit.
+    Let {
+        pattern: Pattern,
+        vtype: Option<TypeDecl>,
+        init: Box<Expr>,
+        info: TokenInfo,
+    },
+
+    // `Name { field: value, .. }`
+    StructLit {
+        name: String,
+        fields: Vec<FieldInit>,
+        info: TokenInfo,
+    },
+    // `expr.field`
+    Field {
+        expr: Box<Expr>,
+        field: String,
+        info: TokenInfo,
+    },
+
+    // `(a, b, ..)`, with at least two elements
+    Tuple {
+        elems: Vec<Box<Expr>>,
+        info: TokenInfo,
+    },
+    // `expr.0`
+    TupleField {
+        expr: Box<Expr>,
+        index: usize,
+        info: TokenInfo,
+    },
+
+    // `match scrutinee { pattern => body, .. }`, taking the first arm
+    // whose pattern matches
+    Match {
+        scrutinee: Box<Expr>,
+        arms: Vec<MatchArm>,
+        info: TokenInfo,
     },
 
     // Function app
     FuncApp {
         callee: Box<Expr>,
         args: Vec<Box<Expr>>,
+        info: TokenInfo,
+    },
+
+    // Conditional. Without else the branch must be Unit.
+    If {
+        cond: Box<Expr>,
+        then_block: Box<Expr>,
+        else_block: Option<Box<Expr>>,
+        info: TokenInfo,
+    },
+
+    // Operators
+    BinOp {
+        op: BinOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+        info: TokenInfo,
+    },
+    UnaryOp {
+        op: UnaryOp,
+        operand: Box<Expr>,
+        info: TokenInfo,
+    },
+    // `expr as T`, converting between number types
+    Cast {
+        expr: Box<Expr>,
+        ty: TypeDecl,
+        info: TokenInfo,
+    },
+
+    // the tokens skipped after a syntax error, left by the parser so that
+    // the rest of the tree can still be used
+    Error {
+        info: TokenInfo,
     },
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl BinOp {
+    pub fn from_token(kind: &TokenKind) -> Option<BinOp> {
+        match kind {
+            TokenKind::Plus => Some(BinOp::Add),
+            TokenKind::Minus => Some(BinOp::Sub),
+            TokenKind::Star => Some(BinOp::Mul),
+            TokenKind::Slash => Some(BinOp::Div),
+            TokenKind::Percent => Some(BinOp::Rem),
+            TokenKind::EqEq => Some(BinOp::Eq),
+            TokenKind::NotEq => Some(BinOp::Ne),
+            TokenKind::Lt => Some(BinOp::Lt),
+            TokenKind::Le => Some(BinOp::Le),
+            TokenKind::Gt => Some(BinOp::Gt),
+            TokenKind::Ge => Some(BinOp::Ge),
+            TokenKind::AndAnd => Some(BinOp::And),
+            TokenKind::OrOr => Some(BinOp::Or),
+            _ => None,
+        }
+    }
+
+    // Binding power; operators with a higher one bind tighter. All binary
+    // operators are left associative.
+    pub fn precedence(self) -> u8 {
+        match self {
+            BinOp::Or => 1,
+            BinOp::And => 2,
+            BinOp::Eq | BinOp::Ne => 3,
+            BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 4,
+            BinOp::Add | BinOp::Sub => 5,
+            BinOp::Mul | BinOp::Div | BinOp::Rem => 6,
+        }
+    }
+}
+
+impl std::fmt::Display for BinOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Rem => "%",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::And => "&&",
+            BinOp::Or => "||",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+impl std::fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            UnaryOp::Neg => write!(f, "-"),
+            UnaryOp::Not => write!(f, "!"),
+        }
+    }
+}
+
+impl Expr {
+    pub fn info(&self) -> &TokenInfo {
+        match self {
+            Expr::Int { info, .. }
+            | Expr::Float { info, .. }
+            | Expr::NamedFunc { info, .. }
+            | Expr::Unit { info }
+            | Expr::Bool { info, .. }
+            | Expr::Str { info, .. }
+            | Expr::Char { info, .. }
+            | Expr::If { info, .. }
+            | Expr::AnonFunc { info, .. }
+            | Expr::Block { info, .. }
+            | Expr::Var { info, .. }
+            | Expr::Let { info, .. }
+            | Expr::StructLit { info, .. }
+            | Expr::Field { info, .. }
+            | Expr::Tuple { info, .. }
+            | Expr::TupleField { info, .. }
+            | Expr::Match { info, .. }
+            | Expr::FuncApp { info, .. }
+            | Expr::BinOp { info, .. }
+            | Expr::UnaryOp { info, .. }
+            | Expr::Cast { info, .. }
+            | Expr::Error { info } => info,
+        }
+    }
+}
+
+// A type written in the source, e.g. the `I32` of `x: I32`.
+#[derive(Debug, Clone)]
+pub struct TypeDecl {
+    pub ty: Type,
+    pub info: TokenInfo,
+}
+
 #[derive(Debug, Clone)]
 pub struct ArgDecl {
-    pub vname: String,
-    pub vtype: Type,
+    // a pattern matching every value, usually just a name
+    pub pattern: Pattern,
+    // None when the type is left to inference
+    pub vtype: Option<TypeDecl>,
+    #[allow(dead_code)]
+    pub info: TokenInfo,
+}
+
+// `name: T` in a struct declaration
+#[derive(Debug, Clone)]
+pub struct FieldDecl {
+    pub name: String,
+    pub ty: TypeDecl,
+    pub info: TokenInfo,
+}
+
+// `name: value` in a struct literal
+#[derive(Debug, Clone)]
+pub struct FieldInit {
+    pub name: String,
+    pub value: Box<Expr>,
+    pub info: TokenInfo,
+}
+
+// `Variant(T, ..)` in an enum declaration
+#[derive(Debug, Clone)]
+pub struct VariantDecl {
+    pub name: String,
+    pub args: Vec<TypeDecl>,
+    pub info: TokenInfo,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Box<Expr>,
+    #[allow(dead_code)]
+    pub info: TokenInfo,
+}
+
+// A name starting with an uppercase letter is a constructor, any other
+// name binds the matched value.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Wildcard {
+        info: TokenInfo,
+    },
+    Bind {
+        name: String,
+        info: TokenInfo,
+    },
+    Int {
+        val: i64,
+        ty: Type,
+        info: TokenInfo,
+    },
+    Bool {
+        val: bool,
+        info: TokenInfo,
+    },
+    Char {
+        val: char,
+        info: TokenInfo,
+    },
+    Str {
+        val: String,
+        info: TokenInfo,
+    },
+    Unit {
+        info: TokenInfo,
+    },
+    Ctor {
+        name: String,
+        args: Vec<Pattern>,
+        info: TokenInfo,
+    },
+    Tuple {
+        elems: Vec<Pattern>,
+        info: TokenInfo,
+    },
+}
+
+impl Pattern {
+    pub fn info(&self) -> &TokenInfo {
+        match self {
+            Pattern::Wildcard { info }
+            | Pattern::Bind { info, .. }
+            | Pattern::Int { info, .. }
+            | Pattern::Bool { info, .. }
+            | Pattern::Char { info, .. }
+            | Pattern::Str { info, .. }
+            | Pattern::Unit { info }
+            | Pattern::Ctor { info, .. }
+            | Pattern::Tuple { info, .. } => info,
+        }
+    }
+
+    // the names bound by the pattern, from left to right
+    pub fn bindings(&self) -> Vec<&String> {
+        match self {
+            Pattern::Bind { name, .. } => vec![name],
+            Pattern::Ctor { args: elems, .. } | Pattern::Tuple { elems, .. } => {
+                elems.iter().flat_map(|p| p.bindings()).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
 }
 
-impl ArgDecl {
-    pub fn into_type(self) -> Type {
-        self.vtype
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Pattern::Wildcard { .. } => write!(f, "_"),
+            Pattern::Bind { name, .. } => write!(f, "{}", name),
+            Pattern::Int {
+                val, ty: Type::I32, ..
+            } => write!(f, "{}", val),
+            Pattern::Int { val, ty, .. } => write!(f, "{}{}", val, ty.to_string().to_lowercase()),
+            Pattern::Bool { val, .. } => write!(f, "{}", val),
+            Pattern::Char { val, .. } => write!(f, "{:?}", val),
+            Pattern::Str { val, .. } => write!(f, "{:?}", val),
+            Pattern::Unit { .. } => write!(f, "unit"),
+            Pattern::Ctor { name, args, .. } => {
+                write!(f, "{}", name)?;
+                if !args.is_empty() {
+                    let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                    write!(f, "({})", args.join(", "))?;
+                }
+                Ok(())
+            }
+            Pattern::Tuple { elems, .. } => {
+                let elems: Vec<String> = elems.iter().map(|p| p.to_string()).collect();
+                write!(f, "({})", elems.join(", "))
+            }
+        }
     }
 }
@@ -1,10 +1,18 @@
 use crate::syntax::*;
 
 #[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::vec_box)]
 pub enum Type {
     // premitive types
     I32,
+    I64,
+    U8,
+    U32,
+    F64,
     Unit,
+    Bool,
+    String,
+    Char,
 
     // function type
     Func {
@@ -12,20 +20,179 @@ pub enum Type {
         ret: Box<Type>,
     },
 
-    // user defined typ
+    // `(T, U, ..)`, with at least two elements
+    Tuple(Vec<Box<Type>>),
+
+    // user defined typ, with the type arguments of a generic one, like
+    // the I32 of `Option<I32>`
+    #[allow(clippy::enum_variant_names)]
     UserType {
         name: String,
+        args: Vec<Box<Type>>,
     },
+
+    // type variable introduced by inference
+    Var(usize),
+}
+
+impl Type {
+    // The integer type written as the suffix `s` of a literal, like the
+    // `u8` of `255u8`.
+    pub fn from_suffix(s: &str) -> Option<Type> {
+        match s {
+            "i32" => Some(Type::I32),
+            "i64" => Some(Type::I64),
+            "u8" => Some(Type::U8),
+            "u32" => Some(Type::U32),
+            "f64" => Some(Type::F64),
+            _ => None,
+        }
+    }
+
+    // the smallest and the largest value of an integer type
+    pub fn int_range(&self) -> Option<(i64, i64)> {
+        match self {
+            Type::I32 => Some((i32::MIN as i64, i32::MAX as i64)),
+            Type::I64 => Some((i64::MIN, i64::MAX)),
+            Type::U8 => Some((0, u8::MAX as i64)),
+            Type::U32 => Some((0, u32::MAX as i64)),
+            _ => None,
+        }
+    }
+
+    pub fn is_number(&self) -> bool {
+        *self == Type::F64 || self.int_range().is_some()
+    }
+
+    // type variables occurring in self, in order of first appearance
+    pub fn free_vars(&self) -> Vec<usize> {
+        let mut vars = Vec::new();
+        self.collect_vars(&mut vars);
+        vars
+    }
+
+    fn collect_vars(&self, vars: &mut Vec<usize>) {
+        match self {
+            Type::Var(v) if !vars.contains(v) => vars.push(*v),
+            Type::Func { args, ret } => {
+                for a in args {
+                    a.collect_vars(vars);
+                }
+                ret.collect_vars(vars);
+            }
+            Type::Tuple(elems) | Type::UserType { args: elems, .. } => {
+                for e in elems {
+                    e.collect_vars(vars);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // replaces type variables according to `f`, leaving unmapped ones as is
+    pub fn map_vars(&self, f: &dyn Fn(usize) -> Option<Type>) -> Type {
+        match self {
+            Type::Var(v) => match f(*v) {
+                Some(t) => t,
+                None => Type::Var(*v),
+            },
+            Type::Func { args, ret } => Type::Func {
+                args: args.iter().map(|a| Box::from(a.map_vars(f))).collect(),
+                ret: Box::from(ret.map_vars(f)),
+            },
+            Type::Tuple(elems) => {
+                Type::Tuple(elems.iter().map(|e| Box::from(e.map_vars(f))).collect())
+            }
+            Type::UserType { name, args } => Type::UserType {
+                name: name.clone(),
+                args: args.iter().map(|a| Box::from(a.map_vars(f))).collect(),
+            },
+            t => t.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Type::I32 => write!(f, "I32"),
+            Type::I64 => write!(f, "I64"),
+            Type::U8 => write!(f, "U8"),
+            Type::U32 => write!(f, "U32"),
+            Type::F64 => write!(f, "F64"),
+            Type::Unit => write!(f, "Unit"),
+            Type::Bool => write!(f, "Bool"),
+            Type::String => write!(f, "String"),
+            Type::Char => write!(f, "Char"),
+            Type::Func { args, ret } => {
+                let args: Vec<String> = args.iter().map(|t| t.to_string()).collect();
+                write!(f, "Fn({}) -> {}", args.join(", "), ret)
+            }
+            Type::Tuple(elems) => {
+                let elems: Vec<String> = elems.iter().map(|t| t.to_string()).collect();
+                write!(f, "({})", elems.join(", "))
+            }
+            Type::UserType { name, args } if args.is_empty() => write!(f, "{}", name),
+            Type::UserType { name, args } => {
+                let args: Vec<String> = args.iter().map(|t| t.to_string()).collect();
+                write!(f, "{}<{}>", name, args.join(", "))
+            }
+            Type::Var(v) => write!(f, "'{}", var_name(*v)),
+        }
+    }
+}
+
+// 'a, 'b, ..., 'z, 'a1, 'b1, ...
+fn var_name(v: usize) -> String {
+    let letter = (b'a' + (v % 26) as u8) as char;
+    match v / 26 {
+        0 => letter.to_string(),
+        n => format!("{}{}", letter, n),
+    }
+}
+
+// A type generalized over `vars`, e.g. the type of `function(x) { x }`
+// is `forall 'a. Fn('a) -> 'a`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scheme {
+    pub vars: Vec<usize>,
+    pub ty: Type,
+}
+
+impl Scheme {
+    pub fn mono(ty: Type) -> Scheme {
+        Scheme {
+            vars: Vec::new(),
+            ty,
+        }
+    }
+}
+
+// Quantified variables are renamed to 'a, 'b, ... in order of appearance.
+impl std::fmt::Display for Scheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let order: Vec<usize> = self
+            .ty
+            .free_vars()
+            .into_iter()
+            .filter(|v| self.vars.contains(v))
+            .collect();
+        let ty = self
+            .ty
+            .map_vars(&|v| order.iter().position(|o| *o == v).map(Type::Var));
+        write!(f, "{}", ty)
+    }
 }
 
 #[derive(Debug)]
 pub struct TypedExpr {
+    #[allow(dead_code)]
     pub expr: Box<Expr>,
-    pub expr_type: Option<Type>,
+    pub expr_type: Type,
 }
 
 impl TypedExpr {
-    pub fn new(expr: Box<Expr>, expr_type: Option<Type>) -> TypedExpr {
+    pub fn new(expr: Box<Expr>, expr_type: Type) -> TypedExpr {
         TypedExpr { expr, expr_type }
     }
 }
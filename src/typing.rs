@@ -1,179 +1,2033 @@
 use std::collections::HashMap;
 
-use crate::parser::*;
-use crate::lexer::*;
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::exhaustive::{Checker, Ctor};
 use crate::syntax::*;
 use crate::type_def::*;
 
+// the names of the type parameters of a declaration, with their variables
+type TypeParams = Vec<(String, usize)>;
+
 #[cfg(test)]
 mod test_typing {
     use super::*;
+    use crate::cst;
+
+    fn infer_str(src: &str) -> Result<Scheme, Diagnostic> {
+        let expr = *cst::parse_expr(src).unwrap();
+        Context::new().infer(expr)
+    }
+
+    fn check_str(src: &str) -> Result<Vec<String>, Diagnostic> {
+        let program = cst::parse_program(src).unwrap();
+        let mut cxt = Context::new();
+        let bindings = cxt.check_program(&program)?;
+        cxt.check_main(&program)?;
+        Ok(bindings
+            .into_iter()
+            .map(|(name, s)| format!("{}: {}", name, s))
+            .collect())
+    }
 
     #[test]
     fn test() {
-        let mut lexer = Lexer::from_file("src/test/test_parser.txt").unwrap();
-        let mut parser = Parser::new(lexer.lex().unwrap());
-        let expr = *parser.parse_program().unwrap();
-        let typed = expr.into_typed_expr(&mut Context::new()).unwrap();
-        println!("{:?}", typed);
+        let src = std::fs::read_to_string("src/test/test_parser.txt").unwrap();
+        let program = cst::parse_program(&src).unwrap();
+        let mut cxt = Context::new();
+        let bindings = cxt.check_program(&program).unwrap();
+        assert_eq!(bindings[1].1.to_string(), "Fn() -> Unit");
+        cxt.check_main(&program).unwrap();
+    }
+
+    #[test]
+    fn test_program() {
+        let src = "
+            type Pred = Fn(I32) -> Bool;
+            let limit = 10;
+            fn small(n) { n < limit }
+            fn check(p: Pred) -> Bool { p(1) }
+            fn main() { check(small) }
+        ";
+        assert_eq!(
+            check_str(src).unwrap(),
+            vec![
+                "limit: I32",
+                "small: Fn(I32) -> Bool",
+                "check: Fn(Fn(I32) -> Bool) -> Bool",
+                "main: Fn() -> Bool"
+            ]
+        );
+        let d = check_str("fn f(x: Foo) { x }").unwrap_err();
+        assert_eq!(d.code, "E0208");
+        assert_eq!(d.primary.as_ref().unwrap().to_string(), "1:9-1:11");
+        assert_eq!(check_str("let x = 1;").unwrap_err().code, "E0209");
+        let d = check_str("fn main(x) { x }").unwrap_err();
+        assert_eq!(d.code, "E0209");
+        assert!(d.primary.is_some());
+        assert!(check_str("let main = function() { 1 };").is_ok());
+        // items are defined in order
+        assert!(check_str("let x = y; let y = 1; fn main() { }").is_err());
+    }
+
+    #[test]
+    fn test_inference() {
+        let t = infer_str("function(x) { x }").unwrap();
+        assert_eq!(t.to_string(), "Fn('a) -> 'a");
+        assert_eq!(t.vars.len(), 1);
+        let t = infer_str("function(f, x) { f(x) }").unwrap();
+        assert_eq!(t.to_string(), "Fn(Fn('a) -> 'b, 'a) -> 'b");
+        let t = infer_str("function(x) { x }(1)").unwrap();
+        assert_eq!(t.ty, Type::I32);
+        let t = infer_str("function(x, y: I32) -> Unit { x }").unwrap();
+        assert_eq!(t.to_string(), "Fn(Unit, I32) -> Unit");
+    }
+
+    #[test]
+    fn test_inference_error() {
+        // annotations are constraints
+        assert!(infer_str("function(x: I32) -> Unit { x }").is_err());
+        assert!(infer_str("function(x: I32) { x }(unit)").is_err());
+        // occurs check
+        assert!(infer_str("function(x) { x(x) }").is_err());
+        assert!(infer_str("function(x, y) { x }(1)").is_err());
+        assert!(infer_str("1(2)").is_err());
+    }
+
+    #[test]
+    fn test_let() {
+        let t = infer_str("{ let x: I32 = 1; let y = x + 1; y }").unwrap();
+        assert_eq!(t.ty, Type::I32);
+        // shadowing
+        let t = infer_str("{ let x = 1; let x = x < 2; x }").unwrap();
+        assert_eq!(t.ty, Type::Bool);
+        // let-polymorphism
+        let t = infer_str("{ let id = function(x) { x }; id(1); id(true) }").unwrap();
+        assert_eq!(t.ty, Type::Bool);
+        // but lambda-bound variables stay monomorphic
+        assert!(infer_str("function(id) { id(1); id(true) }").is_err());
+        // the binding does not escape its block
+        assert!(infer_str("{ { let x = 1; x }; x }").is_err());
+        let d = infer_str("{ let x: Bool = 1; x }").unwrap_err();
+        assert_eq!(d.primary.as_ref().unwrap().to_string(), "1:17-1:17");
+        assert_eq!(d.secondary[0].0.to_string(), "1:10-1:13");
+    }
+
+    #[test]
+    fn test_named_func() {
+        let t =
+            infer_str("{ fn fact(n) { if n == 0 { 1 } else { n * fact(n - 1) } }; fact }").unwrap();
+        assert_eq!(t.to_string(), "Fn(I32) -> I32");
+        // mutual recursion between adjacent definitions
+        let src = "{
+            fn even(n) { if n == 0 { true } else { odd(n - 1) } };
+            fn odd(n: I32) -> Bool { if n == 0 { false } else { even(n - 1) } };
+            even
+        }";
+        assert_eq!(infer_str(src).unwrap().to_string(), "Fn(I32) -> Bool");
+        // generalized after the group is typed
+        let t = infer_str("{ fn id(x) { x }; id(1); id(true) }").unwrap();
+        assert_eq!(t.ty, Type::Bool);
+        // declared types are checked, also for recursive calls
+        assert!(infer_str("{ fn f(x: I32) -> Bool { x } }").is_err());
+        assert!(infer_str("{ fn f(x: I32) -> I32 { f(true) } }").is_err());
+        // functions only see the ones defined with them or before
+        assert!(infer_str("{ fn f() { g() }; 1; fn g() { 1 } }").is_err());
+        let d = infer_str("{ fn f() { 1 }; fn f() { 2 } }").unwrap_err();
+        assert_eq!(d.code, "E0207");
+        assert_eq!(d.primary.as_ref().unwrap().to_string(), "1:17-1:28");
+    }
+
+    #[test]
+    fn test_struct() {
+        let src = "
+            struct Point { x: I32, y: I32 }
+            type P = Point;
+            struct Line { from: P, to: Point }
+            fn len(l: Line) { l.to.x - l.from.x }
+            fn main() {
+                let l = Line { to: Point { x: 3, y: 0 }, from: P { y: 0, x: 1 } };
+                len(l) == 2 && l.from == l.to
+            }
+        ";
+        assert_eq!(check_str(src).unwrap()[0], "len: Fn(Line) -> I32");
+        let errors = [
+            ("struct P { x: Q } fn main() { }", "E0208"),
+            ("fn main() { Q { } }", "E0208"),
+            ("struct P { x: I32, x: I32 } fn main() { }", "E0212"),
+            ("struct P { } type P = I32; fn main() { }", "E0213"),
+            ("struct P { x: I32 } fn main() { P { } }", "E0210"),
+            (
+                "struct P { x: I32 } fn main() { P { x: 1, x: 2 } }",
+                "E0210",
+            ),
+            (
+                "struct P { x: I32 } fn main() { P { x: 1, y: 2 } }",
+                "E0210",
+            ),
+            ("struct P { x: I32 } fn main() { P { x: true } }", "E0200"),
+            ("struct P { x: I32 } fn main() { P { x: 1 }.y }", "E0211"),
+            ("fn main() { function(p) { p.x } }", "E0211"),
+            (
+                "struct F { f: Fn() -> Unit } fn main(f: F) { f == f }",
+                "E0206",
+            ),
+        ];
+        for (src, code) in errors.iter() {
+            assert_eq!(check_str(src).unwrap_err().code, *code, "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_match() {
+        let src = "
+            enum Shape { Circle(I32), Rect(I32, I32), Empty }
+            enum Option { Some(Shape), None }
+            fn area(s) {
+                match s {
+                    Circle(r) => 3 * r * r,
+                    Rect(w, h) => w * h,
+                    Empty => 0
+                }
+            }
+            fn first(o: Option) -> I32 {
+                match o { Some(Rect(1, h)) => h, Some(s) => area(s), None => -1 }
+            }
+            fn main() { first(Some(Circle(2))) }
+        ";
+        let program = cst::parse_program(src).unwrap();
+        let mut cxt = Context::new();
+        let bindings = cxt.check_program(&program).unwrap();
+        assert_eq!(bindings[0].1.to_string(), "Fn(Shape) -> I32");
+        assert!(cxt.take_warnings().is_empty());
+
+        let d = check_str("enum O { S(Bool), N } fn main() { match N { S(true) => 1, N => 0 } }")
+            .unwrap_err();
+        assert_eq!(d.code, "E0216");
+        assert_eq!(d.message, "Patterns `S(false)` not covered");
+        let d = check_str("fn main() { match 1 { 0 => true } }").unwrap_err();
+        assert_eq!(d.message, "Patterns `_` not covered");
+        check_str("fn f(c: Char, s: String) { (match c { 'a' => 1, _ => 0 }, match s { \"a\" => 1, _ => 0 }) } fn main() { }").unwrap();
+        let d = check_str("fn main() { match \"a\" { \"a\" => 1 } }").unwrap_err();
+        assert_eq!(d.message, "Patterns `_` not covered");
+        let errors = [
+            (
+                "fn main() { match true { true => 1, false => unit } }",
+                "E0200",
+            ),
+            ("fn main() { match 1 { true => 1, _ => 2 } }", "E0200"),
+            ("fn main() { match 'a' { \"a\" => 1, _ => 2 } }", "E0200"),
+            ("fn main() { match 1 { X => 1 } }", "E0214"),
+            (
+                "enum O { S(I32) } fn main() { match S(1) { S => 1 } }",
+                "E0215",
+            ),
+            ("enum O { S, S } fn main() { }", "E0212"),
+            ("enum O { S } enum P { S } fn main() { }", "E0213"),
+        ];
+        for (src, code) in errors.iter() {
+            assert_eq!(check_str(src).unwrap_err().code, *code, "{}", src);
+        }
+
+        let src = "fn main() { match unit { _ => 1, unit => 2 } }";
+        let program = cst::parse_program(src).unwrap();
+        let mut cxt = Context::new();
+        cxt.check_program(&program).unwrap();
+        let warnings = cxt.take_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].primary.as_ref().unwrap().to_string(),
+            "1:34-1:37"
+        );
+    }
+
+    #[test]
+    fn test_generics() {
+        let src = "
+            enum Option<T> { Some(T), None }
+            struct Pair<A, B> { a: A, b: B }
+            type Flags = Pair<Bool, Bool>;
+            fn id<T>(x: T) -> T { x }
+            fn swap<A, B>(p: Pair<A, B>) -> Pair<B, A> { Pair { a: p.b, b: p.a } }
+            fn get<T>(o: Option<T>, default: T) -> T {
+                match o { Some(x) => x, None => default }
+            }
+            fn main() {
+                let f = Flags { a: true, b: false };
+                let p = swap(Pair { a: 1, b: f });
+                get(Some(id(p.a.b)), id(false)) && get(None, p.b == 1)
+            }
+        ";
+        assert_eq!(
+            check_str(src).unwrap()[..3],
+            [
+                "id: Fn('a) -> 'a",
+                "swap: Fn(Pair<'a, 'b>) -> Pair<'b, 'a>",
+                "get: Fn(Option<'a>, 'a) -> 'a"
+            ]
+        );
+        // declared generic functions are generic within their group
+        let t =
+            infer_str("{ fn f() -> Bool { g(1) == 1 && g(true) }; fn g<T>(x: T) -> T { x }; f }");
+        assert_eq!(t.unwrap().to_string(), "Fn() -> Bool");
+        let t = infer_str("function<T>(x: T) -> T { x }(1)").unwrap();
+        assert_eq!(t.ty, Type::I32);
+
+        // a type parameter is not any particular type
+        let d = infer_str("function<T>(x: T) -> I32 { x }").unwrap_err();
+        assert_eq!(d.message, "Expected I32 but found T");
+        assert_eq!(d.notes.len(), 1);
+        assert!(infer_str("function<T, U>(x: T) -> U { x }").is_err());
+        // arguments decide the instantiation
+        let d = check_str("fn pair<T>(a: T, b: T) { a } fn main() { pair(1, true) }").unwrap_err();
+        assert_eq!(d.message, "Expected I32 but found Bool");
+        assert_eq!(d.primary.as_ref().unwrap().to_string(), "1:50-1:53");
+        assert_eq!(d.notes, vec!["type parameter `T` is I32 in this call"]);
+        let errors = [
+            ("enum O<T> { S(T) } fn f(o: O) { o } fn main() { }", "E0217"),
+            (
+                "struct P<T> { x: T } fn f(p: P<I32, I32>) { p } fn main() { }",
+                "E0217",
+            ),
+            ("type N = I32; fn f(x: N<Bool>) { x } fn main() { }", "E0217"),
+            ("fn f<T, T>(x: T) { x } fn main() { }", "E0212"),
+            ("struct P<T> { x: U } fn main() { }", "E0208"),
+            (
+                "enum O<T> { S(T), N } fn main() { match S(1) { S(true) => 1, N => 0 } }",
+                "E0200",
+            ),
+            (
+                "enum O<T> { S(T), N } fn main() { match S(true) { S(true) => 1, N => 0 } }",
+                "E0216",
+            ),
+            (
+                "struct B<T> { v: T } fn main() { B { v: B { v: main } } == B { v: B { v: main } } }",
+                "E0206",
+            ),
+        ];
+        for (src, code) in errors.iter() {
+            assert_eq!(check_str(src).unwrap_err().code, *code, "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_tuple() {
+        let src = "
+            fn divmod(a, b) { (a / b, a % b) }
+            fn swap<A, B>((a, b): (A, B)) -> (B, A) { (b, a) }
+            fn main() {
+                let (q, r) = divmod(7, 2);
+                let t = swap((q == 3, unit));
+                match t { (unit, true) => r, (_, false) => 0 }
+            }
+        ";
+        assert_eq!(
+            check_str(src).unwrap()[..2],
+            [
+                "divmod: Fn(I32, I32) -> (I32, I32)",
+                "swap: Fn(('a, 'b)) -> ('b, 'a)"
+            ]
+        );
+        // each binding of a let pattern is generalized
+        let t = infer_str("{ let (f, x) = (function(y) { y }, 1); f(x) == 1 && f(true) }").unwrap();
+        assert_eq!(t.ty, Type::Bool);
+        let t = infer_str("function(p) { p.0 }").unwrap_err();
+        assert_eq!(t.code, "E0211");
+        // the pattern is expected, the initializer is found
+        let d = check_str("let (a, b) = (1, 2, 3); fn main() { }").unwrap_err();
+        assert_eq!(d.code, "E0200");
+        assert_eq!(d.message, "Expected ('a, 'b) but found (I32, I32, I32)");
+        let d = check_str("fn main() { match 1 { true => 1, _ => 2 } }").unwrap_err();
+        assert_eq!(d.message, "Expected Bool but found I32");
+        let d = check_str("enum O { S(I32), N } let S(x) = N; fn main() { }").unwrap_err();
+        assert_eq!(d.code, "E0218");
+        assert_eq!(d.message, "Refutable pattern, `N` not covered");
+        assert_eq!(
+            check_str("fn f((1, x)) { x } fn main() { }")
+                .unwrap_err()
+                .code,
+            "E0218"
+        );
+        assert_eq!(
+            check_str("fn main() { (1, 2).2 }").unwrap_err().code,
+            "E0211"
+        );
+        let d = check_str("fn main() { (1, main) == (1, main) }").unwrap_err();
+        assert_eq!(d.code, "E0206");
+    }
+
+    #[test]
+    fn test_string() {
+        let t = infer_str(r#"function(s: String, c) { s == "a" && c == 'b' }"#).unwrap();
+        assert_eq!(t.to_string(), "Fn(String, Char) -> Bool");
+        assert_eq!(infer_str(r#""a" == 'a'"#).unwrap_err().code, "E0200");
+        assert!(infer_str(r#""a" + "b""#).is_err());
+    }
+
+    #[test]
+    fn test_number() {
+        let t = infer_str("function(x: U8, y) { x + y * 2u8 }").unwrap();
+        assert_eq!(t.to_string(), "Fn(U8, U8) -> U8");
+        let t = infer_str("function(x, y: I64) { x < y }").unwrap();
+        assert_eq!(t.to_string(), "Fn(I64, I64) -> Bool");
+        let t = infer_str("function(x) { x as U32 }").unwrap();
+        assert_eq!(t.to_string(), "Fn(I32) -> U32");
+        let t = infer_str("function(x: I64) { -x }").unwrap();
+        assert_eq!(t.to_string(), "Fn(I64) -> I64");
+        let d = infer_str("1u8 + 1").unwrap_err();
+        assert_eq!(d.message, "Expected U8 but found I32");
+        assert_eq!(
+            d.notes,
+            vec!["numbers are never converted implicitly, write `as U8` to convert"]
+        );
+        assert_eq!(
+            infer_str("{ let x: I64 = 1; x }").unwrap_err().code,
+            "E0200"
+        );
+        assert_eq!(
+            infer_str("function(x: U32) { -x }").unwrap_err().code,
+            "E0200"
+        );
+        assert_eq!(infer_str("true as I32").unwrap_err().code, "E0219");
+        assert_eq!(infer_str("1 as Bool").unwrap_err().code, "E0219");
+        let d = infer_str("function<T>(x: T) { x + x }").unwrap_err();
+        assert_eq!(d.message, "Expected a number but found T");
+        assert!(check_str("fn main() { match 1u8 { 0u8 => 1, _ => 2 } }").is_ok());
+        assert!(check_str("fn main() { match 1u8 { 0 => 1, _ => 2 } }").is_err());
+    }
+
+    #[test]
+    fn test_float() {
+        let t = infer_str("function(x, y) { x * 2.0 < y }").unwrap();
+        assert_eq!(t.to_string(), "Fn(F64, F64) -> Bool");
+        let t = infer_str("function(n: I32) { n as F64 / 2.0 }").unwrap();
+        assert_eq!(t.to_string(), "Fn(I32) -> F64");
+        let t = infer_str("function(x: F64) { -x as U8 }").unwrap();
+        assert_eq!(t.to_string(), "Fn(F64) -> U8");
+        let d = infer_str("1.0 + 1").unwrap_err();
+        assert_eq!(d.message, "Expected F64 but found I32");
+        assert_eq!(
+            d.notes,
+            vec!["numbers are never converted implicitly, write `as F64` to convert"]
+        );
+        assert!(infer_str("function(x: F64) -> I32 { x }").is_err());
+        assert!(infer_str("function(n: I64) -> F64 { n }").is_err());
+    }
+
+    #[test]
+    fn test_if() {
+        let t = infer_str("function(x) { if x < 0 { -x } else { x } }").unwrap();
+        assert_eq!(t.to_string(), "Fn(I32) -> I32");
+        let t = infer_str("function(b: Bool) { if b { unit } }").unwrap();
+        assert_eq!(t.to_string(), "Fn(Bool) -> Unit");
+        let t = infer_str("if true { 1 } else if false { 2 } else { 3 }").unwrap();
+        assert_eq!(t.ty, Type::I32);
+        assert!(infer_str("if 1 { 1 } else { 2 }").is_err());
+        assert!(infer_str("if true { 1 }").is_err());
+        let d = infer_str("if true { 1 } else { unit }").unwrap_err();
+        assert_eq!(d.primary.as_ref().unwrap().to_string(), "1:22-1:25");
+    }
+
+    #[test]
+    fn test_operators() {
+        let t = infer_str("function(x, y) { x + y * 2 }").unwrap();
+        assert_eq!(t.to_string(), "Fn(I32, I32) -> I32");
+        let t = infer_str("function(x, y) { x < y && !(x == y) }").unwrap();
+        assert_eq!(t.to_string(), "Fn(I32, I32) -> Bool");
+        let t = infer_str("function(x, y) { x == y }").unwrap();
+        assert_eq!(t.to_string(), "Fn('a, 'a) -> Bool");
+        assert!(infer_str("1 + unit").is_err());
+        assert!(infer_str("1 && 2").is_err());
+        assert!(infer_str("-(1 < 2)").is_err());
+        assert!(infer_str("function(x) { x } == function(x) { x }").is_err());
+    }
+
+    #[test]
+    fn test_index() {
+        let src = "fn id<T>(x: T) -> T { x }\nfn main() -> I32 { let y = id(1); y + 2 }";
+        let program = cst::parse_program(src).unwrap();
+        let mut cxt = Context::new();
+        cxt.start_index();
+        cxt.check_program(&program).unwrap();
+        let index = cxt.take_index();
+        let type_at = |span: &str| {
+            let found = index
+                .types
+                .iter()
+                .find(|(info, _)| info.to_string() == span);
+            found.map(|(_, t)| t.to_string())
+        };
+        assert_eq!(type_at("1:23-1:23").unwrap(), "T");
+        assert_eq!(type_at("2:28-2:29").unwrap(), "Fn(I32) -> I32");
+        assert_eq!(type_at("2:35-2:35").unwrap(), "I32");
+        assert_eq!(type_at("2:24-2:24"), None);
+        let vars: Vec<String> = index
+            .vars
+            .iter()
+            .map(|(at, name, def)| format!("{} {} {}", at, name, def.as_ref().unwrap()))
+            .collect();
+        assert_eq!(
+            vars,
+            [
+                "1:23-1:23 x 1:10-1:10",
+                "2:28-2:29 id 1:1-1:25",
+                "2:35-2:35 y 2:24-2:24"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_span() {
+        let d = infer_str("function(x: I32, y: Unit) { x }(1, 2)").unwrap_err();
+        assert_eq!(d.primary.as_ref().unwrap().to_string(), "1:36-1:36");
+        let d = infer_str("function(x) -> Unit {\n  x;\n  1\n}").unwrap_err();
+        assert_eq!(d.primary.as_ref().unwrap().to_string(), "3:3-3:3");
+        assert_eq!(d.secondary[0].0.to_string(), "1:16-1:19");
+        let d = infer_str("function(x) { y }").unwrap_err();
+        assert_eq!(d.primary.as_ref().unwrap().to_string(), "1:15-1:15");
     }
 }
 
+#[derive(Clone)]
 struct VarTypeTable {
-    table: HashMap<String, Type>,
+    table: HashMap<String, Scheme>,
+    // where the names are bound, kept while indexing
+    defs: HashMap<String, TokenInfo>,
 }
 
 impl VarTypeTable {
-    fn get(&self, name: &String) -> Option<&Type> {
+    fn get(&self, name: &String) -> Option<&Scheme> {
         self.table.get(name)
     }
-    pub fn from_args_decl(decls: Vec<ArgDecl>) -> VarTypeTable {
-        let mut ret = VarTypeTable {
-            table: HashMap::new(),
-        };
-        for d in decls {
-            ret.table.insert(d.vname, d.vtype);
-        }
-        ret
-    }
 }
 
-struct Context {
+// What typing found out about the spans of a program, for an editor: the
+// type of each expression, and the name of each variable with where it is
+// defined when that is known.
+#[derive(Debug, Clone, Default)]
+pub struct Index {
+    pub types: Vec<(TokenInfo, Type)>,
+    pub vars: Vec<(TokenInfo, String, Option<TokenInfo>)>,
+}
+
+#[derive(Clone)]
+pub struct Context {
     layered_table: Vec<VarTypeTable>,
-    searching_depth: usize,
-    visible_bottom: usize,
-    visible_top: usize,
+    // substitution for type variables, indexed by variable
+    subst: Vec<Option<Type>>,
+    // type names: aliases defined by `type Name = T;`, already expanded,
+    // and the type parameters in scope
+    types: HashMap<String, Type>,
+    // structs and enums, with the variables standing for their type
+    // parameters in the types of their fields
+    type_params: HashMap<String, Vec<usize>>,
+    // fields of the structs, in declaration order
+    structs: HashMap<String, Vec<(String, Type)>>,
+    // variants of the enums with the types of their fields
+    enums: HashMap<String, Vec<(String, Vec<Type>)>>,
+    // variables standing for a declared type parameter, like the `T` of
+    // `fn id<T>(x: T) -> T`, which only unify with themselves
+    rigid: HashMap<usize, String>,
+    // variables a type parameter was instantiated to at some use of a
+    // generic function, with the name of the parameter
+    instances: HashMap<usize, String>,
+    // reported along the way without stopping the typer
+    warnings: Vec<Diagnostic>,
+    // filled while indexing, with types not yet resolved
+    index: Option<Index>,
 }
 
 impl Context {
-    fn get(&mut self, name: String) -> Result<Type, String> {
-        while self.visible_bottom <= self.searching_depth
-            && self.searching_depth <= self.visible_top
-        {
-            match self.layered_table[self.searching_depth].get(&name) {
-                Some(t) => {
-                    return Ok(t.clone());
+    fn get(&mut self, name: String) -> Result<Type, Diagnostic> {
+        for table in self.layered_table.iter().rev() {
+            if let Some(s) = table.get(&name) {
+                let s = s.clone();
+                return Ok(self.instantiate(&s));
+            }
+        }
+        Err(Diagnostic::error(
+            "E0201",
+            &format!("Could not find variable `{}`", name),
+        ))
+    }
+
+    // The type written in `d`, with the type names in it resolved.
+    fn decl_type(&self, d: &TypeDecl) -> Result<Type, Diagnostic> {
+        self.expand_names(&d.ty)
+            .map_err(|e| e.with_primary(d.info.clone()))
+    }
+
+    fn expand_names(&self, t: &Type) -> Result<Type, Diagnostic> {
+        match t {
+            Type::UserType { name, args } => {
+                let mut expanded = Vec::new();
+                for a in args {
+                    expanded.push(Box::from(self.expand_names(a)?));
                 }
-                None => {}
+                let arity = match (self.types.get(name), self.type_params.get(name)) {
+                    (Some(t), _) if expanded.is_empty() => return Ok(t.clone()),
+                    (Some(_), _) => 0,
+                    (None, Some(params)) if params.len() == expanded.len() => {
+                        return Ok(Type::UserType {
+                            name: name.clone(),
+                            args: expanded,
+                        })
+                    }
+                    (None, Some(params)) => params.len(),
+                    (None, None) => {
+                        return Err(Diagnostic::error(
+                            "E0208",
+                            &format!("Could not find type `{}`", name),
+                        ))
+                    }
+                };
+                Err(Diagnostic::error(
+                    "E0217",
+                    &format!(
+                        "Type `{}` takes {} type arguments but {} were given",
+                        name,
+                        arity,
+                        expanded.len()
+                    ),
+                ))
             }
-            self.searching_depth -= 1
+            Type::Func { args, ret } => {
+                let mut expanded = Vec::new();
+                for a in args {
+                    expanded.push(Box::from(self.expand_names(a)?));
+                }
+                Ok(Type::Func {
+                    args: expanded,
+                    ret: Box::from(self.expand_names(ret)?),
+                })
+            }
+            Type::Tuple(elems) => {
+                let mut expanded = Vec::new();
+                for e in elems {
+                    expanded.push(Box::from(self.expand_names(e)?));
+                }
+                Ok(Type::Tuple(expanded))
+            }
+            t => Ok(t.clone()),
         }
-        Err(String::from("Could not found variables"))
     }
 
-    pub fn push_table_from_argsdecl(&mut self, args_decl: Vec<ArgDecl>) {
-        self.layered_table
-            .push(VarTypeTable::from_args_decl(args_decl));
-        self.visible_top += 1;
+    // Rigid variables for the type parameters `names` of the declaration
+    // at `info`.
+    fn new_type_params(
+        &mut self,
+        names: &[String],
+        info: &TokenInfo,
+    ) -> Result<TypeParams, Diagnostic> {
+        let mut params = Vec::new();
+        for (i, name) in names.iter().enumerate() {
+            if names[..i].contains(name) {
+                return Err(Diagnostic::error(
+                    "E0212",
+                    &format!("Type parameter `{}` is declared twice", name),
+                )
+                .with_primary(info.clone()));
+            }
+            self.subst.push(None);
+            let v = self.subst.len() - 1;
+            self.rigid.insert(v, name.clone());
+            params.push((name.clone(), v));
+        }
+        Ok(params)
     }
 
+    // Runs `f` with the type parameters `params` in scope, hiding any type
+    // of the same name.
+    fn with_type_params<T>(
+        &mut self,
+        params: &[(String, usize)],
+        f: impl FnOnce(&mut Context) -> T,
+    ) -> T {
+        let saved: Vec<(String, Option<Type>)> = params
+            .iter()
+            .map(|(name, v)| (name.clone(), self.types.insert(name.clone(), Type::Var(*v))))
+            .collect();
+        let ret = f(self);
+        for (name, t) in saved.into_iter().rev() {
+            match t {
+                Some(t) => self.types.insert(name, t),
+                None => self.types.remove(&name),
+            };
+        }
+        ret
+    }
+
+    // The declared types of the arguments, with fresh type variables for
+    // the undeclared ones.
+    fn args_decl_types(&mut self, args_decl: &[ArgDecl]) -> Result<Vec<Type>, Diagnostic> {
+        let mut types = Vec::new();
+        for d in args_decl {
+            types.push(match &d.vtype {
+                Some(d) => self.decl_type(d)?,
+                None => self.fresh(),
+            });
+        }
+        Ok(types)
+    }
+
+    // Types the body of a function whose arguments have types `args`, and
+    // checks it against the declared return type. Returns the function type.
+    fn infer_func(
+        &mut self,
+        args: Vec<Type>,
+        args_decl: &[ArgDecl],
+        ret_decl: &Option<TypeDecl>,
+        block: &Expr,
+    ) -> Result<Type, Diagnostic> {
+        self.push_table();
+        let typed_block = args_decl
+            .iter()
+            .zip(&args)
+            .try_for_each(|(d, t)| self.bind_irrefutable(&d.pattern, t))
+            .and_then(|_| block.clone().into_typed_expr(self));
+        self.pop_table();
+        let typed_block = typed_block?;
+        let ret = match ret_decl {
+            Some(d) => {
+                let ret = self.decl_type(d)?;
+                self.unify(&ret, &typed_block.expr_type).map_err(|e| {
+                    e.with_primary(block.tail_info().clone())
+                        .with_secondary(d.info.clone(), "expected because of this")
+                })?;
+                ret
+            }
+            None => typed_block.expr_type,
+        };
+        Ok(self.resolve(&Type::Func {
+            args: args.into_iter().map(Box::from).collect(),
+            ret: Box::from(ret),
+        }))
+    }
+
+    // Types adjacent named functions together. Every name is bound before
+    // any body is typed so that the functions can call themselves and each
+    // other, and they are generalized once the whole group is typed.
+    fn infer_func_group(&mut self, group: &[Box<Expr>]) -> Result<(), Diagnostic> {
+        let mut types: Vec<(String, TypeParams, Vec<Type>, Type)> = Vec::new();
+        for f in group {
+            if let Expr::NamedFunc {
+                name,
+                type_params,
+                args_def,
+                ret_decl,
+                info,
+                ..
+            } = &**f
+            {
+                if types.iter().any(|(n, _, _, _)| n == name) {
+                    return Err(Diagnostic::error(
+                        "E0207",
+                        &format!("Function `{}` is defined twice in a row", name),
+                    )
+                    .with_primary(info.clone())
+                    .with_note(
+                        "adjacent functions are defined together, so one would hide the other",
+                    ));
+                }
+                let params = self.new_type_params(type_params, info)?;
+                let (args, ret) = self.with_type_params(&params, |cxt| {
+                    let args = cxt.args_decl_types(args_def)?;
+                    let ret = match ret_decl {
+                        Some(d) => cxt.decl_type(d)?,
+                        None => cxt.fresh(),
+                    };
+                    Ok::<_, Diagnostic>((args, ret))
+                })?;
+                let ty = Type::Func {
+                    args: args.iter().cloned().map(Box::from).collect(),
+                    ret: Box::from(ret),
+                };
+                // generic over its declared type parameters even for the
+                // functions defined with it, though the undeclared parts of
+                // its type are shared until the group is generalized
+                let vars = params.iter().map(|(_, v)| *v).collect();
+                self.define(
+                    name.clone(),
+                    Scheme {
+                        vars,
+                        ty: ty.clone(),
+                    },
+                );
+                self.define_at(name, info);
+                types.push((name.clone(), params, args, ty));
+            }
+        }
+        for (f, (_, params, args, ty)) in group.iter().zip(&types) {
+            if let Expr::NamedFunc {
+                args_def,
+                ret_decl,
+                block,
+                info,
+                ..
+            } = &**f
+            {
+                let found = self.with_type_params(params, |cxt| {
+                    cxt.infer_func(args.clone(), args_def, ret_decl, block)
+                })?;
+                self.unify(ty, &found)
+                    .map_err(|d| d.with_primary(info.clone()))?;
+            }
+        }
+        // the monomorphic bindings would keep their own variables bound
+        if let Some(table) = self.layered_table.last_mut() {
+            for (name, _, _, _) in &types {
+                table.table.remove(name);
+            }
+        }
+        let schemes: Vec<Scheme> = types
+            .iter()
+            .map(|(_, _, _, ty)| self.generalize(ty))
+            .collect();
+        for ((name, _, _, _), scheme) in types.into_iter().zip(schemes) {
+            self.define(name, scheme);
+        }
+        Ok(())
+    }
+
+    // Goes back to `snapshot`, a clone of this context taken earlier, but
+    // keeps the warnings reported since.
+    pub fn restore(&mut self, snapshot: Context) {
+        let warnings = std::mem::take(&mut self.warnings);
+        *self = snapshot;
+        self.warnings.extend(warnings);
+    }
+
+    pub fn pop_table(&mut self) {
+        self.layered_table.pop();
+    }
+
+    pub fn push_table(&mut self) {
+        self.layered_table.push(VarTypeTable {
+            table: HashMap::new(),
+            defs: HashMap::new(),
+        });
+    }
+
+    // Binds `name` in the innermost table, shadowing any outer binding.
+    pub fn define(&mut self, name: String, scheme: Scheme) {
+        if let Some(table) = self.layered_table.last_mut() {
+            table.table.insert(name, scheme);
+        }
+    }
+
+    // Notes that the binding of `name` in the innermost table is made at
+    // `info`, when indexing.
+    fn define_at(&mut self, name: &str, info: &TokenInfo) {
+        if self.index.is_none() {
+            return;
+        }
+        if let Some(table) = self.layered_table.last_mut() {
+            table.defs.insert(String::from(name), info.clone());
+        }
+    }
+
+    // Starts indexing what is typed from now on.
+    pub fn start_index(&mut self) {
+        self.index = Some(Index::default());
+    }
+
+    // The index since `start_index`, with its types as far as they are
+    // known and type parameters by their names.
+    pub fn take_index(&mut self) -> Index {
+        let index = self.index.take().unwrap_or_default();
+        Index {
+            types: index
+                .types
+                .into_iter()
+                .map(|(info, t)| (info, self.show(&t)))
+                .collect(),
+            vars: index.vars,
+        }
+    }
+
+    // The outermost table holds top level bindings, like those the REPL
+    // keeps between inputs.
     pub fn new() -> Context {
-        Context {
+        let mut cxt = Context {
             layered_table: Vec::new(),
-            searching_depth: 0,
-            visible_bottom: 0,
-            visible_top: 0,
+            subst: Vec::new(),
+            types: HashMap::new(),
+            type_params: HashMap::new(),
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            rigid: HashMap::new(),
+            instances: HashMap::new(),
+            warnings: Vec::new(),
+            index: None,
+        };
+        cxt.push_table();
+        cxt
+    }
+
+    // Types `expr` and generalizes the result over the type variables not
+    // bound in the context.
+    pub fn infer(&mut self, expr: Expr) -> Result<Scheme, Diagnostic> {
+        let typed = expr.into_typed_expr(self)?;
+        Ok(self.generalize(&typed.expr_type))
+    }
+
+    // Types the items of a program in order, leaving their bindings in the
+    // outermost table. Returns the type of each function and constant.
+    pub fn check_program(
+        &mut self,
+        program: &Program,
+    ) -> Result<Vec<(String, Scheme)>, Diagnostic> {
+        let mut bindings = Vec::new();
+        let items = &program.items;
+        let mut i = 0;
+        while i < items.len() {
+            let defined: Vec<Box<Expr>> = match &items[i] {
+                Item::Func(_) => {
+                    let group: Vec<Box<Expr>> = items[i..]
+                        .iter()
+                        .map_while(|item| match item {
+                            Item::Func(f) => Some(f.clone()),
+                            _ => None,
+                        })
+                        .collect();
+                    self.infer_func_group(&group)?;
+                    group
+                }
+                Item::Const(c) => {
+                    (**c).clone().into_typed_expr(self)?;
+                    vec![c.clone()]
+                }
+                Item::Struct {
+                    name,
+                    type_params,
+                    fields,
+                    info,
+                } => {
+                    let params = self.new_type_params(type_params, info)?;
+                    self.declare_type(name, &params, info)?;
+                    let decl =
+                        self.with_type_params(&params, |cxt| cxt.field_decl_types(fields))?;
+                    self.structs.insert(name.clone(), decl);
+                    Vec::new()
+                }
+                Item::Enum {
+                    name,
+                    type_params,
+                    variants,
+                    info,
+                } => {
+                    let params = self.new_type_params(type_params, info)?;
+                    self.define_enum(name, &params, variants, info)?;
+                    Vec::new()
+                }
+                Item::TypeAlias { name, ty, info } => {
+                    let t = self.decl_type(ty)?;
+                    self.define_type(name, t, info)?;
+                    Vec::new()
+                }
+                // the driver loads imported files before this one
+                Item::Import { .. } => Vec::new(),
+                // reported by the parser
+                Item::Error { .. } => Vec::new(),
+            };
+            i += defined.len().max(1);
+            for e in defined {
+                let names = match &*e {
+                    Expr::NamedFunc { name, .. } => vec![name],
+                    Expr::Let { pattern, .. } => pattern.bindings(),
+                    _ => Vec::new(),
+                };
+                for name in names {
+                    let scheme = self.scheme_of(name);
+                    bindings.extend(scheme.map(|s| (name.clone(), s)));
+                }
+            }
+        }
+        Ok(bindings)
+    }
+
+    // Type names cannot be shadowed: values of the old type would be
+    // mistaken for values of the new one.
+    fn check_new_type(&self, name: &str, info: &TokenInfo) -> Result<(), Diagnostic> {
+        if self.types.contains_key(name) || self.type_params.contains_key(name) {
+            return Err(
+                Diagnostic::error("E0213", &format!("Type `{}` is already defined", name))
+                    .with_primary(info.clone()),
+            );
+        }
+        Ok(())
+    }
+
+    fn define_type(&mut self, name: &str, t: Type, info: &TokenInfo) -> Result<(), Diagnostic> {
+        self.check_new_type(name, info)?;
+        self.types.insert(String::from(name), t);
+        Ok(())
+    }
+
+    // Declares the struct or enum `name`, before its fields so that they
+    // can refer to it.
+    fn declare_type(
+        &mut self,
+        name: &str,
+        params: &[(String, usize)],
+        info: &TokenInfo,
+    ) -> Result<(), Diagnostic> {
+        self.check_new_type(name, info)?;
+        let vars = params.iter().map(|(_, v)| *v).collect();
+        self.type_params.insert(String::from(name), vars);
+        Ok(())
+    }
+
+    fn field_decl_types(&self, fields: &[FieldDecl]) -> Result<Vec<(String, Type)>, Diagnostic> {
+        let mut decl: Vec<(String, Type)> = Vec::new();
+        for f in fields {
+            if decl.iter().any(|(n, _)| *n == f.name) {
+                return Err(Diagnostic::error(
+                    "E0212",
+                    &format!("Field `{}` is declared twice", f.name),
+                )
+                .with_primary(f.info.clone()));
+            }
+            decl.push((f.name.clone(), self.decl_type(&f.ty)?));
         }
+        Ok(decl)
+    }
+
+    // Defines the enum and binds its variants as constructors: values for
+    // those without fields, functions for the others. The constructors of a
+    // generic enum are generic over its type parameters.
+    fn define_enum(
+        &mut self,
+        name: &str,
+        params: &[(String, usize)],
+        variants: &[VariantDecl],
+        info: &TokenInfo,
+    ) -> Result<(), Diagnostic> {
+        let vars: Vec<usize> = params.iter().map(|(_, v)| *v).collect();
+        let ty = Type::UserType {
+            name: String::from(name),
+            args: vars.iter().map(|v| Box::from(Type::Var(*v))).collect(),
+        };
+        self.declare_type(name, params, info)?;
+        let mut decl: Vec<(String, Vec<Type>)> = Vec::new();
+        for v in variants {
+            if decl.iter().any(|(n, _)| *n == v.name) {
+                return Err(Diagnostic::error(
+                    "E0212",
+                    &format!("Variant `{}` is declared twice", v.name),
+                )
+                .with_primary(v.info.clone()));
+            }
+            if let Some((other, _)) = self.variant(&v.name) {
+                return Err(Diagnostic::error(
+                    "E0213",
+                    &format!("Constructor `{}` is already defined by `{}`", v.name, other),
+                )
+                .with_primary(v.info.clone()));
+            }
+            let args = self.with_type_params(params, |cxt| {
+                let mut args = Vec::new();
+                for a in &v.args {
+                    args.push(cxt.decl_type(a)?);
+                }
+                Ok::<_, Diagnostic>(args)
+            })?;
+            decl.push((v.name.clone(), args));
+        }
+        self.enums.insert(String::from(name), decl.clone());
+        for (v, info) in variants.iter().map(|v| (&v.name, &v.info)) {
+            self.define_at(v, info);
+        }
+        for (v, args) in decl {
+            let ctor_type = if args.is_empty() {
+                ty.clone()
+            } else {
+                Type::Func {
+                    args: args.into_iter().map(Box::from).collect(),
+                    ret: Box::from(ty.clone()),
+                }
+            };
+            self.define(
+                v,
+                Scheme {
+                    vars: vars.clone(),
+                    ty: ctor_type,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    // The enum defining the variant `name`, and the types of its fields.
+    fn variant(&self, name: &str) -> Option<(String, Vec<Type>)> {
+        self.enums.iter().find_map(|(e, variants)| {
+            variants
+                .iter()
+                .find(|(v, _)| v == name)
+                .map(|(_, fields)| (e.clone(), fields.clone()))
+        })
+    }
+
+    // The ways of building values of type `t` which patterns tell apart,
+    // or None when they cannot be listed.
+    fn signature(&self, t: &Type) -> Option<Vec<(Ctor, Vec<Type>)>> {
+        match self.resolve(t) {
+            Type::Bool => Some(vec![
+                (Ctor::Bool(true), Vec::new()),
+                (Ctor::Bool(false), Vec::new()),
+            ]),
+            Type::Unit => Some(vec![(Ctor::Unit, Vec::new())]),
+            Type::Tuple(elems) => {
+                Some(vec![(Ctor::Tuple, elems.into_iter().map(|e| *e).collect())])
+            }
+            t => self.variants_of(&t).map(|variants| {
+                variants
+                    .into_iter()
+                    .map(|(v, fields)| (Ctor::Variant(v), fields))
+                    .collect()
+            }),
+        }
+    }
+
+    // The variants of the enum type `t` with the types of their fields,
+    // given the type arguments of `t`.
+    fn variants_of(&self, t: &Type) -> Option<Vec<(String, Vec<Type>)>> {
+        match self.resolve(t) {
+            Type::UserType { name, args } => {
+                let params = self.type_params.get(&name)?;
+                let variants = self.enums.get(&name)?;
+                Some(
+                    variants
+                        .iter()
+                        .map(|(v, fields)| {
+                            let fields = fields.iter().map(|f| substitute(f, params, &args));
+                            (v.clone(), fields.collect())
+                        })
+                        .collect(),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    // The fields of the struct type `t`, given its type arguments.
+    fn fields_of(&self, t: &Type) -> Option<Vec<(String, Type)>> {
+        match self.resolve(t) {
+            Type::UserType { name, args } => {
+                let params = self.type_params.get(&name)?;
+                let fields = self.structs.get(&name)?;
+                Some(
+                    fields
+                        .iter()
+                        .map(|(n, f)| (n.clone(), substitute(f, params, &args)))
+                        .collect(),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    // Checks that `p` matches values of type `t`, binding its variables in
+    // the innermost table.
+    fn check_pattern(&mut self, p: &Pattern, t: &Type) -> Result<(), Diagnostic> {
+        let literal = match p {
+            Pattern::Wildcard { .. } => return Ok(()),
+            Pattern::Bind { name, info } => {
+                self.define(name.clone(), Scheme::mono(t.clone()));
+                self.define_at(name, info);
+                return Ok(());
+            }
+            Pattern::Int { ty, .. } => ty.clone(),
+            Pattern::Bool { .. } => Type::Bool,
+            Pattern::Char { .. } => Type::Char,
+            Pattern::Str { .. } => Type::String,
+            Pattern::Unit { .. } => Type::Unit,
+            Pattern::Ctor { name, args, info } => {
+                let (enum_name, fields) = match self.variant(name) {
+                    Some(v) => v,
+                    None => {
+                        return Err(Diagnostic::error(
+                            "E0214",
+                            &format!("Could not find constructor `{}`", name),
+                        )
+                        .with_primary(info.clone()))
+                    }
+                };
+                let params = self.type_params[&enum_name].clone();
+                let type_args: Vec<Box<Type>> =
+                    params.iter().map(|_| Box::from(self.fresh())).collect();
+                let fields: Vec<Type> = fields
+                    .iter()
+                    .map(|f| substitute(f, &params, &type_args))
+                    .collect();
+                let ty = Type::UserType {
+                    name: enum_name,
+                    args: type_args,
+                };
+                self.unify(&ty, t)
+                    .map_err(|d| d.with_primary(info.clone()))?;
+                if args.len() != fields.len() {
+                    return Err(Diagnostic::error(
+                        "E0215",
+                        &format!(
+                            "Constructor `{}` has {} fields but the pattern has {}",
+                            name,
+                            fields.len(),
+                            args.len()
+                        ),
+                    )
+                    .with_primary(info.clone()));
+                }
+                for (a, f) in args.iter().zip(&fields) {
+                    self.check_pattern(a, f)?;
+                }
+                return Ok(());
+            }
+            Pattern::Tuple { elems, info } => {
+                let types: Vec<Type> = elems.iter().map(|_| self.fresh()).collect();
+                let ty = Type::Tuple(types.iter().cloned().map(Box::from).collect());
+                self.unify(&ty, t)
+                    .map_err(|d| d.with_primary(info.clone()))?;
+                for (e, t) in elems.iter().zip(&types) {
+                    self.check_pattern(e, t)?;
+                }
+                return Ok(());
+            }
+        };
+        self.unify(&literal, t)
+            .map_err(|d| d.with_primary(p.info().clone()))
+    }
+
+    // Checks `p` like a pattern of a match with a single arm, which must
+    // then match every value of type `t`.
+    fn bind_irrefutable(&mut self, p: &Pattern, t: &Type) -> Result<(), Diagnostic> {
+        self.check_pattern(p, t)?;
+        let t = self.resolve(t);
+        let signature = |ty: &Type| self.signature(ty);
+        let missing = Checker::new(&signature).missing(&[p], &t);
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let missing: Vec<String> = missing.iter().map(|p| format!("`{}`", p)).collect();
+        Err(Diagnostic::error(
+            "E0218",
+            &format!("Refutable pattern, {} not covered", missing.join(", ")),
+        )
+        .with_primary(p.info().clone())
+        .with_note(&format!(
+            "the pattern must match every value of type {}, use match otherwise",
+            t
+        )))
+    }
+
+    // Warns about unreachable arms and fails when some value of type `t`
+    // matches none of them.
+    fn check_arms(
+        &mut self,
+        arms: &[MatchArm],
+        t: &Type,
+        info: &TokenInfo,
+    ) -> Result<(), Diagnostic> {
+        let t = self.resolve(t);
+        let patterns: Vec<&Pattern> = arms.iter().map(|a| &a.pattern).collect();
+        let signature = |ty: &Type| self.signature(ty);
+        let checker = Checker::new(&signature);
+        let unreachable: Vec<TokenInfo> = (0..patterns.len())
+            .filter(|i| !checker.is_useful(&patterns[..*i], patterns[*i], &t))
+            .map(|i| patterns[i].info().clone())
+            .collect();
+        let missing = checker.missing(&patterns, &t);
+        for span in unreachable {
+            self.warnings.push(
+                Diagnostic::new(Severity::Warning, "W0200", "Unreachable match arm")
+                    .with_primary(span)
+                    .with_note("the arms above match every value this one does"),
+            );
+        }
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let missing: Vec<String> = missing.iter().map(|p| format!("`{}`", p)).collect();
+        Err(Diagnostic::error(
+            "E0216",
+            &format!("Patterns {} not covered", missing.join(", ")),
+        )
+        .with_primary(info.clone())
+        .with_note(&format!("values of type {} must match some arm", t)))
+    }
+
+    // Takes the warnings reported so far.
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    // The type and the fields of the struct named `name`, which may be an
+    // alias. A generic struct gets fresh type arguments.
+    fn struct_fields(&mut self, name: &str) -> Option<(Type, Vec<(String, Type)>)> {
+        let ty = match self.types.get(name) {
+            Some(t) => t.clone(),
+            None => {
+                let arity = self.type_params.get(name)?.len();
+                Type::UserType {
+                    name: String::from(name),
+                    args: (0..arity).map(|_| Box::from(self.fresh())).collect(),
+                }
+            }
+        };
+        let fields = self.fields_of(&ty)?;
+        Some((ty, fields))
+    }
+
+    // Whether values of type `t` may hold functions, which cannot be
+    // compared. `seen` guards against recursive types.
+    fn holds_func(&self, t: &Type, seen: &mut Vec<Type>) -> bool {
+        let t = self.resolve(t);
+        match &t {
+            Type::Func { .. } => true,
+            Type::Tuple(elems) => elems.iter().any(|e| self.holds_func(e, seen)),
+            Type::UserType { .. } => {
+                if seen.contains(&t) {
+                    return false;
+                }
+                seen.push(t.clone());
+                let fields: Vec<Type> = match (self.fields_of(&t), self.variants_of(&t)) {
+                    (Some(fields), _) => fields.into_iter().map(|(_, f)| f).collect(),
+                    (_, Some(variants)) => variants.into_iter().flat_map(|(_, f)| f).collect(),
+                    _ => Vec::new(),
+                };
+                fields.iter().any(|f| self.holds_func(f, seen))
+            }
+            _ => false,
+        }
+    }
+
+    // A program is run by calling `main` without arguments, as in
+    // `fn main() -> Unit { .. }`.
+    pub fn check_main(&mut self, program: &Program) -> Result<(), Diagnostic> {
+        // only the root file's own items count, not the imported ones
+        let span = program.items.iter().rev().find_map(|item| match item {
+            Item::Func(f) | Item::Const(f) => match &**f {
+                Expr::NamedFunc { name, info, .. } if name == "main" => Some(info.clone()),
+                Expr::Let { pattern, info, .. }
+                    if pattern.bindings().iter().any(|n| *n == "main") =>
+                {
+                    Some(info.clone())
+                }
+                _ => None,
+            },
+            _ => None,
+        });
+        let scheme = match span.as_ref().and_then(|_| self.scheme_of("main")) {
+            Some(s) => s,
+            None => {
+                return Err(Diagnostic::error("E0209", "Could not find function `main`")
+                    .with_note("a program starts by calling `fn main() -> Unit`"))
+            }
+        };
+        let found = self.instantiate(&scheme);
+        let ret = self.fresh();
+        let expected = Type::Func {
+            args: Vec::new(),
+            ret: Box::from(ret),
+        };
+        self.unify(&expected, &found).map_err(|_| {
+            let d = Diagnostic::error("E0209", "`main` must be a function without arguments")
+                .with_note(&format!("`main` has type {}", scheme));
+            match span {
+                Some(span) => d.with_primary(span),
+                None => d,
+            }
+        })
+    }
+
+    // The names bound in the outermost table with their types, sorted.
+    pub fn top_level(&self) -> Vec<(String, Scheme)> {
+        let mut names: Vec<(String, Scheme)> = match self.layered_table.first() {
+            Some(table) => table
+                .table
+                .keys()
+                .filter_map(|name| self.scheme_of(name).map(|s| (name.clone(), s)))
+                .collect(),
+            None => Vec::new(),
+        };
+        names.sort_by(|a, b| a.0.cmp(&b.0));
+        names
+    }
+
+    // Notes the variable `name` at `info` with its definition, when
+    // indexing.
+    fn index_use(&mut self, name: &str, info: &TokenInfo) {
+        let def = self
+            .layered_table
+            .iter()
+            .rev()
+            .find(|table| table.table.contains_key(name))
+            .and_then(|table| table.defs.get(name));
+        if let Some(index) = self.index.as_mut() {
+            index
+                .vars
+                .push((info.clone(), String::from(name), def.cloned()));
+        }
+    }
+
+    fn scheme_of(&self, name: &str) -> Option<Scheme> {
+        self.layered_table
+            .iter()
+            .rev()
+            .find_map(|table| table.table.get(name))
+            .map(|s| Scheme {
+                vars: s.vars.clone(),
+                ty: self.resolve(&s.ty),
+            })
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.subst.push(None);
+        Type::Var(self.subst.len() - 1)
+    }
+
+    // Applies the current substitution to `t` all the way down.
+    pub fn resolve(&self, t: &Type) -> Type {
+        t.map_vars(&|v| self.subst[v].as_ref().map(|t| self.resolve(t)))
+    }
+
+    fn instantiate(&mut self, s: &Scheme) -> Type {
+        let fresh: Vec<(usize, Type)> = s.vars.iter().map(|v| (*v, self.fresh())).collect();
+        for (from, to) in &fresh {
+            if let (Some(name), Type::Var(to)) = (self.rigid.get(from), to) {
+                self.instances.insert(*to, name.clone());
+            }
+        }
+        self.resolve(&s.ty).map_vars(&|v| {
+            fresh
+                .iter()
+                .find(|(from, _)| *from == v)
+                .map(|(_, to)| to.clone())
+        })
+    }
+
+    fn generalize(&self, t: &Type) -> Scheme {
+        let t = self.resolve(t);
+        let mut bound = Vec::new();
+        for table in &self.layered_table {
+            for s in table.table.values() {
+                for v in self.resolve(&s.ty).free_vars() {
+                    if !s.vars.contains(&v) {
+                        bound.push(v);
+                    }
+                }
+            }
+        }
+        let vars = t
+            .free_vars()
+            .into_iter()
+            .filter(|v| !bound.contains(v))
+            .collect();
+        Scheme { vars, ty: t }
+    }
+
+    fn unify(&mut self, expected: &Type, found: &Type) -> Result<(), Diagnostic> {
+        let expected = self.resolve(expected);
+        let found = self.resolve(found);
+        match (&expected, &found) {
+            (Type::Var(a), Type::Var(b)) if a == b => Ok(()),
+            (Type::Var(v), t) | (t, Type::Var(v)) if !self.rigid.contains_key(v) => {
+                if t.free_vars().contains(v) {
+                    return Err(Diagnostic::error(
+                        "E0202",
+                        &format!("Infinite type: {} occurs in {}", Type::Var(*v), t),
+                    ));
+                }
+                self.subst[*v] = Some(t.clone());
+                Ok(())
+            }
+            (Type::I32, Type::I32)
+            | (Type::I64, Type::I64)
+            | (Type::U8, Type::U8)
+            | (Type::U32, Type::U32)
+            | (Type::F64, Type::F64)
+            | (Type::Unit, Type::Unit)
+            | (Type::Bool, Type::Bool)
+            | (Type::String, Type::String)
+            | (Type::Char, Type::Char) => Ok(()),
+            (Type::Tuple(elems_e), Type::Tuple(elems_f)) if elems_e.len() == elems_f.len() => {
+                for (e, f) in elems_e.iter().zip(elems_f.iter()) {
+                    self.unify(e, f)?;
+                }
+                Ok(())
+            }
+            (
+                Type::UserType {
+                    name: name_e,
+                    args: args_e,
+                },
+                Type::UserType {
+                    name: name_f,
+                    args: args_f,
+                },
+            ) if name_e == name_f && args_e.len() == args_f.len() => {
+                for (e, f) in args_e.iter().zip(args_f.iter()) {
+                    self.unify(e, f)?;
+                }
+                Ok(())
+            }
+            (
+                Type::Func {
+                    args: args_e,
+                    ret: ret_e,
+                },
+                Type::Func {
+                    args: args_f,
+                    ret: ret_f,
+                },
+            ) if args_e.len() == args_f.len() => {
+                for (e, f) in args_e.iter().zip(args_f.iter()) {
+                    self.unify(e, f)?;
+                }
+                self.unify(ret_e, ret_f)
+            }
+            _ => {
+                let d = Diagnostic::error(
+                    "E0200",
+                    &format!(
+                        "Expected {} but found {}",
+                        self.show(&expected),
+                        self.show(&found)
+                    ),
+                );
+                let rigid = [&expected, &found].iter().find_map(|t| match t {
+                    Type::Var(v) => self.rigid.get(v),
+                    _ => None,
+                });
+                Err(match rigid {
+                    Some(name) => d.with_note(&format!(
+                        "type parameter `{}` stands for any type, so it only matches itself",
+                        name
+                    )),
+                    None if expected.is_number() && found.is_number() => d.with_note(&format!(
+                        "numbers are never converted implicitly, write `as {}` to convert",
+                        expected
+                    )),
+                    None => d,
+                })
+            }
+        }
+    }
+
+    // The number type `t`, which is I32 when nothing tells it yet, as for
+    // the `x` of `function(x) { -x }`.
+    fn number(&mut self, t: &Type, info: &TokenInfo) -> Result<Type, Diagnostic> {
+        match self.resolve(t) {
+            Type::Var(v) if !self.rigid.contains_key(&v) => {
+                self.unify(&Type::I32, &Type::Var(v))?;
+                Ok(Type::I32)
+            }
+            t if t.is_number() => Ok(t),
+            t => Err(Diagnostic::error(
+                "E0200",
+                &format!("Expected a number but found {}", self.show(&t)),
+            )
+            .with_primary(info.clone())),
+        }
+    }
+
+    // The type of the operands of an arithmetic or comparison operator,
+    // numbers of the same type. A known operand decides the type of the
+    // other, as in `x + 1u8`.
+    fn number_operands(
+        &mut self,
+        lhs: &Expr,
+        lhs_type: &Type,
+        rhs: &Expr,
+        rhs_type: &Type,
+    ) -> Result<Type, Diagnostic> {
+        for (e, t) in [(lhs, lhs_type), (rhs, rhs_type)].iter() {
+            if !matches!(self.resolve(t), Type::Var(_)) {
+                self.number(t, e.info())?;
+            }
+        }
+        self.unify(lhs_type, rhs_type).map_err(|d| {
+            d.with_primary(rhs.info().clone())
+                .with_secondary(lhs.info().clone(), "expected because of this")
+        })?;
+        self.number(lhs_type, lhs.info())
+    }
+
+    // `t` as shown to the user, with type parameters by their names
+    fn show(&self, t: &Type) -> Type {
+        self.resolve(t).map_vars(&|v| {
+            self.rigid.get(&v).map(|name| Type::UserType {
+                name: name.clone(),
+                args: Vec::new(),
+            })
+        })
+    }
+
+    // Notes what the type parameters instantiated in `t` stand for at this
+    // use, since an earlier argument may have decided it.
+    fn with_instance_notes(&self, d: Diagnostic, t: &Type) -> Diagnostic {
+        t.free_vars()
+            .into_iter()
+            .filter_map(|v| {
+                self.instances
+                    .get(&v)
+                    .map(|name| (name, self.show(&Type::Var(v))))
+            })
+            .filter(|(_, t)| !matches!(t, Type::Var(_)))
+            .fold(d, |d, (name, t)| {
+                d.with_note(&format!("type parameter `{}` is {} in this call", name, t))
+            })
     }
 }
 
+// `t` with the type parameters `params` of a generic type replaced by the
+// type arguments `args`
+fn substitute(t: &Type, params: &[usize], args: &[Box<Type>]) -> Type {
+    t.map_vars(&|v| {
+        params
+            .iter()
+            .position(|p| *p == v)
+            .map(|i| (*args[i]).clone())
+    })
+}
+
 impl Expr {
-    fn into_typed_expr(self, cxt: &mut Context) -> Result<TypedExpr, String> {
+    // span of the expression whose value this one evaluates to
+    fn tail_info(&self) -> &TokenInfo {
+        match self {
+            Expr::Block { exprs, .. } => match exprs.last() {
+                Some(e) => e.tail_info(),
+                None => self.info(),
+            },
+            e => e.info(),
+        }
+    }
+
+    pub fn into_typed_expr(self, cxt: &mut Context) -> Result<TypedExpr, Diagnostic> {
+        // named functions and lets bind names and are typed Unit, which
+        // is not worth indexing
+        let indexed = !matches!(self, Expr::NamedFunc { .. } | Expr::Let { .. });
+        let info = self.info().clone();
+        let typed = self.type_expr(cxt)?;
+        if let Some(index) = cxt.index.as_mut().filter(|_| indexed) {
+            index.types.push((info, typed.expr_type.clone()));
+        }
+        Ok(typed)
+    }
+
+    fn type_expr(self, cxt: &mut Context) -> Result<TypedExpr, Diagnostic> {
         match &self {
-            Expr::Unit => Ok(TypedExpr::new(Box::from(self), Some(Type::Unit))),
-            Expr::I32 { val: _ } => Ok(TypedExpr::new(Box::from(self), Some(Type::I32))),
-            Expr::Var { name } => {
-                let expr_type = cxt.get(name.clone())?;
-                Ok(TypedExpr::new(Box::from(self), Some(expr_type)))
+            Expr::Unit { .. } => Ok(TypedExpr::new(Box::from(self), Type::Unit)),
+            Expr::Int { ty, .. } => {
+                let ty = ty.clone();
+                Ok(TypedExpr::new(Box::from(self), ty))
             }
-            Expr::Block { exprs } => {
-                let mut last = TypedExpr::new(Box::from(Expr::Unit), Some(Type::Unit));
-                for expr in exprs.clone() {
-                    match expr.into_typed_expr(cxt) {
-                        Ok(s) => last = s,
-                        Err(e) => return Err(e),
+            Expr::Float { .. } => Ok(TypedExpr::new(Box::from(self), Type::F64)),
+            // reported by the parser, it may have any type so that the rest
+            // of a partial tree can be typed
+            Expr::Error { .. } => {
+                let t = cxt.fresh();
+                Ok(TypedExpr::new(Box::from(self), t))
+            }
+            Expr::Bool { .. } => Ok(TypedExpr::new(Box::from(self), Type::Bool)),
+            Expr::Str { .. } => Ok(TypedExpr::new(Box::from(self), Type::String)),
+            Expr::Char { .. } => Ok(TypedExpr::new(Box::from(self), Type::Char)),
+            Expr::Var { name, info } => {
+                let expr_type = cxt
+                    .get(name.clone())
+                    .map_err(|d| d.with_primary(info.clone()))?;
+                cxt.index_use(name, info);
+                Ok(TypedExpr::new(Box::from(self), expr_type))
+            }
+            Expr::Block { exprs, info } => {
+                let mut last =
+                    TypedExpr::new(Box::from(Expr::Unit { info: info.clone() }), Type::Unit);
+                // let bindings in the block go to its own table
+                cxt.push_table();
+                let mut i = 0;
+                while i < exprs.len() {
+                    let typed = match *exprs[i] {
+                        Expr::NamedFunc { .. } => {
+                            let len = exprs[i..]
+                                .iter()
+                                .take_while(|e| matches!(***e, Expr::NamedFunc { .. }))
+                                .count();
+                            let group = &exprs[i..i + len];
+                            i += len;
+                            cxt.infer_func_group(group)
+                                .map(|_| TypedExpr::new(group[len - 1].clone(), Type::Unit))
+                        }
+                        _ => {
+                            i += 1;
+                            (*exprs[i - 1].clone()).into_typed_expr(cxt)
+                        }
+                    };
+                    match typed {
+                        Ok(t) => last = t,
+                        Err(d) => {
+                            cxt.pop_table();
+                            return Err(d);
+                        }
                     }
                 }
+                cxt.pop_table();
                 Ok(last)
             }
+            Expr::Let {
+                pattern,
+                vtype,
+                init,
+                ..
+            } => {
+                let init_type = (*init.clone()).into_typed_expr(cxt)?.expr_type;
+                if let Some(d) = vtype {
+                    let declared = cxt.decl_type(d)?;
+                    cxt.unify(&declared, &init_type).map_err(|e| {
+                        e.with_primary(init.tail_info().clone())
+                            .with_secondary(d.info.clone(), "expected because of this")
+                    })?;
+                }
+                // let多相: the bindings go to a table of their own first, so
+                // that they are generalized one by one
+                cxt.push_table();
+                let bound = cxt.bind_irrefutable(pattern, &init_type);
+                let table = cxt.layered_table.pop();
+                bound?;
+                let (table, defs) = table.map(|t| (t.table, t.defs)).unwrap_or_default();
+                for (name, s) in table {
+                    let scheme = cxt.generalize(&s.ty);
+                    cxt.define(name, scheme);
+                }
+                for (name, info) in defs {
+                    cxt.define_at(&name, &info);
+                }
+                Ok(TypedExpr::new(Box::from(self), Type::Unit))
+            }
             Expr::AnonFunc {
+                type_params,
                 args_decl,
                 ret_decl,
                 block,
+                info,
             } => {
-                cxt.push_table_from_argsdecl(args_decl.clone());
-                let typed_block = block.clone().into_typed_expr(cxt)?;
-                match typed_block.expr_type {
-                    Some(ref t) if *t == *ret_decl => (),
-                    n => {println!("{:?}",n);return Err(String::from("Expected but found"))}
+                let params = cxt.new_type_params(type_params, info)?;
+                let expr_type = cxt.with_type_params(&params, |cxt| {
+                    let args = cxt.args_decl_types(args_decl)?;
+                    cxt.infer_func(args, args_decl, ret_decl, block)
+                })?;
+                // outside of its body a type parameter may be any type
+                let vars = params.iter().map(|(_, v)| *v).collect();
+                let expr_type = cxt.instantiate(&Scheme {
+                    vars,
+                    ty: expr_type,
+                });
+                Ok(TypedExpr::new(Box::from(self), expr_type))
+            }
+            Expr::StructLit { name, fields, info } => {
+                let (ty, decl) = match cxt.struct_fields(name) {
+                    Some(s) => s,
+                    None => {
+                        return Err(Diagnostic::error(
+                            "E0208",
+                            &format!("Could not find struct `{}`", name),
+                        )
+                        .with_primary(info.clone()))
+                    }
+                };
+                for (i, f) in fields.iter().enumerate() {
+                    let expected = match decl.iter().find(|(n, _)| *n == f.name) {
+                        Some(_) if fields[..i].iter().any(|g| g.name == f.name) => {
+                            return Err(Diagnostic::error(
+                                "E0210",
+                                &format!("Field `{}` is given twice", f.name),
+                            )
+                            .with_primary(f.info.clone()))
+                        }
+                        Some((_, t)) => t.clone(),
+                        None => {
+                            return Err(Diagnostic::error(
+                                "E0210",
+                                &format!("Struct `{}` has no field `{}`", name, f.name),
+                            )
+                            .with_primary(f.info.clone()))
+                        }
+                    };
+                    let found = (*f.value.clone()).into_typed_expr(cxt)?.expr_type;
+                    cxt.unify(&expected, &found)
+                        .map_err(|d| d.with_primary(f.value.info().clone()))?;
                 }
-
-                let args = args_decl
+                let missing: Vec<String> = decl
                     .iter()
-                    .map(|x| Box::from(x.clone().vtype))
+                    .filter(|(n, _)| !fields.iter().any(|f| f.name == *n))
+                    .map(|(n, _)| format!("`{}`", n))
                     .collect();
-                let ret = Box::from(ret_decl.clone());
-                let expr = Box::from(self);
-                let expr_type = Some(Type::Func { args, ret });
-                Ok(TypedExpr { expr, expr_type })
+                if !missing.is_empty() {
+                    return Err(Diagnostic::error(
+                        "E0210",
+                        &format!("Missing fields {} of struct `{}`", missing.join(", "), name),
+                    )
+                    .with_primary(info.clone()));
+                }
+                Ok(TypedExpr::new(Box::from(self), cxt.resolve(&ty)))
             }
-            Expr::FuncApp { callee, args } => {
-                // calleeの型を調べる
-                let callee_type = (*callee.clone()).into_typed_expr(cxt)?;
-                let fn_args_ty;
-                let ret_ty;
-                match callee_type.expr_type {
-                    Some(Type::Func { args, ret }) => {
-                        fn_args_ty = args;
-                        ret_ty = ret;
+            Expr::Field { expr, field, info } => {
+                let expr_type = (*expr.clone()).into_typed_expr(cxt)?.expr_type;
+                let expr_type = cxt.resolve(&expr_type);
+                let found = cxt
+                    .fields_of(&expr_type)
+                    .and_then(|fields| fields.into_iter().find(|(n, _)| n == field));
+                match found {
+                    Some((_, t)) => Ok(TypedExpr::new(Box::from(self), t)),
+                    None => {
+                        let d = Diagnostic::error(
+                            "E0211",
+                            &format!("Type {} has no field `{}`", cxt.show(&expr_type), field),
+                        )
+                        .with_primary(info.clone());
+                        Err(match expr_type {
+                            Type::Var(_) => d.with_note(
+                                "the type must be known here, it can be given with an annotation",
+                            ),
+                            _ => d,
+                        })
+                    }
+                }
+            }
+            Expr::Tuple { elems, .. } => {
+                let mut types = Vec::new();
+                for e in elems {
+                    types.push(Box::from((*e.clone()).into_typed_expr(cxt)?.expr_type));
+                }
+                Ok(TypedExpr::new(Box::from(self), Type::Tuple(types)))
+            }
+            Expr::TupleField { expr, index, info } => {
+                let expr_type = (*expr.clone()).into_typed_expr(cxt)?.expr_type;
+                let expr_type = cxt.resolve(&expr_type);
+                match &expr_type {
+                    Type::Tuple(elems) if *index < elems.len() => {
+                        let t = (*elems[*index]).clone();
+                        Ok(TypedExpr::new(Box::from(self), t))
+                    }
+                    _ => {
+                        let d = Diagnostic::error(
+                            "E0211",
+                            &format!("Type {} has no field `{}`", cxt.show(&expr_type), index),
+                        )
+                        .with_primary(info.clone());
+                        Err(match expr_type {
+                            Type::Var(_) => d.with_note(
+                                "the type must be known here, it can be given with an annotation",
+                            ),
+                            _ => d,
+                        })
                     }
-                    _ => return Err(String::from("Error: Callee must have function type")),
                 }
+            }
+            Expr::Match {
+                scrutinee, arms, ..
+            } => {
+                let scrutinee_type = (*scrutinee.clone()).into_typed_expr(cxt)?.expr_type;
+                let mut first: Option<(Type, &MatchArm)> = None;
+                for arm in arms {
+                    cxt.push_table();
+                    let body = cxt
+                        .check_pattern(&arm.pattern, &scrutinee_type)
+                        .and_then(|_| (*arm.body.clone()).into_typed_expr(cxt));
+                    cxt.pop_table();
+                    let body_type = body?.expr_type;
+                    match &first {
+                        None => first = Some((body_type, arm)),
+                        Some((t, first_arm)) => cxt.unify(t, &body_type).map_err(|d| {
+                            d.with_primary(arm.body.tail_info().clone())
+                                .with_secondary(
+                                    first_arm.body.tail_info().clone(),
+                                    "expected because of this",
+                                )
+                                .with_note("all arms of match must have the same type")
+                        })?,
+                    }
+                }
+                cxt.check_arms(arms, &scrutinee_type, scrutinee.info())?;
+                // a match without arms never gives a value
+                let expr_type = match first {
+                    Some((t, _)) => cxt.resolve(&t),
+                    None => cxt.fresh(),
+                };
+                Ok(TypedExpr::new(Box::from(self), expr_type))
+            }
+            Expr::FuncApp { callee, args, info } => {
+                // calleeの型を調べる
+                let callee_type = (*callee.clone()).into_typed_expr(cxt)?.expr_type;
 
                 // argsの型を調べる
                 let mut app_args_type = Vec::new();
                 for e in args.clone() {
-                    let e_to_typedexpr = (*e).into_typed_expr(cxt)?;
-                    let e_type = match e_to_typedexpr.expr_type {
-                        Some(t) => t,
-                        None => return Err(String::from("型推論実装してねえ")),
-                    };
-                    app_args_type.push(e_type);
-                }
-
-                // calleeのargsの型とargsの型が一致するか調べる
-                let mut fn_args_type = fn_args_ty.iter();
-                let mut app_args_type = app_args_type.iter();
-                loop {
-                    match fn_args_type.next() {
-                        Some(tf) => match app_args_type.next() {
-                            Some(ta) => {
-                                if !(*ta == **tf) {
-                                    return Err(String::from("Expected but"));
-                                }
-                            }
-                            None => {
-                                return Err(String::from(
-                                    "The number of the args is expected to be {}",
-                                ))
-                            }
-                        },
-                        None => match app_args_type.next() {
-                            None => break,
-                            Some(_) => {
-                                return Err(String::from(
-                                    "The number of the args is expected to be {}",
-                                ))
-                            }
-                        },
-                    }
-                }
-                Ok(TypedExpr {
-                    expr: Box::from(self),
-                    expr_type: Some(*ret_ty),
-                })
+                    app_args_type.push((*e).into_typed_expr(cxt)?.expr_type);
+                }
+
+                match cxt.resolve(&callee_type) {
+                    Type::Func { args: fn_args, ret } => {
+                        if fn_args.len() != args.len() {
+                            return Err(Diagnostic::error(
+                                "E0203",
+                                &format!(
+                                    "The number of the args is expected to be {} but found {}",
+                                    fn_args.len(),
+                                    args.len()
+                                ),
+                            )
+                            .with_primary(info.clone())
+                            .with_secondary(
+                                callee.info().clone(),
+                                &format!("this has type {}", cxt.resolve(&callee_type)),
+                            ));
+                        }
+                        // 引数ごとに型が一致するか調べる
+                        for ((tf, ta), e) in fn_args.iter().zip(app_args_type.iter()).zip(args) {
+                            cxt.unify(tf, ta).map_err(|d| {
+                                cxt.with_instance_notes(d.with_primary(e.info().clone()), tf)
+                            })?;
+                        }
+                        Ok(TypedExpr::new(Box::from(self), cxt.resolve(&ret)))
+                    }
+                    Type::Var(_) => {
+                        // calleeの型が引数の型から作った関数型と単一化できるか調べる
+                        let ret_ty = cxt.fresh();
+                        let app_type = Type::Func {
+                            args: app_args_type.into_iter().map(Box::from).collect(),
+                            ret: Box::from(ret_ty.clone()),
+                        };
+                        cxt.unify(&callee_type, &app_type)
+                            .map_err(|d| d.with_primary(info.clone()))?;
+                        Ok(TypedExpr::new(Box::from(self), cxt.resolve(&ret_ty)))
+                    }
+                    t => Err(Diagnostic::error(
+                        "E0204",
+                        &format!("Callee must have function type but found {}", t),
+                    )
+                    .with_primary(callee.info().clone())),
+                }
+            }
+            Expr::If {
+                cond,
+                then_block,
+                else_block,
+                ..
+            } => {
+                let cond_type = (*cond.clone()).into_typed_expr(cxt)?.expr_type;
+                cxt.unify(&Type::Bool, &cond_type)
+                    .map_err(|d| d.with_primary(cond.info().clone()))?;
+                let then_type = (*then_block.clone()).into_typed_expr(cxt)?.expr_type;
+                match else_block {
+                    Some(else_block) => {
+                        let else_type = (*else_block.clone()).into_typed_expr(cxt)?.expr_type;
+                        cxt.unify(&then_type, &else_type).map_err(|d| {
+                            d.with_primary(else_block.tail_info().clone())
+                                .with_secondary(
+                                    then_block.tail_info().clone(),
+                                    "expected because of this",
+                                )
+                                .with_note("both branches of if must have the same type")
+                        })?;
+                    }
+                    None => {
+                        cxt.unify(&Type::Unit, &then_type).map_err(|d| {
+                            d.with_primary(then_block.tail_info().clone())
+                                .with_note("if without else must have type Unit")
+                        })?;
+                    }
+                }
+                let expr_type = cxt.resolve(&then_type);
+                Ok(TypedExpr::new(Box::from(self), expr_type))
+            }
+            Expr::BinOp { op, lhs, rhs, info } => {
+                let lhs_type = (*lhs.clone()).into_typed_expr(cxt)?.expr_type;
+                let rhs_type = (*rhs.clone()).into_typed_expr(cxt)?.expr_type;
+                let result = match op {
+                    BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Rem => {
+                        cxt.number_operands(lhs, &lhs_type, rhs, &rhs_type)?
+                    }
+                    BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => {
+                        cxt.number_operands(lhs, &lhs_type, rhs, &rhs_type)?;
+                        Type::Bool
+                    }
+                    _ => Type::Bool,
+                };
+                match op {
+                    BinOp::And | BinOp::Or => {
+                        cxt.unify(&Type::Bool, &lhs_type)
+                            .map_err(|d| d.with_primary(lhs.info().clone()))?;
+                        cxt.unify(&Type::Bool, &rhs_type)
+                            .map_err(|d| d.with_primary(rhs.info().clone()))?;
+                    }
+                    // 同じ型同士なら比較できる
+                    BinOp::Eq | BinOp::Ne => {
+                        cxt.unify(&lhs_type, &rhs_type).map_err(|d| {
+                            d.with_primary(rhs.info().clone())
+                                .with_secondary(lhs.info().clone(), "compared with this")
+                        })?;
+                        if cxt.holds_func(&lhs_type, &mut Vec::new()) {
+                            return Err(Diagnostic::error(
+                                "E0206",
+                                &format!(
+                                    "Values of type {} hold functions and cannot be compared",
+                                    cxt.resolve(&lhs_type)
+                                ),
+                            )
+                            .with_primary(info.clone()));
+                        }
+                    }
+                    _ => (),
+                }
+                Ok(TypedExpr::new(Box::from(self), result))
+            }
+            Expr::UnaryOp { op, operand, .. } => {
+                let operand_type = (*operand.clone()).into_typed_expr(cxt)?.expr_type;
+                let t = match op {
+                    UnaryOp::Neg => {
+                        let t = cxt.number(&operand_type, operand.info())?;
+                        if let Some((0, _)) = t.int_range() {
+                            return Err(Diagnostic::error(
+                                "E0200",
+                                &format!("Expected a signed number but found {}", t),
+                            )
+                            .with_primary(operand.info().clone()));
+                        }
+                        t
+                    }
+                    UnaryOp::Not => {
+                        cxt.unify(&Type::Bool, &operand_type)
+                            .map_err(|d| d.with_primary(operand.info().clone()))?;
+                        Type::Bool
+                    }
+                };
+                Ok(TypedExpr::new(Box::from(self), t))
+            }
+            // checked at runtime, where a value out of the range of `ty`
+            // is an overflow. The type is written out rather than through
+            // an alias since the values carry it.
+            Expr::Cast { expr, ty, info } => {
+                let expr_type = (*expr.clone()).into_typed_expr(cxt)?.expr_type;
+                let to = ty.ty.clone();
+                if cxt.number(&expr_type, expr.info()).is_err() || !to.is_number() {
+                    return Err(Diagnostic::error(
+                        "E0219",
+                        &format!(
+                            "Cannot convert {} to {}",
+                            cxt.show(&expr_type),
+                            cxt.show(&to)
+                        ),
+                    )
+                    .with_primary(info.clone())
+                    .with_note("`as` only converts between number types"));
+                }
+                Ok(TypedExpr::new(Box::from(self), to))
+            }
+            // bound in the enclosing table, so that a function defined on
+            // its own (like an input of the REPL) can still call itself
+            Expr::NamedFunc { .. } => {
+                cxt.infer_func_group(&[Box::from(self.clone())])?;
+                Ok(TypedExpr::new(Box::from(self), Type::Unit))
             }
-            _ => Err(String::from("hello")),
         }
     }
 }